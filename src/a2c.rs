@@ -0,0 +1,105 @@
+use crate::features::{current_feature_set, FeatureSet};
+use crate::nn::{Network, PolicyLoss};
+use crate::ppo::{gae, sample_action, Rollout};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Steps each environment contributes to a rollout
+pub const ROLLOUT_STEPS: usize = 5;
+
+/// Synchronous advantage actor-critic: every environment steps the same softmax policy, and
+/// each `ROLLOUT_STEPS` x environments rollout gets one policy-gradient and one value step on
+/// its n-step return advantages. PPO without the ratio clipping or the epochs.
+pub struct A2cAgent {
+    pub policy: Network,
+    pub value: Network,
+    pub features: &'static FeatureSet,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    pub entropy_coef: f32,
+    // One per environment
+    rollouts: Vec<Rollout>,
+    rng: ChaCha8Rng,
+}
+
+impl A2cAgent {
+    /// Fresh networks for `num_envs` environments; reproducible when `seed` is set
+    pub fn new(num_actions: usize, num_envs: usize, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        A2cAgent {
+            policy: Network::new(features.size, num_actions, false, &mut rng),
+            value: Network::new(features.size, 1, false, &mut rng),
+            features,
+            gamma: 0.99,
+            learning_rate: 7e-4,
+            entropy_coef: 0.01,
+            rollouts: (0..num_envs).map(|_| Rollout::default()).collect(),
+            rng,
+        }
+    }
+
+    /// Samples an action for environment `env` and starts its rollout step; `remember` must
+    /// follow with the step's outcome
+    pub fn act(&mut self, env: usize, features: &[f32]) -> usize {
+        let (action, log_prob) = sample_action(&self.policy.forward(features), &mut self.rng);
+        let rollout = &mut self.rollouts[env];
+        rollout.states.push(features.to_vec());
+        rollout.actions.push(action);
+        rollout.log_probs.push(log_prob);
+        rollout.values.push(self.value.forward(features)[0]);
+        action
+    }
+
+    /// Completes environment `env`'s step; a truncated episode bootstraps from
+    /// V(`next_features`) as in `PpoAgent::remember`
+    pub fn remember(
+        &mut self,
+        env: usize,
+        reward: f32,
+        done: bool,
+        truncated: bool,
+        next_features: &[f32],
+    ) {
+        let reward = if truncated {
+            reward + self.gamma * self.value.forward(next_features)[0]
+        } else {
+            reward
+        };
+        self.rollouts[env].rewards.push(reward);
+        self.rollouts[env].dones.push(done);
+    }
+
+    /// Steps per environment since the last update
+    pub fn rollout_len(&self) -> usize {
+        self.rollouts[0].rewards.len()
+    }
+
+    /// One update on all the rollouts, which are then cleared; `next_features[env]` is the
+    /// state environment `env` bootstraps from
+    pub fn update(&mut self, next_features: &[Vec<f32>]) {
+        let (mut inputs, mut actions, mut log_probs) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut advantages, mut returns) = (Vec::new(), Vec::new());
+        for (rollout, next) in self.rollouts.iter_mut().zip(next_features) {
+            let rollout = std::mem::take(rollout);
+            let last_value = self.value.forward(next)[0];
+            // lambda = 1: advantages against plain n-step returns
+            let (a, r) =
+                gae(&rollout.rewards, &rollout.values, &rollout.dones, last_value, self.gamma, 1.0);
+            advantages.extend(a);
+            returns.extend(r);
+            inputs.extend(rollout.states);
+            actions.extend(rollout.actions);
+            log_probs.extend(rollout.log_probs);
+        }
+        // The policy is the one that collected the rollout, so every ratio is 1 and the
+        // surrogate's gradient is the plain policy gradient; nothing is ever clipped
+        let loss = PolicyLoss {
+            clip: f32::INFINITY,
+            entropy_coef: self.entropy_coef,
+        };
+        let lr = self.learning_rate;
+        self.policy.train_batch_ppo(&inputs, &actions, &advantages, &log_probs, &loss, lr);
+        self.value.train_batch(&inputs, &returns, &vec![0; inputs.len()], lr);
+    }
+}
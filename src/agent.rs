@@ -1,21 +1,278 @@
+use crate::distributional::ReturnDistribution;
+use crate::engine::EnvMode;
+use crate::features::{current_feature_set, FeatureSet};
 use crate::nn::Network;
-use rand::Rng;
+use crate::normalize::ObsNormalizer;
+use crate::replay::ReplayBuffer;
+use crate::tools::BestModel;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Experience {
     pub state: Vec<f32>,
     pub action: usize,
     pub reward: f32,
     pub next_state: Vec<f32>,
     pub done: bool,
+    /// Env steps the transition covers (more than 1 with action repeat); `reward` is already
+    /// discounted within the span
+    #[serde(default = "one_step")]
+    pub span: u32,
+    /// Action taken from `next_state`, filled in once the agent remembers its next transition
+    /// (None for terminal transitions and the end of a truncated episode); SARSA targets
+    /// bootstrap from it
+    #[serde(default)]
+    pub next_action: Option<usize>,
+}
+
+fn one_step() -> u32 {
+    1
+}
+
+/// Initial noise scale of NoisyNet layers (sigma = sigma0 / sqrt(fan-in))
+const NOISY_SIGMA0: f32 = 0.5;
+
+impl Experience {
+    /// Discount on the next state's value: gamma^span
+    pub fn bootstrap_discount(&self, gamma: f32) -> f32 {
+        gamma.powi(self.span as i32)
+    }
+}
+
+/// The n-step transition starting at `window[0]`: its state and action, the discounted sum of
+/// the window's rewards, and the last transition's next state, `done` and summed span
+pub fn n_step_experience(window: &VecDeque<Experience>, gamma: f32) -> Experience {
+    let (first, last) = (&window[0], &window[window.len() - 1]);
+    let mut reward = 0.0;
+    let mut discount = 1.0;
+    for exp in window {
+        reward += discount * exp.reward;
+        discount *= exp.bootstrap_discount(gamma);
+    }
+    Experience {
+        state: first.state.clone(),
+        action: first.action,
+        reward,
+        next_state: last.next_state.clone(),
+        done: last.done,
+        span: window.iter().map(|e| e.span).sum(),
+        next_action: last.next_action,
+    }
+}
+
+/// Index of the largest Q-value
+fn argmax(q: &[f32]) -> usize {
+    q.iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .unwrap()
+        .0
+}
+
+/// Mean absolute difference between two networks' Q-values over `states`
+pub fn mean_abs_q_diff(a: &Network, b: &Network, states: &[Vec<f32>]) -> f32 {
+    if states.is_empty() {
+        return 0.0;
+    }
+    let qa = a.predict_batch(states);
+    let qb = b.predict_batch(states);
+    let total: f32 = qa
+        .iter()
+        .zip(qb.iter())
+        .flat_map(|(x, y)| x.iter().zip(y.iter()).map(|(u, v)| (u - v).abs()))
+        .sum();
+    total / (states.len() * a.output_size()) as f32
+}
+
+/// Discounted return from each step to the end of an episode: G_t = r_t + d_t * G_{t+1}, where
+/// `discounts[t]` is the transition's bootstrap discount (gamma^span)
+pub fn mc_returns(rewards: &[f32], discounts: &[f32]) -> Vec<f32> {
+    let mut returns = vec![0.0; rewards.len()];
+    let mut g = 0.0;
+    for t in (0..rewards.len()).rev() {
+        g = rewards[t] + discounts[t] * g;
+        returns[t] = g;
+    }
+    returns
+}
+
+/// Q-value overestimation: the Q(s, a) predicted at each visited step against the Monte Carlo
+/// return that actually followed, averaged over completed episodes
+#[derive(Default)]
+pub struct OverestimationStats {
+    // (predicted Q, reward, bootstrap discount) per step of the current episode
+    episode: Vec<(f32, f32, f32)>,
+    bias_sum: f64,
+    count: usize,
+}
+
+impl OverestimationStats {
+    pub fn record(&mut self, predicted_q: f32, reward: f32, discount: f32) {
+        self.episode.push((predicted_q, reward, discount));
+    }
+
+    /// Scores the episode's steps against their returns. Truncated episodes are dropped: their
+    /// returns are missing everything after the cut.
+    pub fn end_episode(&mut self, complete: bool) {
+        if complete {
+            let rewards: Vec<f32> = self.episode.iter().map(|s| s.1).collect();
+            let discounts: Vec<f32> = self.episode.iter().map(|s| s.2).collect();
+            for (step, g) in self.episode.iter().zip(mc_returns(&rewards, &discounts)) {
+                self.bias_sum += (step.0 - g) as f64;
+                self.count += 1;
+            }
+        }
+        self.episode.clear();
+    }
+
+    /// Mean signed bias (predicted Q - return) since the last call, if any step was scored
+    pub fn take_mean(&mut self) -> Option<(f32, usize)> {
+        let count = std::mem::take(&mut self.count);
+        let sum = std::mem::take(&mut self.bias_sum);
+        (count > 0).then(|| ((sum / count as f64) as f32, count))
+    }
+}
+
+/// How a TD target picks and evaluates the next state's bootstrap action
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TargetRule {
+    /// The target network picks and evaluates: max over its Q-values
+    Vanilla,
+    /// Double DQN: the main network picks, the target network evaluates
+    Double,
+    /// Clipped Double Q: the main network picks, evaluated by the smaller of two target
+    /// networks' estimates. Needs a twin online network (with its own target), trained on the
+    /// same targets from an independent initialization.
+    ClippedDouble,
+    /// SARSA: the target network evaluates the action actually taken next (the transition's
+    /// `next_action`), so the learned values are those of the behaviour policy. Falls back to
+    /// `Double` where the next action is unknown (after a truncation).
+    Sarsa,
+    /// Expected SARSA: the target network's value under the current epsilon-greedy policy,
+    /// i.e. the `Double` pick's value with probability 1 - epsilon and the mean over all
+    /// actions with probability epsilon
+    ExpectedSarsa,
+}
+
+/// Everything needed to resume training. The replay buffer is only included on request (it's
+/// large); without it the buffer refills on resume and the run diverges from an uninterrupted
+/// one.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub episode: u64,
+    pub network: Network,
+    pub target_network: Network,
+    pub epsilon: f32,
+    pub learning_rate: f32,
+    pub step_count: u64,
+    pub train_steps: u64,
+    #[serde(default)]
+    pub obs_norm: Option<ObsNormalizer>,
+    /// Environment the run was training in (filled in by `save_checkpoint`)
+    #[serde(default)]
+    pub env: Option<EnvMode>,
+    /// Agent RNG (exploration, replay sampling) at checkpoint time
+    #[serde(default)]
+    pub rng: Option<ChaCha8Rng>,
+    /// Engine RNG (food, obstacles, sticky actions), filled in by `save_checkpoint`
+    #[serde(default)]
+    pub engine_rng: Option<ChaCha8Rng>,
+    #[serde(default)]
+    pub replay: Option<ReplayBuffer>,
+    /// Feature layout the network was trained on (None for checkpoints saved before feature
+    /// versioning)
+    #[serde(default)]
+    pub feature_version: Option<u32>,
+    /// Output distribution of a C51 or QR-DQN agent (None for a plain Q-network)
+    #[serde(default)]
+    pub distribution: Option<ReturnDistribution>,
+    /// Twin online and target networks of a clipped Double Q agent
+    #[serde(default)]
+    pub twin: Option<(Network, Network)>,
+    /// TD target rule the run was training with (None for checkpoints saved before it was
+    /// recorded)
+    #[serde(default)]
+    pub target_rule: Option<TargetRule>,
+    /// Rolling scores and best model of the run, filled in by `save_checkpoint` so a resumed
+    /// run saves, evaluates and reverts at the same episodes as an uninterrupted one
+    #[serde(default)]
+    pub best: Option<BestModel>,
+}
+
+impl Clone for Checkpoint {
+    fn clone(&self) -> Self {
+        Checkpoint {
+            episode: self.episode,
+            network: self.network.clone_with_optimizer(),
+            target_network: self.target_network.clone_weights(),
+            epsilon: self.epsilon,
+            learning_rate: self.learning_rate,
+            step_count: self.step_count,
+            train_steps: self.train_steps,
+            obs_norm: self.obs_norm.clone(),
+            env: self.env.clone(),
+            rng: self.rng.clone(),
+            engine_rng: self.engine_rng.clone(),
+            replay: self.replay.clone(),
+            feature_version: self.feature_version,
+            distribution: self.distribution,
+            twin: self.twin.as_ref().map(|(online, target)| {
+                (online.clone_with_optimizer(), target.clone_weights())
+            }),
+            target_rule: self.target_rule,
+            best: self.best.clone(),
+        }
+    }
 }
 
 pub struct DQNAgent {
     pub network: Network,
     pub target_network: Network,
-    replay_buffer: VecDeque<Experience>,
-    buffer_size: usize,
+    replay_buffer: ReplayBuffer,
     batch_size: usize,
+    // Minimum share of terminal transitions in each sampled batch
+    pub terminal_fraction: f32,
+    // Sample only from the N most recently inserted transitions
+    pub max_sample_age: Option<usize>,
+    // Recompute TD-error sampling priorities every N train steps (0 = uniform sampling)
+    pub lazy_priority_recompute: u64,
+    // Act uniformly at random for this many steps, with no training or epsilon decay, purely to
+    // seed the replay buffer
+    pub pure_random_steps: u64,
+    // Share of pure-random-phase actions taken from the heuristic instead (see `act_guided`)
+    pub warmup_heuristic_ratio: f32,
+    // Skip per-step training and do one update on the whole episode in `end_episode`
+    pub update_per_episode: bool,
+    episode_steps: usize,
+    // Transitions per stored return: each buffer entry sums this many rewards and bootstraps
+    // from the nth next state (fewer at the end of an episode)
+    pub n_step: usize,
+    // The current episode's newest transitions, not yet folded into an n-step entry
+    pending: VecDeque<Experience>,
+    // The newest buffer entry bootstraps from the state the next remembered transition starts
+    // in, so that transition's action is its `next_action`
+    awaiting_next_action: bool,
+    // Which network picks and which evaluates the bootstrap action (see `set_target_rule`)
+    target_rule: TargetRule,
+    // Clipped Double Q: second online network and its target
+    twin: Option<(Network, Network)>,
+    // Clamp every TD target into [min, max] (plain Q-networks; distributional targets are
+    // never clamped)
+    pub target_clamp: Option<(f32, f32)>,
+    // Standardizes features before every forward pass; replay keeps raw features and the
+    // statistics are updated from each remembered state
+    pub obs_norm: Option<ObsNormalizer>,
+    // Feature indices zeroed (after normalization) in every network input, for ablations
+    pub feature_mask: Vec<usize>,
+    // C51 / QR-DQN: the networks output a return distribution per action instead of a Q-value
+    // (see `distributional`)
+    distribution: Option<ReturnDistribution>,
+    // Feature layout every state fed to the network must be extracted with
+    pub features: &'static FeatureSet,
+    train_steps: u64,
     pub gamma: f32,
     pub epsilon: f32,
     pub epsilon_min: f32,
@@ -26,20 +283,115 @@ pub struct DQNAgent {
     lr_decay: f32,
     // Soft target update
     tau: f32,
+    // Copy the online network into the target outright on every update for this many steps,
+    // then switch to soft updates with `tau`
+    pub target_update_warmup: u64,
     train_every: u64,
     step_count: u64,
+    // Exploration, replay sampling and weight init all draw from this
+    rng: ChaCha8Rng,
 }
 
 impl DQNAgent {
-    pub fn new() -> Self {
-        let network = Network::new();
+    /// `dueling` gives the networks separate value and advantage streams (see `Network::new`)
+    pub fn new(num_actions: usize, dueling: bool) -> Self {
+        Self::with_rng(ChaCha8Rng::from_entropy(), num_actions, dueling)
+    }
+
+    /// Reproducible agent: same seed, same initial weights and random choices
+    pub fn with_seed(seed: u64, num_actions: usize, dueling: bool) -> Self {
+        Self::with_rng(ChaCha8Rng::seed_from_u64(seed), num_actions, dueling)
+    }
+
+    /// Distributional agent: the networks output a return distribution per action. C51 is
+    /// trained with the cross-entropy to the projected Bellman target distribution, QR-DQN with
+    /// the quantile Huber loss to the shifted target quantiles. `dueling` recombines value and
+    /// advantage streams per atom. Seeded like `with_seed` when `seed` is set.
+    pub fn distributional(
+        num_actions: usize,
+        distribution: ReturnDistribution,
+        dueling: bool,
+        seed: Option<u64>,
+    ) -> Self {
+        let rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let mut agent = Self::with_rng(rng, num_actions, false);
+        let (inputs, per_action) = (agent.features.size, distribution.outputs_per_action());
+        let rng = &mut agent.rng;
+        agent.network = Network::with_head(inputs, num_actions, per_action, dueling, rng);
+        agent.target_network = agent.network.clone_weights();
+        agent.distribution = Some(distribution);
+        agent
+    }
+
+    /// NoisyNet exploration: both networks get noisy linear layers and epsilon-greedy is
+    /// switched off for good. `act` explores through a fresh noise sample per step instead,
+    /// while greedy evaluation and export use the noise-free means.
+    pub fn enable_noisy_nets(&mut self) {
+        self.network.make_noisy(NOISY_SIGMA0);
+        self.target_network = self.network.clone_weights();
+        if let Some((twin, twin_target)) = self.twin.as_mut() {
+            twin.make_noisy(NOISY_SIGMA0);
+            *twin_target = twin.clone_weights();
+        }
+        self.epsilon = 0.0;
+        self.epsilon_min = 0.0;
+    }
+
+    pub fn is_noisy(&self) -> bool {
+        self.network.is_noisy()
+    }
+
+    pub fn target_rule(&self) -> TargetRule {
+        self.target_rule
+    }
+
+    /// Switches the TD target rule. `ClippedDouble` adds a freshly initialized twin online
+    /// network (shaped like the main one) unless one exists, e.g. from a checkpoint; the other
+    /// rules drop it.
+    pub fn set_target_rule(&mut self, rule: TargetRule) {
+        self.target_rule = rule;
+        if rule != TargetRule::ClippedDouble {
+            self.twin = None;
+        } else if self.twin.is_none() {
+            let per_action = self.distribution.map_or(1, |d| d.outputs_per_action());
+            let (inputs, actions) = (self.network.input_size(), self.num_actions());
+            let dueling = self.network.is_dueling();
+            let mut twin = Network::with_head(inputs, actions, per_action, dueling, &mut self.rng);
+            if self.is_noisy() {
+                twin.make_noisy(NOISY_SIGMA0);
+            }
+            let twin_target = twin.clone_weights();
+            self.twin = Some((twin, twin_target));
+        }
+    }
+
+    fn with_rng(mut rng: ChaCha8Rng, num_actions: usize, dueling: bool) -> Self {
+        let features = current_feature_set();
+        let network = Network::new(features.size, num_actions, dueling, &mut rng);
         let target_network = network.clone_weights();
         DQNAgent {
             network,
             target_network,
-            replay_buffer: VecDeque::with_capacity(50_000),
-            buffer_size: 50_000,
+            replay_buffer: ReplayBuffer::new(50_000),
             batch_size: 64,
+            terminal_fraction: 0.0,
+            max_sample_age: None,
+            lazy_priority_recompute: 0,
+            pure_random_steps: 0,
+            warmup_heuristic_ratio: 0.0,
+            update_per_episode: false,
+            episode_steps: 0,
+            n_step: 3,
+            pending: VecDeque::new(),
+            awaiting_next_action: false,
+            target_rule: TargetRule::Double,
+            twin: None,
+            target_clamp: None,
+            obs_norm: None,
+            feature_mask: Vec::new(),
+            distribution: None,
+            features,
+            train_steps: 0,
             gamma: 0.99,
             epsilon: 1.0,
             epsilon_min: 0.01,
@@ -48,99 +400,372 @@ impl DQNAgent {
             lr_min: 0.0001,
             lr_decay: 0.999995,
             tau: 0.001,
+            target_update_warmup: 0,
             train_every: 4,
             step_count: 0,
+            rng,
         }
     }
 
-    pub fn act(&self, features: &[f32]) -> usize {
-        let mut rng = rand::thread_rng();
-        if rng.gen::<f32>() < self.epsilon {
-            rng.gen_range(0..4)
+    /// `act`, except that during the pure-random phase a `warmup_heuristic_ratio` share of the
+    /// actions come from `heuristic` instead of uniform random
+    pub fn act_guided(&mut self, features: &[f32], heuristic: impl FnOnce() -> usize) -> usize {
+        if self.step_count < self.pure_random_steps
+            && self.warmup_heuristic_ratio > 0.0
+            && self.rng.gen::<f32>() < self.warmup_heuristic_ratio
+        {
+            return heuristic();
+        }
+        self.act(features)
+    }
+
+    pub fn act(&mut self, features: &[f32]) -> usize {
+        if self.step_count < self.pure_random_steps {
+            self.rng.gen_range(0..self.num_actions())
+        } else if self.is_noisy() {
+            self.network.resample_noise(&mut self.rng);
+            let output = self.network.forward_noisy(&self.network_input(features));
+            let q = match &self.distribution {
+                Some(distribution) => distribution.q_values(&output),
+                None => output,
+            };
+            argmax(&q)
+        } else if self.rng.gen::<f32>() < self.epsilon {
+            self.rng.gen_range(0..self.num_actions())
         } else {
             self.act_greedy(features)
         }
     }
 
+    /// Errors (with both lengths) when `features` can't be fed to the network, e.g. a feature
+    /// set that doesn't match a resumed network
+    pub fn check_input(&self, features: &[f32]) -> Result<(), String> {
+        let expected = self.network.input_size();
+        if features.len() != expected {
+            return Err(format!(
+                "feature vector has {} values but the network expects {} inputs (feature version {})",
+                features.len(),
+                expected,
+                self.features.version
+            ));
+        }
+        Ok(())
+    }
+
+    /// Size of the action space (the network's output count, over the outputs per action for
+    /// a distributional agent)
+    pub fn num_actions(&self) -> usize {
+        self.network.output_size() / self.distribution.map_or(1, |d| d.outputs_per_action())
+    }
+
+    /// Return distribution of a C51 or QR-DQN agent, None for a plain Q-network
+    pub fn distribution(&self) -> Option<ReturnDistribution> {
+        self.distribution
+    }
+
+    pub fn q_values(&self, features: &[f32]) -> Vec<f32> {
+        let output = if self.obs_norm.is_none() && self.feature_mask.is_empty() {
+            self.network.forward(features)
+        } else {
+            self.network.forward(&self.network_input(features))
+        };
+        match &self.distribution {
+            Some(distribution) => distribution.q_values(&output),
+            None => output,
+        }
+    }
+
+    /// Q-values of `network` for each of `inputs` (network inputs already), taking each
+    /// distribution's mean for a distributional agent
+    fn q_batch(&self, network: &Network, inputs: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        let outputs = network.predict_batch(inputs);
+        match &self.distribution {
+            Some(distribution) => outputs.iter().map(|o| distribution.q_values(o)).collect(),
+            None => outputs,
+        }
+    }
+
+    /// What the network is fed for `features`: normalized if enabled, then masked
+    pub fn network_input(&self, features: &[f32]) -> Vec<f32> {
+        let mut input = features.to_vec();
+        if let Some(norm) = &self.obs_norm {
+            norm.normalize(&mut input);
+        }
+        for &i in &self.feature_mask {
+            if let Some(value) = input.get_mut(i) {
+                *value = 0.0;
+            }
+        }
+        input
+    }
+
+    /// Hidden-layer activations for `features` (normalized and masked first), one vector per
+    /// hidden layer
+    pub fn hidden_activations(&self, features: &[f32]) -> Vec<Vec<f32>> {
+        let [hidden1, hidden2, _] =
+            self.network.forward_with_activations(&self.network_input(features));
+        vec![hidden1, hidden2]
+    }
+
+    /// Network inputs for buffer entries `indices`: their (next) states, see `network_input`
+    fn batch_inputs(&self, indices: impl Iterator<Item = usize>, next: bool) -> Vec<Vec<f32>> {
+        indices
+            .map(|i| {
+                let exp = self.replay_buffer.get(i);
+                self.network_input(if next { &exp.next_state } else { &exp.state })
+            })
+            .collect()
+    }
+
     pub fn act_greedy(&self, features: &[f32]) -> usize {
-        let q = self.network.forward(features);
-        q.iter()
-            .enumerate()
-            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-            .unwrap()
-            .0
+        argmax(&self.q_values(features))
     }
 
+    /// Stores `exp` as the last step of an n-step window: once `n_step` transitions are
+    /// pending the oldest one is stored with the window's return. A terminal transition
+    /// flushes the whole window, since nothing after it belongs to the same return.
     pub fn remember(&mut self, exp: Experience) {
-        if self.replay_buffer.len() >= self.buffer_size {
-            self.replay_buffer.pop_front();
+        if let Some(norm) = self.obs_norm.as_mut() {
+            norm.update(&exp.state);
+        }
+        if std::mem::take(&mut self.awaiting_next_action) {
+            if let Some(last) = self.replay_buffer.last_mut() {
+                last.next_action = Some(exp.action);
+            }
+        }
+        let done = exp.done;
+        self.pending.push_back(exp);
+        if done {
+            self.flush_pending();
+        } else if self.pending.len() >= self.n_step.max(1) {
+            self.replay_buffer.push(n_step_experience(&self.pending, self.gamma));
+            self.pending.pop_front();
+            self.awaiting_next_action = true;
+        }
+    }
+
+    /// Stores every pending transition with the (shorter) return left in the episode. After a
+    /// truncation the last transition isn't `done`, so these still bootstrap from its next
+    /// state.
+    fn flush_pending(&mut self) {
+        self.awaiting_next_action = false;
+        while !self.pending.is_empty() {
+            self.replay_buffer.push(n_step_experience(&self.pending, self.gamma));
+            self.pending.pop_front();
         }
-        self.replay_buffer.push_back(exp);
     }
 
     pub fn buffer_len(&self) -> usize {
         self.replay_buffer.len()
     }
 
+    /// Mean |Q_online - Q_target| over the `sample` most recent buffer states: how far the
+    /// target network lags. A large, growing gap means the soft update is too slow or the
+    /// online network is diverging.
+    pub fn target_drift(&self, sample: usize) -> f32 {
+        let len = self.replay_buffer.len();
+        let states = self.batch_inputs(len.saturating_sub(sample)..len, false);
+        mean_abs_q_diff(&self.network, &self.target_network, &states)
+    }
+
     pub fn step_and_train(&mut self) {
         self.step_count += 1;
-        if self.step_count % self.train_every != 0 {
+        if self.step_count <= self.pure_random_steps {
+            return;
+        }
+        if self.update_per_episode {
+            self.episode_steps += 1;
+            return;
+        }
+        if !self.step_count.is_multiple_of(self.train_every) {
             return;
         }
         self.train();
     }
 
+    /// Polyak rate of a target update made at agent step `step`: 1 (a hard copy) within the
+    /// first `target_update_warmup` steps, `tau` after
+    pub fn target_tau(&self, step: u64) -> f32 {
+        if step <= self.target_update_warmup {
+            1.0
+        } else {
+            self.tau
+        }
+    }
+
+    /// TD target, with the next action picked and evaluated as `target_rule` says;
+    /// `twin_next_q` is the twin target network's Q-values, for clipped Double Q. Clamped into
+    /// `target_clamp` when set.
+    pub fn td_target(
+        &self,
+        exp: &Experience,
+        main_next_q: &[f32],
+        target_next_q: &[f32],
+        twin_next_q: Option<&[f32]>,
+    ) -> f32 {
+        let target = if exp.done {
+            exp.reward
+        } else {
+            let best_action = match (self.target_rule, exp.next_action) {
+                (TargetRule::Sarsa, Some(action)) => action,
+                (TargetRule::Vanilla, _) => argmax(target_next_q),
+                _ => argmax(main_next_q),
+            };
+            let value = match twin_next_q {
+                Some(twin) => target_next_q[best_action].min(twin[best_action]),
+                None => target_next_q[best_action],
+            };
+            let value = if self.target_rule == TargetRule::ExpectedSarsa {
+                let mean = target_next_q.iter().sum::<f32>() / target_next_q.len() as f32;
+                (1.0 - self.epsilon) * value + self.epsilon * mean
+            } else {
+                value
+            };
+            exp.reward + exp.bootstrap_discount(self.gamma) * value
+        };
+        match self.target_clamp {
+            Some((lo, hi)) => target.clamp(lo, hi),
+            None => target,
+        }
+    }
+
+    /// Lazy prioritization: TD error of every transition in the buffer, used as its sampling
+    /// priority until the next recompute
+    fn recompute_priorities(&mut self) {
+        const CHUNK: usize = 1024;
+        let mut priorities = Vec::with_capacity(self.replay_buffer.len());
+
+        for start in (0..self.replay_buffer.len()).step_by(CHUNK) {
+            let end = (start + CHUNK).min(self.replay_buffer.len());
+            let states = self.batch_inputs(start..end, false);
+            let next_states = self.batch_inputs(start..end, true);
+
+            let current_qs = self.q_batch(&self.network, &states);
+            let main_next_qs = self.q_batch(&self.network, &next_states);
+            let target_next_qs = self.q_batch(&self.target_network, &next_states);
+            let twin_next_qs = self.twin.as_ref().map(|(_, t)| self.q_batch(t, &next_states));
+
+            for (k, i) in (start..end).enumerate() {
+                let exp = self.replay_buffer.get(i);
+                let twin = twin_next_qs.as_ref().map(|qs| qs[k].as_slice());
+                let target = self.td_target(exp, &main_next_qs[k], &target_next_qs[k], twin);
+                priorities.push((target - current_qs[k][exp.action]).abs());
+            }
+        }
+
+        self.replay_buffer.set_priorities(priorities);
+    }
+
     fn train(&mut self) {
         if self.replay_buffer.len() < self.batch_size {
             return;
         }
 
-        let mut rng = rand::thread_rng();
-        let buf_len = self.replay_buffer.len();
+        self.train_steps += 1;
+        if self.lazy_priority_recompute > 0
+            && (self.train_steps - 1).is_multiple_of(self.lazy_priority_recompute)
+        {
+            self.recompute_priorities();
+        }
 
-        let indices: Vec<usize> = (0..self.batch_size)
-            .map(|_| rng.gen_range(0..buf_len))
-            .collect();
+        let indices = self.replay_buffer.sample_indices(
+            self.batch_size,
+            self.terminal_fraction,
+            self.max_sample_age,
+            &mut self.rng,
+        );
+        self.train_on(&indices);
+    }
 
-        let states: Vec<Vec<f32>> = indices
-            .iter()
-            .map(|&i| self.replay_buffer[i].state.clone())
-            .collect();
-        let next_states: Vec<Vec<f32>> = indices
-            .iter()
-            .map(|&i| self.replay_buffer[i].next_state.clone())
-            .collect();
+    /// Distributional target for `exp`: the target network's distribution for the next action
+    /// (picked as `target_rule` says, see `td_target`), shifted by the reward and discounted
+    /// (see `ReturnDistribution::bellman_target`)
+    pub fn distributional_target(
+        &self,
+        distribution: &ReturnDistribution,
+        exp: &Experience,
+        main_next_q: &[f32],
+        target_next_output: &[f32],
+    ) -> Vec<f32> {
+        let best_action = match (self.target_rule, exp.next_action) {
+            (TargetRule::Sarsa, Some(action)) => action,
+            (TargetRule::Vanilla, _) => argmax(&distribution.q_values(target_next_output)),
+            _ => argmax(main_next_q),
+        };
+        let n = distribution.outputs_per_action();
+        let next = &target_next_output[best_action * n..(best_action + 1) * n];
+        distribution.bellman_target(exp.reward, exp.bootstrap_discount(self.gamma), exp.done, next)
+    }
 
-        let current_qs = self.network.predict_batch(&states);
+    /// One Adam step pulling each taken action's output distribution toward `targets[b]`, with
+    /// the loss that fits `distribution`
+    fn train_distributional(
+        &mut self,
+        distribution: &ReturnDistribution,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        actions: &[usize],
+    ) {
+        let lr = self.learning_rate;
+        match distribution {
+            ReturnDistribution::Categorical(_) => {
+                self.network.train_batch_distributional(inputs, targets, actions, lr)
+            }
+            ReturnDistribution::Quantile(_) => {
+                self.network.train_batch_quantile(inputs, targets, actions, lr)
+            }
+        }
+    }
 
-        // Double DQN: main network selects action, target network evaluates
-        let main_next_qs = self.network.predict_batch(&next_states);
-        let target_next_qs = self.target_network.predict_batch(&next_states);
+    /// One gradient step on the buffer entries `indices`, then the target and LR updates. A
+    /// noisy online network trains on a fresh noise sample; the targets use the means.
+    fn train_on(&mut self, indices: &[usize]) {
+        self.network.resample_noise(&mut self.rng);
+        let states = self.batch_inputs(indices.iter().copied(), false);
+        let next_states = self.batch_inputs(indices.iter().copied(), true);
 
-        let mut targets: Vec<Vec<f32>> = current_qs.iter().map(|q| q.to_vec()).collect();
+        let main_next_qs = self.q_batch(&self.network, &next_states);
+        let target_next_outputs = self.target_network.predict_batch(&next_states);
 
-        for (idx, &buf_idx) in indices.iter().enumerate() {
-            let exp = &self.replay_buffer[buf_idx];
-            targets[idx][exp.action] = if exp.done {
-                exp.reward
-            } else {
-                // Main network picks best action
-                let best_action = main_next_qs[idx]
+        let actions: Vec<usize> =
+            indices.iter().map(|&i| self.replay_buffer.get(i).action).collect();
+        match self.distribution {
+            Some(distribution) => {
+                let targets: Vec<Vec<f32>> = indices
                     .iter()
                     .enumerate()
-                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                    .unwrap()
-                    .0;
-                // Target network evaluates that action's value
-                exp.reward + self.gamma * target_next_qs[idx][best_action]
-            };
+                    .map(|(idx, &buf_idx)| {
+                        let exp = self.replay_buffer.get(buf_idx);
+                        let (main, target) = (&main_next_qs[idx], &target_next_outputs[idx]);
+                        self.distributional_target(&distribution, exp, main, target)
+                    })
+                    .collect();
+                self.train_distributional(&distribution, &states, &targets, &actions);
+            }
+            None => {
+                let twin_next_qs =
+                    self.twin.as_ref().map(|(_, t)| t.predict_batch(&next_states));
+                let targets: Vec<f32> = indices
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, &buf_idx)| {
+                        let exp = self.replay_buffer.get(buf_idx);
+                        let twin = twin_next_qs.as_ref().map(|qs| qs[idx].as_slice());
+                        self.td_target(exp, &main_next_qs[idx], &target_next_outputs[idx], twin)
+                    })
+                    .collect();
+                self.network
+                    .train_batch(&states, &targets, &actions, self.learning_rate);
+                self.train_twin(&states, &targets, &actions);
+            }
         }
 
-        self.network
-            .train_batch(&states, &targets, self.learning_rate);
-
-        // Soft target update (Polyak averaging)
-        self.network.soft_update_into(&mut self.target_network, self.tau);
+        // Target update: hard copy during the warmup, then Polyak averaging
+        let tau = self.target_tau(self.step_count);
+        self.network.soft_update_into(&mut self.target_network, tau);
+        if let Some((twin, twin_target)) = self.twin.as_mut() {
+            twin.soft_update_into(twin_target, tau);
+        }
 
         // LR decay
         if self.learning_rate > self.lr_min {
@@ -151,8 +776,116 @@ impl DQNAgent {
         }
     }
 
+    /// Supervised warm start: `steps` Adam steps regressing each output toward the one-hot of
+    /// `labels[i]` on random minibatches of `states`, after which the target network is synced
+    pub fn pretrain(&mut self, states: &[Vec<f32>], labels: &[usize], steps: u64) {
+        let num_actions = self.num_actions();
+        let rows = self.batch_size * num_actions;
+        for _ in 0..steps {
+            let mut inputs = Vec::with_capacity(rows);
+            let mut targets = Vec::with_capacity(rows);
+            let mut actions = Vec::with_capacity(rows);
+            for _ in 0..self.batch_size {
+                let i = self.rng.gen_range(0..states.len());
+                let input = self.network_input(&states[i]);
+                for action in 0..num_actions {
+                    inputs.push(input.clone());
+                    targets.push(if action == labels[i] { 1.0 } else { 0.0 });
+                    actions.push(action);
+                }
+            }
+            self.network.resample_noise(&mut self.rng);
+            match self.distribution {
+                // Distributional: each action's distribution is pulled onto its one-hot value
+                Some(distribution) => {
+                    let targets: Vec<Vec<f32>> =
+                        targets.iter().map(|&t| distribution.point_mass(t)).collect();
+                    self.train_distributional(&distribution, &inputs, &targets, &actions);
+                }
+                None => {
+                    self.network
+                        .train_batch(&inputs, &targets, &actions, self.learning_rate);
+                    self.train_twin(&inputs, &targets, &actions);
+                }
+            }
+        }
+        self.target_network = self.network.clone_weights();
+        if let Some((twin, twin_target)) = self.twin.as_mut() {
+            *twin_target = twin.clone_weights();
+        }
+    }
+
+    /// Clipped Double Q: the twin online network's step on the main network's batch
+    fn train_twin(&mut self, inputs: &[Vec<f32>], targets: &[f32], actions: &[usize]) {
+        if let Some((twin, _)) = self.twin.as_mut() {
+            twin.resample_noise(&mut self.rng);
+            twin.train_batch(inputs, targets, actions, self.learning_rate);
+        }
+    }
+
+    /// Snapshot of the learner state after `episode` episodes (networks are copied, including
+    /// the online network's Adam moments and step count)
+    pub fn checkpoint(&self, episode: u64) -> Checkpoint {
+        Checkpoint {
+            episode,
+            network: self.network.clone_with_optimizer(),
+            target_network: self.target_network.clone_weights(),
+            epsilon: self.epsilon,
+            learning_rate: self.learning_rate,
+            step_count: self.step_count,
+            train_steps: self.train_steps,
+            obs_norm: self.obs_norm.clone(),
+            env: None,
+            rng: Some(self.rng.clone()),
+            engine_rng: None,
+            replay: None,
+            feature_version: Some(self.features.version),
+            distribution: self.distribution,
+            twin: self.twin.as_ref().map(|(online, target)| {
+                (online.clone_with_optimizer(), target.clone_weights())
+            }),
+            target_rule: Some(self.target_rule),
+            best: None,
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.network = checkpoint.network;
+        self.target_network = checkpoint.target_network;
+        self.epsilon = checkpoint.epsilon;
+        self.learning_rate = checkpoint.learning_rate;
+        self.step_count = checkpoint.step_count;
+        self.train_steps = checkpoint.train_steps;
+        self.obs_norm = checkpoint.obs_norm;
+        self.distribution = checkpoint.distribution;
+        self.twin = checkpoint.twin;
+        if let Some(rng) = checkpoint.rng {
+            self.rng = rng;
+        }
+        if let Some(replay) = checkpoint.replay {
+            self.replay_buffer = replay;
+        }
+    }
+
+    pub fn replay_buffer(&self) -> &ReplayBuffer {
+        &self.replay_buffer
+    }
+
     pub fn end_episode(&mut self) {
-        self.decay_epsilon();
+        self.flush_pending();
+        self.replay_buffer.end_episode();
+        if self.update_per_episode && self.episode_steps > 0 {
+            // The episode's transitions are the newest entries in the buffer
+            let len = self.replay_buffer.len();
+            let indices: Vec<usize> = (len.saturating_sub(self.episode_steps)..len).collect();
+            self.episode_steps = 0;
+            self.train_steps += 1;
+            self.train_on(&indices);
+        }
+        // Epsilon starts decaying with the first episode that reaches past the random phase
+        if self.step_count > self.pure_random_steps {
+            self.decay_epsilon();
+        }
     }
 
     fn decay_epsilon(&mut self) {
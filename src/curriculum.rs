@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+/// Episodes averaged before a stage can advance
+const WINDOW: usize = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CurriculumMetric {
+    /// Rolling average score
+    Score,
+    /// Rolling average of snake length / board area at death
+    Fill,
+}
+
+/// Grid-size curriculum: trains on each size in turn, advancing once the rolling average of
+/// the chosen metric over the last `WINDOW` episodes of the stage reaches `threshold`
+#[derive(Clone)]
+pub struct Curriculum {
+    pub grid_sizes: Vec<i32>,
+    pub metric: CurriculumMetric,
+    pub threshold: f32,
+    pub stage: usize,
+    /// Raise epsilon to at least this value on every stage advance, to re-explore the harder
+    /// board (decay then resumes as usual)
+    pub epsilon_reset: Option<f32>,
+    recent: VecDeque<f32>,
+}
+
+impl Curriculum {
+    pub fn new(grid_sizes: Vec<i32>, metric: CurriculumMetric, threshold: f32) -> Self {
+        Curriculum {
+            grid_sizes,
+            metric,
+            threshold,
+            stage: 0,
+            epsilon_reset: None,
+            recent: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    pub fn grid_size(&self) -> i32 {
+        self.grid_sizes[self.stage]
+    }
+
+    /// Records a finished episode; returns true if this advanced to the next stage, in which
+    /// case `epsilon` has been raised to `epsilon_reset` if set
+    pub fn record(&mut self, score: i32, snake_len: usize, epsilon: &mut f32) -> bool {
+        let gs = self.grid_size();
+        let value = match self.metric {
+            CurriculumMetric::Score => score as f32,
+            CurriculumMetric::Fill => snake_len as f32 / (gs * gs) as f32,
+        };
+        self.recent.push_back(value);
+        if self.recent.len() > WINDOW {
+            self.recent.pop_front();
+        }
+
+        let avg = self.recent.iter().sum::<f32>() / self.recent.len() as f32;
+        if self.recent.len() < WINDOW
+            || avg < self.threshold
+            || self.stage + 1 >= self.grid_sizes.len()
+        {
+            return false;
+        }
+
+        self.stage += 1;
+        self.recent.clear();
+        if let Some(reset) = self.epsilon_reset {
+            *epsilon = epsilon.max(reset);
+        }
+        true
+    }
+}
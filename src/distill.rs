@@ -0,0 +1,200 @@
+use crate::agent::DQNAgent;
+use crate::features::FEATURE_NAMES;
+use crate::tools::{sample_greedy_states, tool_action, ARROWS};
+
+/// Depth-limited CART classifier (Gini splits on `feature <= threshold`) approximating the
+/// greedy policy
+enum Node {
+    Leaf {
+        action: usize,
+        samples: usize,
+    },
+    Split {
+        feature: usize,
+        threshold: f32,
+        left: Box<Node>,
+        right: Box<Node>,
+    },
+}
+
+impl Node {
+    fn predict(&self, features: &[f32]) -> usize {
+        match self {
+            Node::Leaf { action, .. } => *action,
+            Node::Split {
+                feature,
+                threshold,
+                left,
+                right,
+            } => {
+                if features[*feature] <= *threshold {
+                    left.predict(features)
+                } else {
+                    right.predict(features)
+                }
+            }
+        }
+    }
+
+    fn render(&self, indent: usize, out: &mut String) {
+        let pad = "  ".repeat(indent);
+        match self {
+            Node::Leaf { action, samples } => {
+                out.push_str(&format!("{}-> {} (n={})\n", pad, ARROWS[*action], samples));
+            }
+            Node::Split {
+                feature,
+                threshold,
+                left,
+                right,
+            } => {
+                let name = FEATURE_NAMES[*feature];
+                out.push_str(&format!("{}if {} <= {:.4}:\n", pad, name, threshold));
+                left.render(indent + 1, out);
+                out.push_str(&format!("{}else:\n", pad));
+                right.render(indent + 1, out);
+            }
+        }
+    }
+}
+
+fn gini(counts: &[usize], total: usize) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    let t = total as f32;
+    1.0 - counts.iter().map(|&c| (c as f32 / t).powi(2)).sum::<f32>()
+}
+
+fn class_counts(labels: &[usize], rows: &[usize], num_actions: usize) -> Vec<usize> {
+    let mut counts = vec![0; num_actions];
+    for &r in rows {
+        counts[labels[r]] += 1;
+    }
+    counts
+}
+
+fn majority(counts: &[usize]) -> usize {
+    (0..counts.len()).max_by_key(|&a| counts[a]).unwrap_or(0)
+}
+
+/// Best (feature, threshold, weighted child impurity) over all features, if any split separates
+/// the rows
+// `feature` indexes a column across many rows, which no single iterator covers
+#[allow(clippy::needless_range_loop)]
+fn best_split(
+    states: &[Vec<f32>],
+    labels: &[usize],
+    rows: &[usize],
+    num_actions: usize,
+) -> Option<(usize, f32, f32)> {
+    let total = rows.len();
+    let all = class_counts(labels, rows, num_actions);
+    let mut best: Option<(usize, f32, f32)> = None;
+    let mut sorted = rows.to_vec();
+
+    for feature in 0..states[rows[0]].len() {
+        sorted.sort_by(|&a, &b| states[a][feature].partial_cmp(&states[b][feature]).unwrap());
+        let mut left = vec![0; num_actions];
+        for (i, pair) in sorted.windows(2).enumerate() {
+            left[labels[pair[0]]] += 1;
+            let (v, next) = (states[pair[0]][feature], states[pair[1]][feature]);
+            if v == next {
+                continue;
+            }
+            let right: Vec<usize> = all.iter().zip(&left).map(|(a, l)| a - l).collect();
+            let n_left = i + 1;
+            let impurity = (n_left as f32 * gini(&left, n_left)
+                + (total - n_left) as f32 * gini(&right, total - n_left))
+                / total as f32;
+            if best.is_none_or(|(_, _, b)| impurity < b) {
+                best = Some((feature, (v + next) / 2.0, impurity));
+            }
+        }
+    }
+    best
+}
+
+fn fit(
+    states: &[Vec<f32>],
+    labels: &[usize],
+    rows: &[usize],
+    depth: usize,
+    num_actions: usize,
+) -> Node {
+    let counts = class_counts(labels, rows, num_actions);
+    let leaf = Node::Leaf {
+        action: majority(&counts),
+        samples: rows.len(),
+    };
+    let impurity = gini(&counts, rows.len());
+    if depth == 0 || rows.len() < 2 || impurity == 0.0 {
+        return leaf;
+    }
+
+    match best_split(states, labels, rows, num_actions) {
+        Some((feature, threshold, split_impurity)) if split_impurity < impurity => {
+            let (l, r): (Vec<usize>, Vec<usize>) =
+                rows.iter().partition(|&&i| states[i][feature] <= threshold);
+            Node::Split {
+                feature,
+                threshold,
+                left: Box::new(fit(states, labels, &l, depth - 1, num_actions)),
+                right: Box::new(fit(states, labels, &r, depth - 1, num_actions)),
+            }
+        }
+        _ => leaf,
+    }
+}
+
+/// Tree distilled from a greedy policy by `distill_tree`, with how well it matches
+pub struct DistilledTree {
+    tree: Node,
+    depth: usize,
+    /// Share of the sampled states where tree and network pick the same action
+    pub agreement: f32,
+    pub samples: usize,
+}
+
+impl DistilledTree {
+    /// The tree as nested if/else rules followed by its agreement with the network
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.tree.render(0, &mut out);
+        out.push_str(&format!(
+            "Tree (depth <= {}) matches the greedy policy on {:.1}% of {} sampled states\n",
+            self.depth,
+            100.0 * self.agreement,
+            self.samples
+        ));
+        out
+    }
+}
+
+/// Fits a tree of at most `depth` splits to the greedy actions of `agent` on `samples` states
+/// from its own greedy episodes (reproducible with a `seed`). On a briefly trained model,
+/// depth 4 matched about 73%, depth 6 about 87% and depth 8 about 95%.
+pub fn distill_tree(
+    agent: &DQNAgent,
+    grid_size: i32,
+    samples: usize,
+    depth: usize,
+    seed: Option<u64>,
+) -> DistilledTree {
+    let states = sample_greedy_states(agent, grid_size, samples, seed);
+    let labels: Vec<usize> = states.iter().map(|s| tool_action(agent, s)).collect();
+    let rows: Vec<usize> = (0..states.len()).collect();
+    let tree = fit(&states, &labels, &rows, depth, agent.num_actions());
+
+    let agree = states
+        .iter()
+        .zip(&labels)
+        .filter(|(s, &a)| tree.predict(s) == a)
+        .count();
+    DistilledTree {
+        tree,
+        depth,
+        agreement: agree as f32 / states.len().max(1) as f32,
+        samples: states.len(),
+    }
+}
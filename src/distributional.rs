@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+/// Atoms per action in the C51 value distribution
+pub const C51_ATOMS: usize = 51;
+
+/// What a distributional agent's network outputs per action in place of a Q-value
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReturnDistribution {
+    /// C51: logits of a categorical distribution over a fixed support
+    Categorical(Support),
+    /// QR-DQN: this many quantile values of the return, at the quantile midpoints
+    /// (2i + 1) / 2N, with no fixed range
+    Quantile(usize),
+}
+
+impl ReturnDistribution {
+    /// Network outputs per action (atoms or quantiles)
+    pub fn outputs_per_action(&self) -> usize {
+        match self {
+            ReturnDistribution::Categorical(support) => support.atoms,
+            ReturnDistribution::Quantile(n) => *n,
+        }
+    }
+
+    /// Q-values from the network output: the mean of each action's distribution
+    pub fn q_values(&self, output: &[f32]) -> Vec<f32> {
+        match self {
+            ReturnDistribution::Categorical(support) => support.q_values(output),
+            ReturnDistribution::Quantile(n) => {
+                output.chunks(*n).map(|q| q.iter().sum::<f32>() / *n as f32).collect()
+            }
+        }
+    }
+
+    /// Bellman target for one action's distribution, from `next` (the network output block
+    /// of the bootstrap action): C51 projects the shifted probabilities onto the support,
+    /// QR-DQN shifts each quantile (all collapse onto `reward` when `done`)
+    pub fn bellman_target(&self, reward: f32, discount: f32, done: bool, next: &[f32]) -> Vec<f32> {
+        match self {
+            ReturnDistribution::Categorical(support) => {
+                support.project(reward, discount, done, &support.probabilities(next))
+            }
+            ReturnDistribution::Quantile(_) => next
+                .iter()
+                .map(|&q| if done { reward } else { reward + discount * q })
+                .collect(),
+        }
+    }
+
+    /// Target for a distribution with all its mass on `value`
+    pub fn point_mass(&self, value: f32) -> Vec<f32> {
+        match self {
+            ReturnDistribution::Categorical(support) => support.dirac(value),
+            ReturnDistribution::Quantile(n) => vec![value; *n],
+        }
+    }
+}
+
+/// Fixed support of a categorical (C51) return distribution: `atoms` evenly spaced values from
+/// `v_min` to `v_max`. The network outputs one logit per (action, atom), action-major.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Support {
+    pub atoms: usize,
+    pub v_min: f32,
+    pub v_max: f32,
+}
+
+impl Support {
+    pub fn new(v_min: f32, v_max: f32) -> Self {
+        Support {
+            atoms: C51_ATOMS,
+            v_min,
+            v_max,
+        }
+    }
+
+    fn delta(&self) -> f32 {
+        (self.v_max - self.v_min) / (self.atoms - 1) as f32
+    }
+
+    /// Value of atom `i`
+    pub fn atom(&self, i: usize) -> f32 {
+        self.v_min + i as f32 * self.delta()
+    }
+
+    /// Per-action probabilities: a softmax over each action's block of `atoms` logits
+    pub fn probabilities(&self, logits: &[f32]) -> Vec<f32> {
+        let mut probs = logits.to_vec();
+        for block in probs.chunks_mut(self.atoms) {
+            let max = block.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let mut sum = 0.0;
+            for p in block.iter_mut() {
+                *p = (*p - max).exp();
+                sum += *p;
+            }
+            block.iter_mut().for_each(|p| *p /= sum);
+        }
+        probs
+    }
+
+    /// Q-values: the mean of each action's distribution
+    pub fn q_values(&self, logits: &[f32]) -> Vec<f32> {
+        self.probabilities(logits)
+            .chunks(self.atoms)
+            .map(|block| block.iter().enumerate().map(|(i, p)| p * self.atom(i)).sum())
+            .collect()
+    }
+
+    /// Projects the distribution of `reward + discount * Z` (just `reward` when `done`), where
+    /// Z has probabilities `next_probs` over this support, back onto the support: each shifted
+    /// atom is clamped into [v_min, v_max] and its mass split between its two neighbours
+    pub fn project(&self, reward: f32, discount: f32, done: bool, next_probs: &[f32]) -> Vec<f32> {
+        let mut target = vec![0.0; self.atoms];
+        for (i, &p) in next_probs.iter().enumerate() {
+            let value = if done { reward } else { reward + discount * self.atom(i) };
+            self.spread(value, p, &mut target);
+        }
+        target
+    }
+
+    /// All the mass on `value` (clamped into the support), split between its neighbours
+    pub fn dirac(&self, value: f32) -> Vec<f32> {
+        let mut target = vec![0.0; self.atoms];
+        self.spread(value, 1.0, &mut target);
+        target
+    }
+
+    fn spread(&self, value: f32, mass: f32, target: &mut [f32]) {
+        let b = (value.clamp(self.v_min, self.v_max) - self.v_min) / self.delta();
+        let (lower, upper) = (b.floor() as usize, (b.ceil() as usize).min(self.atoms - 1));
+        if lower == upper {
+            target[lower] += mass;
+        } else {
+            target[lower] += mass * (upper as f32 - b);
+            target[upper] += mass * (b - lower as f32);
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashSet, VecDeque};
 
 #[derive(Clone, Copy, PartialEq)]
@@ -7,6 +11,10 @@ pub enum Direction {
     Right,
     Down,
     Left,
+    UpRight,
+    DownRight,
+    DownLeft,
+    UpLeft,
 }
 
 impl Direction {
@@ -16,6 +24,10 @@ impl Direction {
             Direction::Down => Direction::Up,
             Direction::Left => Direction::Right,
             Direction::Right => Direction::Left,
+            Direction::UpRight => Direction::DownLeft,
+            Direction::DownLeft => Direction::UpRight,
+            Direction::DownRight => Direction::UpLeft,
+            Direction::UpLeft => Direction::DownRight,
         }
     }
 
@@ -25,6 +37,10 @@ impl Direction {
             Direction::Right => (1, 0),
             Direction::Down => (0, 1),
             Direction::Left => (-1, 0),
+            Direction::UpRight => (1, -1),
+            Direction::DownRight => (1, 1),
+            Direction::DownLeft => (-1, 1),
+            Direction::UpLeft => (-1, -1),
         }
     }
 }
@@ -35,21 +51,144 @@ pub struct Point {
     pub y: i32,
 }
 
-pub const ACTIONS: [Direction; 4] = [
+/// Action index → move. The first `ORTHOGONAL_ACTIONS` are the classic four; the diagonals
+/// after them are only used when `SnakeEngine::diagonals` is set.
+pub const ACTIONS: [Direction; 8] = [
     Direction::Up,
     Direction::Right,
     Direction::Down,
     Direction::Left,
+    Direction::UpRight,
+    Direction::DownRight,
+    Direction::DownLeft,
+    Direction::UpLeft,
 ];
 
+pub const ORTHOGONAL_ACTIONS: usize = 4;
+
+/// Environment settings a model was trained under, saved with exports and checkpoints so a
+/// model isn't silently run in a different environment
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvMode {
+    pub grid_size: i32,
+    pub obstacles: usize,
+    pub diagonals: bool,
+}
+
+impl EnvMode {
+    /// Human-readable list of the settings that differ between `self` (trained) and `current`
+    pub fn differences(&self, current: &EnvMode) -> Vec<String> {
+        let mut out = Vec::new();
+        if self.grid_size != current.grid_size {
+            out.push(format!("grid {} (now {})", self.grid_size, current.grid_size));
+        }
+        if self.obstacles != current.obstacles {
+            out.push(format!("{} obstacles (now {})", self.obstacles, current.obstacles));
+        }
+        if self.diagonals != current.diagonals {
+            out.push(format!("diagonals {} (now {})", self.diagonals, current.diagonals));
+        }
+        out
+    }
+}
+
+/// State potential Phi(s) for potential-based shaping
+#[derive(Clone, Copy, PartialEq)]
+pub enum Potential {
+    /// Negative Manhattan distance from head to food, as a fraction of the grid size
+    Distance,
+    /// Cells reachable from the head, as a fraction of the board
+    Flood,
+}
+
+/// Optional reward-shaping terms; the defaults reproduce the original rewards
+#[derive(Clone, Default)]
+pub struct RewardConfig {
+    /// Replace the +1/-1 food-approach term with F = gamma * Phi(s') - Phi(s) (Phi = 0 at
+    /// death), which leaves the optimal policy unchanged
+    pub potential: Option<Potential>,
+    /// Discount used in F; should match the agent's gamma
+    pub potential_gamma: f32,
+    /// Number of recent head positions checked for loops (0 = off)
+    pub anti_loop_window: usize,
+    /// Per-step penalty while the head is cycling over a few cells
+    pub anti_loop_penalty: f32,
+    /// Number of previous head positions checked for revisits (0 = off)
+    pub revisit_window: usize,
+    /// Penalty when the head enters a cell it occupied within the last `revisit_window` steps
+    pub revisit_penalty: f32,
+    /// Drop the tail-reachability term (and its BFS) from the long-snake safety shaping
+    pub no_tail_bonus: bool,
+    /// Weight on the step-over-step change in head reachable space (as a fraction of the board)
+    pub space_delta_bonus: f32,
+    /// Extra death penalty per segment grown beyond the starting length, so losing a long
+    /// snake costs more than losing a short one
+    pub death_length_scale: f32,
+    /// Food reward change per segment of the snake that eats it: 10 + slope × length, so
+    /// food gets worth more (slope > 0) or less (slope < 0) as the snake grows
+    pub food_length_slope: f32,
+    /// Bonus when the head enters a cell it hasn't occupied yet this episode
+    pub coverage_bonus: f32,
+    /// Final fraction of the starvation limit over which a per-step penalty ramps from 0 up to
+    /// 1, so running out of time hurts before the cliff (0 = off)
+    pub starvation_ramp: f32,
+    /// Penalty weight for the head being close to its own body: weight / Manhattan distance to
+    /// the nearest segment past the neck
+    pub body_proximity_penalty: f32,
+}
+
 pub struct SnakeEngine {
     pub grid_size: i32,
+    /// Head first. Invariant: never empty after `reset` (every move pushes a head before
+    /// popping the tail), so `snake[0]` and `snake[len - 1]` are always valid.
     pub snake: Vec<Point>,
     pub direction: Direction,
     pub food: Point,
+    /// Blocked cells, re-placed on every `reset`; treated like walls
+    pub obstacles: Vec<Point>,
+    /// Number of random obstacles placed on `reset`
+    pub num_obstacles: usize,
     pub score: i32,
     pub game_over: bool,
     pub steps_without_food: i32,
+    /// Steps taken this episode
+    pub steps: u32,
+    /// End episodes after this many steps without marking them terminal
+    pub truncate_at: Option<u32>,
+    /// Set when the episode ended by truncation rather than death
+    pub truncated: bool,
+    /// End the episode as a death once the head's reachable space falls below this fraction of
+    /// the snake length (the snake can't fit, so it's doomed)
+    pub doom_cutoff: Option<f32>,
+    /// Extra steps without food allowed per snake segment on top of the board area, so longer
+    /// snakes, which need longer paths, don't starve as quickly
+    pub starvation_length_scale: f32,
+    pub reward: RewardConfig,
+    /// Food-spawn bias toward open regions: 0 = uniform, 1 = weight cells by the size of the
+    /// free region they belong to
+    pub food_open_bias: f32,
+    /// Probability that a food spawn skips the four corner cells (0 = corners allowed)
+    pub corner_food_exclusion: f32,
+    /// Scripted food positions used in order (wrapping) instead of random spawning; the script
+    /// restarts every episode
+    pub food_script: Vec<Point>,
+    food_script_pos: usize,
+    /// Probability of ignoring the requested action and repeating the previous one
+    pub sticky_actions: f32,
+    /// Action actually executed by the last `step` (differs from the request when sticky)
+    pub last_action: usize,
+    /// Allow the four diagonal moves in addition to the orthogonal ones
+    pub diagonals: bool,
+    recent_heads: VecDeque<(i32, i32)>,
+    // Head flood-fill count after the previous step (for `space_delta_bonus`)
+    prev_reachable: Option<u32>,
+    // Cells the snake has occupied this episode, row-major (for `coverage_bonus`)
+    visited: Vec<bool>,
+    // Food placement and sticky actions
+    rng: ChaCha8Rng,
+    /// Times `step` has run the `can_reach_tail` search for the shaping's tail term
+    #[cfg(feature = "test-hooks")]
+    pub tail_checks: u64,
 }
 
 impl SnakeEngine {
@@ -59,14 +198,50 @@ impl SnakeEngine {
             snake: Vec::new(),
             direction: Direction::Right,
             food: Point { x: 0, y: 0 },
+            obstacles: Vec::new(),
+            num_obstacles: 0,
             score: 0,
             game_over: false,
             steps_without_food: 0,
+            steps: 0,
+            truncate_at: None,
+            truncated: false,
+            doom_cutoff: None,
+            starvation_length_scale: 0.0,
+            reward: RewardConfig::default(),
+            food_open_bias: 0.0,
+            corner_food_exclusion: 0.0,
+            food_script: Vec::new(),
+            food_script_pos: 0,
+            sticky_actions: 0.0,
+            last_action: 1,
+            diagonals: false,
+            recent_heads: VecDeque::new(),
+            prev_reachable: None,
+            visited: Vec::new(),
+            rng: ChaCha8Rng::from_entropy(),
+            #[cfg(feature = "test-hooks")]
+            tail_checks: 0,
         };
         engine.reset();
         engine
     }
 
+    /// Makes food placement (and sticky actions) reproducible from the next `reset`
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    /// Current RNG state, for checkpoints
+    pub fn rng(&self) -> &ChaCha8Rng {
+        &self.rng
+    }
+
+    /// Continues from a saved RNG state (see `rng`)
+    pub fn set_rng(&mut self, rng: ChaCha8Rng) {
+        self.rng = rng;
+    }
+
     pub fn reset(&mut self) {
         let mid = self.grid_size / 2;
         self.snake = vec![
@@ -75,40 +250,118 @@ impl SnakeEngine {
             Point { x: mid - 2, y: mid },
         ];
         self.direction = Direction::Right;
+        self.last_action = 1;
         self.score = 0;
         self.game_over = false;
         self.steps_without_food = 0;
+        self.steps = 0;
+        self.truncated = false;
+        self.recent_heads.clear();
+        self.prev_reachable = None;
+        self.food_script_pos = 0;
+        self.visited.clear();
+        self.visited.resize((self.grid_size * self.grid_size) as usize, false);
+        for s in &self.snake {
+            self.visited[(s.y * self.grid_size + s.x) as usize] = true;
+        }
+        self.place_obstacles();
         self.food = self.spawn_food();
     }
 
+    pub fn is_obstacle(&self, x: i32, y: i32) -> bool {
+        self.obstacles.iter().any(|o| o.x == x && o.y == y)
+    }
+
+    /// Random obstacles off the snake, keeping the three cells ahead of the head clear so the
+    /// episode doesn't start blocked
+    fn place_obstacles(&mut self) {
+        self.obstacles.clear();
+        if self.num_obstacles == 0 {
+            return;
+        }
+
+        let head = self.snake[0];
+        let mut candidates = Vec::new();
+        for x in 0..self.grid_size {
+            for y in 0..self.grid_size {
+                let ahead = y == head.y && x > head.x && x <= head.x + 3;
+                if !ahead && !self.snake.iter().any(|s| s.x == x && s.y == y) {
+                    candidates.push(Point { x, y });
+                }
+            }
+        }
+        self.obstacles = candidates
+            .choose_multiple(&mut self.rng, self.num_obstacles)
+            .copied()
+            .collect();
+    }
+
+    pub fn mode(&self) -> EnvMode {
+        EnvMode {
+            grid_size: self.grid_size,
+            obstacles: self.num_obstacles,
+            diagonals: self.diagonals,
+        }
+    }
+
+    /// Number of valid action indices (`ACTIONS` prefix)
+    pub fn num_actions(&self) -> usize {
+        if self.diagonals {
+            ACTIONS.len()
+        } else {
+            ORTHOGONAL_ACTIONS
+        }
+    }
+
     pub fn step(&mut self, action: usize) -> (f32, bool) {
+        debug_assert!(!self.snake.is_empty(), "step called with an empty snake");
+        debug_assert!(action < self.num_actions(), "action {} out of range", action);
+        let action = if self.sticky_actions > 0.0
+            && self.rng.gen::<f32>() < self.sticky_actions
+        {
+            self.last_action
+        } else {
+            action
+        };
+        self.last_action = action;
         let dir = ACTIONS[action];
         if dir.opposite() != self.direction {
             self.direction = dir;
         }
 
         let head = self.snake[0];
+        let prev_len = self.snake.len();
         let prev_dist = (head.x - self.food.x).abs() + (head.y - self.food.y).abs();
         let prev_score = self.score;
+        let phi_before = self.potential();
 
         self.update();
+        let space_delta = self.space_delta();
+        let coverage = self.coverage();
 
         let reward;
         if self.game_over {
-            reward = -10.0;
+            reward = -self.death_penalty();
         } else if self.score > prev_score {
-            reward = 10.0;
+            reward = self.food_reward(prev_len) + coverage;
             self.steps_without_food = 0;
+            self.recent_heads.clear();
         } else {
             self.steps_without_food += 1;
-            if self.steps_without_food > self.grid_size * self.grid_size {
+            if self.steps_without_food > self.starvation_limit() || self.doomed() {
                 self.game_over = true;
-                reward = -10.0;
+                reward = -self.death_penalty();
             } else {
                 let new_head = self.snake[0];
                 let new_dist =
                     (new_head.x - self.food.x).abs() + (new_head.y - self.food.y).abs();
-                let approach = if new_dist < prev_dist { 1.0 } else { -1.0 };
+                let approach = if self.reward.potential.is_some() {
+                    0.0
+                } else if new_dist < prev_dist {
+                    1.0
+                } else {
+                    -1.0
+                };
 
                 // Preventive reward shaping (only kicks in when snake is big enough to matter)
                 let snake_len = self.snake.len() as f32;
@@ -128,18 +381,68 @@ impl SnakeEngine {
                     };
 
                     // Bonus for maintaining access to tail
-                    let tail_bonus = if self.can_reach_tail() { 0.5 } else { -1.0 };
+                    let tail_bonus = if self.reward.no_tail_bonus {
+                        0.0
+                    } else {
+                        #[cfg(feature = "test-hooks")]
+                        {
+                            self.tail_checks += 1;
+                        }
+                        if self.can_reach_tail() {
+                            0.5
+                        } else {
+                            -1.0
+                        }
+                    };
 
                     space_penalty + tail_bonus
                 } else {
                     0.0
                 };
 
-                reward = approach + safety_bonus;
+                self.record_head();
+                reward = approach + safety_bonus - self.loop_penalty() - self.revisit_penalty()
+                    - self.starvation_penalty()
+                    - self.body_proximity()
+                    + space_delta
+                    + coverage;
             }
         }
 
-        (reward, self.game_over)
+        let reward = if self.reward.potential.is_some() {
+            let phi_after = if self.game_over { 0.0 } else { self.potential() };
+            reward + self.reward.potential_gamma * phi_after - phi_before
+        } else {
+            reward
+        };
+
+        // Truncation ends the episode without making it terminal: the caller should keep
+        // bootstrapping from the last state (`done && !truncated`)
+        self.steps += 1;
+        if !self.game_over && self.truncate_at.is_some_and(|t| self.steps >= t) {
+            self.truncated = true;
+        }
+
+        (reward, self.game_over || self.truncated)
+    }
+
+    /// Frame skip: runs `action` for up to `repeat` steps, stopping when the episode ends.
+    /// Returns the rewards discounted within the span (r0 + gamma r1 + gamma^2 r2 ...), whether
+    /// the episode ended, and the number of steps taken; a transition built from it bootstraps
+    /// with gamma^steps.
+    pub fn step_repeat(&mut self, action: usize, repeat: u32, gamma: f32) -> (f32, bool, u32) {
+        let mut total = 0.0;
+        let mut discount = 1.0;
+        let mut steps = 0;
+        loop {
+            let (reward, done) = self.step(action);
+            total += discount * reward;
+            discount *= gamma;
+            steps += 1;
+            if done || steps >= repeat {
+                return (total, done, steps);
+            }
+        }
     }
 
     fn update(&mut self) {
@@ -167,6 +470,7 @@ impl SnakeEngine {
             .snake
             .iter()
             .any(|s| s.x == new_head.x && s.y == new_head.y)
+            || self.is_obstacle(new_head.x, new_head.y)
         {
             self.game_over = true;
             return;
@@ -180,12 +484,174 @@ impl SnakeEngine {
         } else {
             self.snake.pop();
         }
+        debug_assert!(!self.snake.is_empty());
+    }
+
+    /// `space_delta_bonus` × change in head reachable space since the previous step, as a
+    /// fraction of the board: positive when the move opened up room
+    fn space_delta(&mut self) -> f32 {
+        if self.reward.space_delta_bonus == 0.0 || self.game_over {
+            return 0.0;
+        }
+        let reachable = self.flood_fill_from_head();
+        let delta = match self.prev_reachable {
+            Some(prev) => reachable as f32 - prev as f32,
+            None => 0.0,
+        };
+        self.prev_reachable = Some(reachable);
+        self.reward.space_delta_bonus * delta / (self.grid_size * self.grid_size) as f32
+    }
+
+    /// Steps without food after which the snake starves: the board area, plus
+    /// `starvation_length_scale` per segment
+    pub fn starvation_limit(&self) -> i32 {
+        let extra = self.starvation_length_scale * self.snake.len() as f32;
+        self.grid_size * self.grid_size + extra as i32
+    }
+
+    /// Linear 0 → 1 over the final `starvation_ramp` fraction of the starvation limit
+    fn starvation_penalty(&self) -> f32 {
+        if self.reward.starvation_ramp <= 0.0 {
+            return 0.0;
+        }
+        let limit = self.starvation_limit() as f32;
+        let span = limit * self.reward.starvation_ramp.min(1.0);
+        ((self.steps_without_food as f32 - (limit - span)) / span).clamp(0.0, 1.0)
+    }
+
+    /// `body_proximity_penalty` / distance from the head to the nearest segment past the neck
+    /// (0 for a snake too short to have one)
+    fn body_proximity(&self) -> f32 {
+        if self.reward.body_proximity_penalty == 0.0 {
+            return 0.0;
+        }
+        let head = self.snake[0];
+        let nearest = self
+            .snake
+            .iter()
+            .skip(2)
+            .map(|s| (s.x - head.x).abs() + (s.y - head.y).abs())
+            .min();
+        match nearest {
+            Some(d) => self.reward.body_proximity_penalty / d.max(1) as f32,
+            None => 0.0,
+        }
+    }
+
+    /// `coverage_bonus` if the head just entered a cell not visited earlier this episode
+    fn coverage(&mut self) -> f32 {
+        if self.game_over {
+            return 0.0;
+        }
+        let head = self.snake[0];
+        let cell = &mut self.visited[(head.y * self.grid_size + head.x) as usize];
+        if *cell {
+            0.0
+        } else {
+            *cell = true;
+            self.reward.coverage_bonus
+        }
+    }
+
+    /// 10, plus `food_length_slope` per segment of the `length`-long snake that ate
+    fn food_reward(&self, length: usize) -> f32 {
+        10.0 + self.reward.food_length_slope * length as f32
+    }
+
+    /// 10, plus `death_length_scale` per segment beyond the starting 3
+    fn death_penalty(&self) -> f32 {
+        10.0 + self.reward.death_length_scale * self.snake.len().saturating_sub(3) as f32
+    }
+
+    fn doomed(&self) -> bool {
+        self.doom_cutoff.is_some_and(|ratio| {
+            (self.flood_fill_from_head() as f32) < ratio * self.snake.len() as f32
+        })
+    }
+
+    /// Phi of the current state under `reward.potential` (0 when unset)
+    fn potential(&self) -> f32 {
+        let head = self.snake[0];
+        match self.reward.potential {
+            None => 0.0,
+            Some(Potential::Distance) => {
+                let dist = (head.x - self.food.x).abs() + (head.y - self.food.y).abs();
+                -(dist as f32) / self.grid_size as f32
+            }
+            Some(Potential::Flood) => {
+                self.flood_fill_from_head() as f32 / (self.grid_size * self.grid_size) as f32
+            }
+        }
+    }
+
+    /// Pushes the head onto `recent_heads`, keeping as many positions as the loop and revisit
+    /// checks need
+    fn record_head(&mut self) {
+        let keep = if self.reward.revisit_window > 0 {
+            self.reward.anti_loop_window.max(self.reward.revisit_window + 1)
+        } else {
+            self.reward.anti_loop_window
+        };
+        if keep == 0 {
+            return;
+        }
+
+        let head = self.snake[0];
+        self.recent_heads.push_back((head.x, head.y));
+        while self.recent_heads.len() > keep {
+            self.recent_heads.pop_front();
+        }
+    }
+
+    /// Penalty while looping: the last `anti_loop_window` heads cover at most half as many
+    /// distinct cells, i.e. the snake keeps circling instead of going anywhere
+    fn loop_penalty(&self) -> f32 {
+        let window = self.reward.anti_loop_window;
+        if window == 0 || self.recent_heads.len() < window {
+            return 0.0;
+        }
+
+        let skip = self.recent_heads.len() - window;
+        let distinct: HashSet<&(i32, i32)> = self.recent_heads.iter().skip(skip).collect();
+        if distinct.len() * 2 <= window {
+            self.reward.anti_loop_penalty
+        } else {
+            0.0
+        }
+    }
+
+    /// Penalty when the current head (the newest `recent_heads` entry) repeats one of the
+    /// `revisit_window` positions before it
+    fn revisit_penalty(&self) -> f32 {
+        let window = self.reward.revisit_window;
+        let Some(&head) = self.recent_heads.back() else {
+            return 0.0;
+        };
+        if window == 0 {
+            return 0.0;
+        }
+
+        let last = self.recent_heads.len() - 1;
+        let revisited = self
+            .recent_heads
+            .range(last.saturating_sub(window)..last)
+            .any(|&cell| cell == head);
+        if revisited {
+            self.reward.revisit_penalty
+        } else {
+            0.0
+        }
     }
 
     fn flood_fill_from_head(&self) -> u32 {
         let gs = self.grid_size;
         let head = self.snake[0];
-        let occupied: HashSet<(i32, i32)> = self.snake.iter().map(|s| (s.x, s.y)).collect();
+        let occupied: HashSet<(i32, i32)> = self
+            .snake
+            .iter()
+            .chain(self.obstacles.iter())
+            .map(|s| (s.x, s.y))
+            .collect();
         let mut visited = HashSet::new();
         let mut queue = VecDeque::new();
         visited.insert((head.x, head.y));
@@ -217,7 +683,12 @@ impl SnakeEngine {
         let tail = self.snake[self.snake.len() - 1];
 
         // Occupied = all body EXCEPT tail (tail will move, so it's reachable)
-        let mut occupied: HashSet<(i32, i32)> = self.snake.iter().map(|s| (s.x, s.y)).collect();
+        let mut occupied: HashSet<(i32, i32)> = self
+            .snake
+            .iter()
+            .chain(self.obstacles.iter())
+            .map(|s| (s.x, s.y))
+            .collect();
         occupied.remove(&(tail.x, tail.y));
 
         let mut visited = HashSet::new();
@@ -245,19 +716,105 @@ impl SnakeEngine {
         false
     }
 
-    fn spawn_food(&self) -> Point {
-        let mut rng = rand::thread_rng();
+    /// Next scripted position that is in bounds and free right now; entries covered by the
+    /// snake or an obstacle are skipped. None if no entry is usable.
+    fn next_scripted_food(&mut self) -> Option<Point> {
+        for _ in 0..self.food_script.len() {
+            let p = self.food_script[self.food_script_pos % self.food_script.len()];
+            self.food_script_pos += 1;
+            let in_bounds = p.x >= 0 && p.x < self.grid_size && p.y >= 0 && p.y < self.grid_size;
+            if in_bounds
+                && !self.snake.iter().any(|s| s.x == p.x && s.y == p.y)
+                && !self.is_obstacle(p.x, p.y)
+            {
+                return Some(p);
+            }
+        }
+        None
+    }
+
+    fn spawn_food(&mut self) -> Point {
+        if let Some(p) = self.next_scripted_food() {
+            return p;
+        }
+
         let mut free = Vec::new();
         for x in 0..self.grid_size {
             for y in 0..self.grid_size {
-                if !self.snake.iter().any(|s| s.x == x && s.y == y) {
+                if !self.snake.iter().any(|s| s.x == x && s.y == y) && !self.is_obstacle(x, y) {
                     free.push(Point { x, y });
                 }
             }
         }
+        if self.corner_food_exclusion > 0.0 && self.rng.gen::<f32>() < self.corner_food_exclusion {
+            let last = self.grid_size - 1;
+            let away: Vec<Point> = free
+                .iter()
+                .copied()
+                .filter(|p| !((p.x == 0 || p.x == last) && (p.y == 0 || p.y == last)))
+                .collect();
+            // Corners stay allowed when they're all that's left
+            if !away.is_empty() {
+                free = away;
+            }
+        }
         if free.is_empty() {
             return Point { x: 0, y: 0 };
         }
-        free[rng.gen_range(0..free.len())]
+        if self.food_open_bias <= 0.0 {
+            return free[self.rng.gen_range(0..free.len())];
+        }
+
+        // Blend uniform with the relative size of each cell's free region, so food lands in
+        // open space rather than in small trapped pockets
+        let region = self.free_region_sizes();
+        let total = free.len() as f32;
+        let weights: Vec<f32> = free
+            .iter()
+            .map(|p| {
+                let size = region[(p.y * self.grid_size + p.x) as usize] as f32;
+                (1.0 - self.food_open_bias) + self.food_open_bias * size / total
+            })
+            .collect();
+        free[WeightedIndex::new(&weights).unwrap().sample(&mut self.rng)]
+    }
+
+    /// For each cell, the size of the connected free region containing it (0 for snake and
+    /// obstacle cells)
+    fn free_region_sizes(&self) -> Vec<u32> {
+        let gs = self.grid_size;
+        let mut sizes = vec![0u32; (gs * gs) as usize];
+        let mut seen = vec![false; (gs * gs) as usize];
+        for s in self.snake.iter().chain(self.obstacles.iter()) {
+            seen[(s.y * gs + s.x) as usize] = true;
+        }
+
+        for start in 0..sizes.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut region = vec![start];
+            let mut i = 0;
+            while i < region.len() {
+                let (x, y) = (region[i] as i32 % gs, region[i] as i32 / gs);
+                for &(dx, dy) in &[(0i32, -1i32), (1, 0), (0, 1), (-1, 0)] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx >= 0 && nx < gs && ny >= 0 && ny < gs {
+                        let idx = (ny * gs + nx) as usize;
+                        if !seen[idx] {
+                            seen[idx] = true;
+                            region.push(idx);
+                        }
+                    }
+                }
+                i += 1;
+            }
+            for &idx in &region {
+                sizes[idx] = region.len() as u32;
+            }
+        }
+
+        sizes
     }
 }
@@ -0,0 +1,207 @@
+use crate::engine::SnakeEngine;
+use crate::eval::derive_seed;
+use crate::features::{current_feature_set, FeatureSet, Scratch};
+use crate::nn::Network;
+use crate::ppo::most_probable;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Fitness of one population member
+pub struct Candidate {
+    /// Mean episode return (summed rewards), the quantity ES climbs
+    pub fitness: f32,
+    pub mean_score: f32,
+}
+
+/// Gradient-free OpenAI-style evolution strategy over the weights of a greedy policy network:
+/// each generation perturbs the current weights with mirrored Gaussian noise, plays every
+/// perturbation on the same episode seeds, and steps along the noise weighted by the centered
+/// ranks of the returns. The best perturbation seen so far is kept for export.
+pub struct EvolutionStrategy {
+    /// Center of the search distribution
+    pub network: Network,
+    pub features: &'static FeatureSet,
+    /// Population size, rounded up to an even number (perturbations come in +/- pairs)
+    pub population: usize,
+    /// Perturbation standard deviation
+    pub sigma: f32,
+    pub learning_rate: f32,
+    /// Episodes each candidate plays per generation
+    pub episodes: u64,
+    best: Option<(f32, Network)>,
+    rng: ChaCha8Rng,
+}
+
+impl EvolutionStrategy {
+    /// Fresh network; reproducible when `seed` is set
+    pub fn new(num_actions: usize, population: usize, sigma: f32, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        EvolutionStrategy {
+            network: Network::new(features.size, num_actions, false, &mut rng),
+            features,
+            population: population.max(2).div_ceil(2) * 2,
+            sigma,
+            learning_rate: 0.3,
+            episodes: 3,
+            best: None,
+            rng,
+        }
+    }
+
+    /// The fittest candidate so far (the center before the first generation)
+    pub fn best(&self) -> &Network {
+        self.best.as_ref().map_or(&self.network, |(_, network)| network)
+    }
+
+    pub fn best_fitness(&self) -> Option<f32> {
+        self.best.as_ref().map(|(fitness, _)| *fitness)
+    }
+
+    /// One generation: evaluates the population on engines from `make_engine` across
+    /// `threads` threads (results don't depend on the count), then updates the center.
+    /// Returns the candidates in population order.
+    pub fn generation(
+        &mut self,
+        make_engine: &(impl Fn() -> SnakeEngine + Sync),
+        threads: usize,
+    ) -> Vec<Candidate> {
+        let center = parameters(&self.network);
+        let noise: Vec<Vec<f32>> = (0..self.population / 2)
+            .map(|_| center.iter().map(|_| gaussian(&mut self.rng)).collect())
+            .collect();
+        // Member 2i adds noise i, member 2i + 1 subtracts it
+        let candidates: Vec<Network> = (0..self.population)
+            .map(|member| {
+                let sign = if member % 2 == 0 { self.sigma } else { -self.sigma };
+                let params: Vec<f32> =
+                    center.iter().zip(&noise[member / 2]).map(|(c, e)| c + sign * e).collect();
+                with_parameters(&self.network, &params)
+            })
+            .collect();
+        let seeds: Vec<u64> = (0..self.episodes)
+            .map(|episode| derive_seed(self.rng.gen(), episode))
+            .collect();
+        let results = evaluate_population(&candidates, self.features, make_engine, &seeds, threads);
+
+        let ranks = centered_ranks(&results.iter().map(|c| c.fitness).collect::<Vec<_>>());
+        let scale = self.learning_rate / (self.population as f32 * self.sigma);
+        // Mirrored pairs: each noise vector is weighted by its + rank minus its - rank
+        let weights: Vec<f32> = ranks.chunks(2).map(|pair| pair[0] - pair[1]).collect();
+        let updated: Vec<f32> = center
+            .iter()
+            .enumerate()
+            .map(|(p, c)| {
+                let step: f32 = noise.iter().zip(&weights).map(|(e, w)| w * e[p]).sum();
+                c + scale * step
+            })
+            .collect();
+        self.network = with_parameters(&self.network, &updated);
+
+        let (fittest, top) = results
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.fitness.total_cmp(&b.1.fitness))
+            .unwrap();
+        if self.best_fitness().is_none_or(|best| top.fitness > best) {
+            self.best = Some((top.fitness, candidates[fittest].clone_weights()));
+        }
+        results
+    }
+}
+
+/// Plays every network's greedy policy once per seed on engines from `make_engine`, split
+/// across `threads` threads. Results come back in `networks` order and don't depend on the
+/// thread count.
+pub fn evaluate_population(
+    networks: &[Network],
+    features: &FeatureSet,
+    make_engine: &(impl Fn() -> SnakeEngine + Sync),
+    seeds: &[u64],
+    threads: usize,
+) -> Vec<Candidate> {
+    let evaluate = |networks: &[Network]| -> Vec<Candidate> {
+        let mut engine = make_engine();
+        let mut scratch = Scratch::default();
+        networks.iter().map(|n| play(n, features, &mut engine, &mut scratch, seeds)).collect()
+    };
+    let chunk = networks.len().div_ceil(threads.clamp(1, networks.len().max(1)));
+    std::thread::scope(|scope| {
+        let handles: Vec<_> =
+            networks.chunks(chunk).map(|part| scope.spawn(move || evaluate(part))).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Mean return and score of the greedy policy of `network` over one episode per seed
+fn play(
+    network: &Network,
+    features: &FeatureSet,
+    engine: &mut SnakeEngine,
+    scratch: &mut Scratch,
+    seeds: &[u64],
+) -> Candidate {
+    let (mut total_return, mut total_score) = (0.0, 0);
+    let mut state = Vec::new();
+    for &seed in seeds {
+        engine.seed(seed);
+        engine.reset();
+        loop {
+            features.extract(engine, &mut state, scratch);
+            let (reward, done) = engine.step(most_probable(network, &state));
+            total_return += reward;
+            if done {
+                break;
+            }
+        }
+        total_score += engine.score;
+    }
+    Candidate {
+        fitness: total_return / seeds.len() as f32,
+        mean_score: total_score as f32 / seeds.len() as f32,
+    }
+}
+
+/// Fitness ranks scaled to [-0.5, 0.5], so the update ignores the returns' scale and outliers
+pub fn centered_ranks(fitness: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..fitness.len()).collect();
+    order.sort_by(|&a, &b| fitness[a].total_cmp(&fitness[b]));
+    let mut ranks = vec![0.0; fitness.len()];
+    let top = (fitness.len() - 1).max(1) as f32;
+    for (rank, &i) in order.iter().enumerate() {
+        ranks[i] = rank as f32 / top - 0.5;
+    }
+    ranks
+}
+
+/// Every layer's weights then biases, in layer order
+pub fn parameters(network: &Network) -> Vec<f32> {
+    (0..network.num_layers())
+        .flat_map(|i| {
+            let (weights, biases, _, _) = network.layer_info(i);
+            weights.iter().chain(biases).copied().collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// A network shaped like `template` with the flat `params` (as laid out by `parameters`)
+pub fn with_parameters(template: &Network, params: &[f32]) -> Network {
+    let mut offset = 0;
+    let layers = (0..template.num_layers())
+        .map(|i| {
+            let (weights, biases, _, _) = template.layer_info(i);
+            let (w, b) = (weights.len(), biases.len());
+            let layer = &params[offset..offset + w + b];
+            offset += w + b;
+            (layer[..w].to_vec(), layer[w..].to_vec())
+        })
+        .collect();
+    Network::from_layers(layers).unwrap()
+}
+
+/// Standard normal sample (Box-Muller)
+pub fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1 = rng.gen::<f32>().max(1e-12);
+    let u2 = rng.gen::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
@@ -0,0 +1,194 @@
+use crate::agent::DQNAgent;
+use crate::engine::SnakeEngine;
+use crate::features::{current_feature_set, FeatureSet, Scratch};
+use crate::heuristic::greedy_food_action;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// How evaluation episodes pick actions
+pub struct EvalOptions {
+    pub episodes: u64,
+    /// Random-action rate. Values > 0 break deterministic loops the greedy policy can get
+    /// stuck in, at the cost of making the run non-deterministic.
+    pub epsilon: f32,
+    /// When set, sample actions from softmax(Q / T) instead of acting greedily
+    pub temperature: Option<f32>,
+    /// Seeds the environment (food, obstacles, sticky actions) and exploration: episode i plays
+    /// on `seed + i` (see `episode_seeds`). With one greedy episode this replays a training
+    /// episode logged by `--log-episode-seeds`.
+    pub seed: Option<u64>,
+    /// Episodes are split across this many threads when above 1, with the same scores as one
+    /// thread
+    pub threads: usize,
+}
+
+/// Per-episode scores of an evaluation run
+pub struct EvalStats {
+    pub scores: Vec<i32>,
+}
+
+impl EvalStats {
+    pub fn mean(&self) -> f32 {
+        if self.scores.is_empty() {
+            return 0.0;
+        }
+        self.scores.iter().sum::<i32>() as f32 / self.scores.len() as f32
+    }
+
+    pub fn max(&self) -> i32 {
+        self.scores.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn min(&self) -> i32 {
+        self.scores.iter().copied().min().unwrap_or(0)
+    }
+}
+
+/// Plays one episode to completion with `policy(engine, features)`, extracting `feature_set`
+/// each step, and returns the score
+pub fn run_episode(
+    engine: &mut SnakeEngine,
+    feature_set: &FeatureSet,
+    scratch: &mut Scratch,
+    policy: &mut impl FnMut(&SnakeEngine, &[f32]) -> usize,
+) -> i32 {
+    let mut features = Vec::new();
+    engine.reset();
+    loop {
+        feature_set.extract(engine, &mut features, scratch);
+        let action = policy(engine, &features);
+        let (_, done) = engine.step(action);
+        if done {
+            return engine.score;
+        }
+    }
+}
+
+/// Samples an index from softmax(q / temperature). As T → 0 this approaches argmax, as T grows
+/// it approaches uniform.
+pub fn softmax_sample(q: &[f32], temperature: f32, rng: &mut impl Rng) -> usize {
+    let t = temperature.max(1e-6);
+    let max_q = q.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = q.iter().map(|&v| ((v - max_q) / t).exp()).collect();
+    let total: f32 = weights.iter().sum();
+
+    let mut pick = rng.gen::<f32>() * total;
+    for (i, w) in weights.iter().enumerate() {
+        if pick < *w {
+            return i;
+        }
+        pick -= w;
+    }
+    weights.len() - 1
+}
+
+/// Seed number `index` of a stream derived from `base` (splitmix64), for per-episode seeds
+pub fn derive_seed(base: u64, index: u64) -> u64 {
+    let mut z = base.wrapping_add(index.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// One episode per seed on engines from `make_engine`, each freshly seeded with exploration
+/// drawn from the same seed, split across `options.threads` threads. Scores come back in
+/// `seeds` order, so they are identical for any thread count.
+pub fn evaluate_seeds(
+    agent: &DQNAgent,
+    make_engine: &(impl Fn() -> SnakeEngine + Sync),
+    options: &EvalOptions,
+    seeds: &[u64],
+) -> Vec<i32> {
+    let play = |seeds: &[u64]| -> Vec<i32> {
+        let mut engine = make_engine();
+        let mut scratch = Scratch::default();
+        seeds
+            .iter()
+            .map(|&seed| {
+                engine.seed(seed);
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                let mut policy = |_: &SnakeEngine, features: &[f32]| {
+                    eval_action(agent, options, features, &mut rng)
+                };
+                run_episode(&mut engine, agent.features, &mut scratch, &mut policy)
+            })
+            .collect()
+    };
+
+    let threads = options.threads.clamp(1, seeds.len().max(1));
+    if threads == 1 {
+        return play(seeds);
+    }
+    let chunk = seeds.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = seeds
+            .chunks(chunk)
+            .map(|part| scope.spawn(move || play(part)))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Action under `options`: random with probability epsilon, else softmax-sampled or greedy
+fn eval_action(
+    agent: &DQNAgent,
+    options: &EvalOptions,
+    features: &[f32],
+    rng: &mut impl Rng,
+) -> usize {
+    if options.epsilon > 0.0 && rng.gen::<f32>() < options.epsilon {
+        rng.gen_range(0..agent.num_actions())
+    } else if let Some(t) = options.temperature {
+        softmax_sample(&agent.q_values(features), t, rng)
+    } else {
+        agent.act_greedy(features)
+    }
+}
+
+/// Plays `options.episodes` episodes of `agent` on an engine from `make_engine`, which should
+/// carry the environment (obstacles, dynamics, food script) the agent is meant to be scored in
+pub fn evaluate(
+    agent: &DQNAgent,
+    make_engine: &(impl Fn() -> SnakeEngine + Sync),
+    options: &EvalOptions,
+) -> EvalStats {
+    EvalStats {
+        scores: evaluate_seeds(agent, make_engine, options, &episode_seeds(options)),
+    }
+}
+
+/// Environment seed of each episode of `options`: `seed + i` for episode i, so the first one
+/// is the seed itself, from a random base when unseeded
+pub fn episode_seeds(options: &EvalOptions) -> Vec<u64> {
+    let base = options.seed.unwrap_or_else(rand::random);
+    (0..options.episodes).map(|i| base.wrapping_add(i)).collect()
+}
+
+/// Same suite as `evaluate` (episodes, epsilon, seeds) played by the greedy-toward-food
+/// heuristic, as a baseline for model scores, on an engine from `make_engine`
+pub fn evaluate_baseline(
+    make_engine: &impl Fn() -> SnakeEngine,
+    options: &EvalOptions,
+) -> EvalStats {
+    let mut engine = make_engine();
+    let num_actions = engine.num_actions();
+    let mut scratch = Scratch::default();
+
+    EvalStats {
+        scores: episode_seeds(options)
+            .into_iter()
+            .map(|seed| {
+                engine.seed(seed);
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                let mut policy = |engine: &SnakeEngine, _: &[f32]| {
+                    if options.epsilon > 0.0 && rng.gen::<f32>() < options.epsilon {
+                        rng.gen_range(0..num_actions)
+                    } else {
+                        greedy_food_action(engine)
+                    }
+                };
+                run_episode(&mut engine, current_feature_set(), &mut scratch, &mut policy)
+            })
+            .collect(),
+    }
+}
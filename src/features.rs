@@ -1,5 +1,79 @@
 use crate::engine::{Direction, SnakeEngine};
-use std::collections::{HashSet, VecDeque};
+use crate::nn::INPUT_SIZE;
+use std::collections::VecDeque;
+
+/// Name of each feature, in the order `extract_features` emits them
+pub const FEATURE_NAMES: [&str; INPUT_SIZE] = [
+    "danger_straight", "danger_right", "danger_left",
+    "danger_straight2", "danger_right2", "danger_left2",
+    "ray_straight", "ray_right", "ray_left",
+    "dir_up", "dir_right", "dir_down", "dir_left",
+    "food_up", "food_right", "food_down", "food_left",
+    "wall_up", "wall_right", "wall_down", "wall_left",
+    "snake_length",
+    "flood_ratio",
+    "flood_straight", "flood_right", "flood_left",
+    "tail_dx", "tail_dy",
+    "tail_straight", "tail_right", "tail_left",
+    "tail_path",
+    "open_straight", "open_right", "open_left",
+    "free_ratio",
+    "phase_early", "phase_mid", "phase_late",
+];
+
+/// A versioned feature layout: exports and checkpoints record the version they were trained
+/// with, so a model is always fed the features it learned on
+pub struct FeatureSet {
+    pub version: u32,
+    pub size: usize,
+    extract: fn(&SnakeEngine, &mut Vec<f32>, &mut Scratch),
+}
+
+impl FeatureSet {
+    /// Writes this layout's features for `engine` into `buf` (cleared first)
+    pub fn extract(&self, engine: &SnakeEngine, buf: &mut Vec<f32>, scratch: &mut Scratch) {
+        (self.extract)(engine, buf, scratch)
+    }
+}
+
+/// Every supported layout, oldest first; the last entry is the one new models train with. Any
+/// change to the features (added, removed, reordered or redefined) gets a new entry instead of
+/// editing an old one.
+pub static FEATURE_SETS: [FeatureSet; 3] = [
+    FeatureSet { version: 1, size: 28, extract: extract_v1_into },
+    FeatureSet { version: 2, size: 35, extract: extract_v2_into },
+    FeatureSet { version: 3, size: INPUT_SIZE, extract: extract_features_into },
+];
+
+pub fn current_feature_set() -> &'static FeatureSet {
+    &FEATURE_SETS[FEATURE_SETS.len() - 1]
+}
+
+/// Feature set for a model with `input_size` inputs tagged with `version`. Untagged models
+/// (saved before versioning) are matched by input size.
+pub fn feature_set(version: Option<u32>, input_size: usize) -> Result<&'static FeatureSet, String> {
+    let set = match version {
+        Some(v) => FEATURE_SETS.iter().find(|s| s.version == v).ok_or_else(|| {
+            format!(
+                "feature version {} is not supported (known versions: 1-{})",
+                v,
+                current_feature_set().version
+            )
+        })?,
+        None => FEATURE_SETS
+            .iter()
+            .rev()
+            .find(|s| s.size == input_size)
+            .ok_or_else(|| format!("no known feature set has {} inputs", input_size))?,
+    };
+    if set.size != input_size {
+        return Err(format!(
+            "feature version {} has {} inputs, but the network expects {}",
+            set.version, set.size, input_size
+        ));
+    }
+    Ok(set)
+}
 
 fn relative_dirs(dir: Direction) -> (Direction, Direction, Direction) {
     match dir {
@@ -7,6 +81,10 @@ fn relative_dirs(dir: Direction) -> (Direction, Direction, Direction) {
         Direction::Right => (Direction::Right, Direction::Down, Direction::Up),
         Direction::Down => (Direction::Down, Direction::Left, Direction::Right),
         Direction::Left => (Direction::Left, Direction::Up, Direction::Down),
+        Direction::UpRight => (Direction::UpRight, Direction::DownRight, Direction::UpLeft),
+        Direction::DownRight => (Direction::DownRight, Direction::DownLeft, Direction::UpRight),
+        Direction::DownLeft => (Direction::DownLeft, Direction::UpLeft, Direction::DownRight),
+        Direction::UpLeft => (Direction::UpLeft, Direction::UpRight, Direction::DownLeft),
     }
 }
 
@@ -14,10 +92,11 @@ fn is_collision(x: i32, y: i32, engine: &SnakeEngine) -> bool {
     if x < 0 || x >= engine.grid_size || y < 0 || y >= engine.grid_size {
         return true;
     }
-    engine.snake.iter().any(|s| s.x == x && s.y == y)
+    engine.snake.iter().any(|s| s.x == x && s.y == y) || engine.is_obstacle(x, y)
 }
 
-/// Ray-cast: distance to first obstacle (wall or body) in given direction, normalized by grid size
+/// Ray-cast: distance to first obstacle (wall, body or obstacle cell) in given direction,
+/// normalized by grid size
 fn ray_distance(engine: &SnakeEngine, dx: i32, dy: i32) -> f32 {
     let head = engine.snake[0];
     let gs = engine.grid_size as f32;
@@ -27,6 +106,7 @@ fn ray_distance(engine: &SnakeEngine, dx: i32, dy: i32) -> f32 {
 
     while x >= 0 && x < engine.grid_size && y >= 0 && y < engine.grid_size
         && !engine.snake.iter().any(|s| s.x == x && s.y == y)
+        && !engine.is_obstacle(x, y)
     {
         x += dx;
         y += dy;
@@ -36,36 +116,72 @@ fn ray_distance(engine: &SnakeEngine, dx: i32, dy: i32) -> f32 {
     dist as f32 / gs
 }
 
-/// Flood fill from a starting point, reusing a pre-built occupied set
-fn flood_fill_from(
-    start_x: i32,
-    start_y: i32,
-    gs: i32,
-    occupied: &HashSet<(i32, i32)>,
-) -> u32 {
+/// Reusable buffers for `extract_features_into`, so the hot loop doesn't allocate per call
+#[derive(Default)]
+pub struct Scratch {
+    // Occupancy grid (snake and obstacles), row-major [y * gs + x]
+    occupied: Vec<bool>,
+    // Visit stamps: a cell is visited in the current fill when it equals `stamp`
+    visited: Vec<u32>,
+    stamp: u32,
+    queue: VecDeque<(i32, i32)>,
+}
+
+impl Scratch {
+    fn load(&mut self, engine: &SnakeEngine) {
+        let cells = (engine.grid_size * engine.grid_size) as usize;
+        if self.occupied.len() != cells {
+            self.occupied = vec![false; cells];
+            self.visited = vec![0; cells];
+            self.stamp = 0;
+        } else {
+            self.occupied.fill(false);
+        }
+        for s in engine.snake.iter().chain(engine.obstacles.iter()) {
+            self.occupied[(s.y * engine.grid_size + s.x) as usize] = true;
+        }
+    }
+}
+
+/// Flood fill from a starting point, reusing the occupancy grid loaded into `scratch`.
+///
+/// Convention for every flood feature: the count includes the start cell plus every free cell
+/// 4-connected to it, and is 0 when the start cell is blocked (wall, body or obstacle). The
+/// directional floods start at the cell next to the head, so a move into a one-cell dead end
+/// scores 1; the global flood counts the free cells reachable from the head, head excluded.
+/// Counting the start cell keeps a one-cell dead end (1) apart from a blocked move (0). The
+/// convention is fixed rather than a flag because exported models record only their feature
+/// version, so a model loaded under the other convention would silently get different inputs.
+fn flood_fill_from(start_x: i32, start_y: i32, gs: i32, scratch: &mut Scratch) -> u32 {
     if start_x < 0 || start_x >= gs || start_y < 0 || start_y >= gs
-        || occupied.contains(&(start_x, start_y))
+        || scratch.occupied[(start_y * gs + start_x) as usize]
     {
         return 0;
     }
 
-    let mut visited = HashSet::new();
-    let mut queue = VecDeque::new();
-    visited.insert((start_x, start_y));
-    queue.push_back((start_x, start_y));
+    scratch.stamp = scratch.stamp.wrapping_add(1);
+    if scratch.stamp == 0 {
+        scratch.visited.fill(0);
+        scratch.stamp = 1;
+    }
+    let stamp = scratch.stamp;
+
+    scratch.queue.clear();
+    scratch.visited[(start_y * gs + start_x) as usize] = stamp;
+    scratch.queue.push_back((start_x, start_y));
     let mut count = 0u32;
 
-    while let Some((x, y)) = queue.pop_front() {
+    while let Some((x, y)) = scratch.queue.pop_front() {
         count += 1;
         for &(dx, dy) in &[(0i32, -1i32), (1, 0), (0, 1), (-1, 0)] {
             let nx = x + dx;
             let ny = y + dy;
-            if nx >= 0 && nx < gs && ny >= 0 && ny < gs
-                && !occupied.contains(&(nx, ny))
-                && !visited.contains(&(nx, ny))
-            {
-                visited.insert((nx, ny));
-                queue.push_back((nx, ny));
+            if nx >= 0 && nx < gs && ny >= 0 && ny < gs {
+                let idx = (ny * gs + nx) as usize;
+                if !scratch.occupied[idx] && scratch.visited[idx] != stamp {
+                    scratch.visited[idx] = stamp;
+                    scratch.queue.push_back((nx, ny));
+                }
             }
         }
     }
@@ -73,8 +189,40 @@ fn flood_fill_from(
     count
 }
 
-/// 28 features: original 22 + ray-cast (3) + directional flood fill (3)
+/// Version 1: the first 28 features of the current layout, before tail adjacency, tail path and
+/// most-open direction were added. Its global flood started from the occupied head cell, so
+/// `flood_ratio` was always 0.
+fn extract_v1_into(engine: &SnakeEngine, buf: &mut Vec<f32>, scratch: &mut Scratch) {
+    extract_features_into(engine, buf, scratch);
+    buf.truncate(28);
+    buf[22] = 0.0; // flood_ratio
+}
+
+/// Version 2: the first 35 features of the current layout, before the congestion features
+fn extract_v2_into(engine: &SnakeEngine, buf: &mut Vec<f32>, scratch: &mut Scratch) {
+    extract_features_into(engine, buf, scratch);
+    buf.truncate(35);
+}
+
+/// 39 features: original 22 + ray-cast (3) + directional flood fill (3) + tail adjacency (3)
+/// + body length (1) + most-open direction (3) + congestion (4)
 pub fn extract_features(engine: &SnakeEngine) -> Vec<f32> {
+    let mut buf = Vec::with_capacity(INPUT_SIZE);
+    extract_features_into(engine, &mut buf, &mut Scratch::default());
+    buf
+}
+
+/// Same as `extract_features`, but writes into `buf` (cleared first) and reuses `scratch`
+/// across calls
+pub fn extract_features_into(engine: &SnakeEngine, buf: &mut Vec<f32>, scratch: &mut Scratch) {
+    // The engine never produces an empty snake; if one is set up by hand, emit all-zero
+    // features instead of panicking on `snake[0]`
+    if engine.snake.is_empty() {
+        buf.clear();
+        buf.resize(INPUT_SIZE, 0.0);
+        return;
+    }
+
     let head = engine.snake[0];
     let tail = engine.snake[engine.snake.len() - 1];
     let dir = engine.direction;
@@ -101,11 +249,11 @@ pub fn extract_features(engine: &SnakeEngine) -> Vec<f32> {
     let ray_right = ray_distance(engine, rdx, rdy);
     let ray_left = ray_distance(engine, ldx, ldy);
 
-    // Direction one-hot
-    let dir_up = if dir == Direction::Up { 1.0 } else { 0.0 };
-    let dir_right_f = if dir == Direction::Right { 1.0 } else { 0.0 };
-    let dir_down = if dir == Direction::Down { 1.0 } else { 0.0 };
-    let dir_left_f = if dir == Direction::Left { 1.0 } else { 0.0 };
+    // Direction one-hot (a diagonal heading sets both of its components)
+    let dir_up = if sdy < 0 { 1.0 } else { 0.0 };
+    let dir_right_f = if sdx > 0 { 1.0 } else { 0.0 };
+    let dir_down = if sdy > 0 { 1.0 } else { 0.0 };
+    let dir_left_f = if sdx < 0 { 1.0 } else { 0.0 };
 
     // Food relative
     let food_up = if engine.food.y < head.y { 1.0 } else { 0.0 };
@@ -122,28 +270,64 @@ pub fn extract_features(engine: &SnakeEngine) -> Vec<f32> {
     // Snake length (normalized)
     let snake_length = engine.snake.len() as f32 / (gsf * gsf);
 
-    // Build occupied set once for all flood fills
-    let occupied: HashSet<(i32, i32)> = engine.snake.iter().map(|s| (s.x, s.y)).collect();
+    // Build occupancy grid once for all flood fills
+    scratch.load(engine);
 
-    // Global flood fill ratio
-    let total_free = (gs * gs) as f32 - engine.snake.len() as f32;
-    let reachable = flood_fill_from(head.x, head.y, gs, &occupied) as f32;
+    // Global flood fill ratio: the head's cell is occupied, so fill from it as if it were free
+    // and drop it from the count
+    let total_free = (gs * gs) as f32 - engine.snake.len() as f32 - engine.obstacles.len() as f32;
+    let head_idx = (head.y * gs + head.x) as usize;
+    scratch.occupied[head_idx] = false;
+    let reachable = (flood_fill_from(head.x, head.y, gs, scratch) - 1) as f32;
+    scratch.occupied[head_idx] = true;
     let flood_ratio = if total_free > 0.0 { reachable / total_free } else { 0.0 };
 
     // Directional flood fill: reachable space from cell in each relative direction
-    let flood_straight = flood_fill_from(head.x + sdx, head.y + sdy, gs, &occupied) as f32;
-    let flood_right_f = flood_fill_from(head.x + rdx, head.y + rdy, gs, &occupied) as f32;
-    let flood_left_f = flood_fill_from(head.x + ldx, head.y + ldy, gs, &occupied) as f32;
+    let flood_straight = flood_fill_from(head.x + sdx, head.y + sdy, gs, scratch) as f32;
+    let flood_right_f = flood_fill_from(head.x + rdx, head.y + rdy, gs, scratch) as f32;
+    let flood_left_f = flood_fill_from(head.x + ldx, head.y + ldy, gs, scratch) as f32;
     let flood_max = total_free.max(1.0);
     let flood_straight_n = flood_straight / flood_max;
     let flood_right_n = flood_right_f / flood_max;
     let flood_left_n = flood_left_f / flood_max;
 
+    // One-hot of the relative direction leading to the most reachable space (ties → first of
+    // straight/right/left; all zero when every direction is blocked)
+    let (mut open_straight, mut open_right, mut open_left) = (0.0, 0.0, 0.0);
+    let most_open = flood_straight.max(flood_right_f).max(flood_left_f);
+    if most_open > 0.0 {
+        if flood_straight == most_open {
+            open_straight = 1.0;
+        } else if flood_right_f == most_open {
+            open_right = 1.0;
+        } else {
+            open_left = 1.0;
+        }
+    }
+
     // Tail relative direction (sign)
     let tail_dx = (tail.x - head.x).signum() as f32;
     let tail_dy = (tail.y - head.y).signum() as f32;
 
-    vec![
+    // Tail adjacency: tail sits exactly one cell away in each relative direction
+    let tail_straight = if head.x + sdx == tail.x && head.y + sdy == tail.y { 1.0 } else { 0.0 };
+    let tail_right = if head.x + rdx == tail.x && head.y + rdy == tail.y { 1.0 } else { 0.0 };
+    let tail_left = if head.x + ldx == tail.x && head.y + ldy == tail.y { 1.0 } else { 0.0 };
+
+    // Path distance head → tail along the body (normalized)
+    let tail_path = (engine.snake.len() - 1) as f32 / (gsf * gsf);
+
+    // Congestion: share of the board still free, and a one-hot game phase by occupancy (early
+    // matches the 15% threshold where the safety shaping kicks in)
+    let area = gsf * gsf;
+    let free_ratio = total_free / area;
+    let occupancy = engine.snake.len() as f32 / area;
+    let phase_early = if occupancy < 0.15 { 1.0 } else { 0.0 };
+    let phase_mid = if (0.15..0.4).contains(&occupancy) { 1.0 } else { 0.0 };
+    let phase_late = if occupancy >= 0.4 { 1.0 } else { 0.0 };
+
+    buf.clear();
+    buf.extend_from_slice(&[
         danger_straight, danger_right, danger_left,
         danger_straight2, danger_right2, danger_left2,
         ray_straight, ray_right, ray_left,
@@ -154,5 +338,10 @@ pub fn extract_features(engine: &SnakeEngine) -> Vec<f32> {
         flood_ratio,
         flood_straight_n, flood_right_n, flood_left_n,
         tail_dx, tail_dy,
-    ]
+        tail_straight, tail_right, tail_left,
+        tail_path,
+        open_straight, open_right, open_left,
+        free_ratio,
+        phase_early, phase_mid, phase_late,
+    ]);
 }
@@ -0,0 +1,107 @@
+use crate::engine::SnakeEngine;
+use crate::es::{evaluate_population, gaussian, parameters, with_parameters, Candidate};
+use crate::eval::derive_seed;
+use crate::features::{current_feature_set, FeatureSet};
+use crate::nn::Network;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Fittest members copied unchanged into the next generation
+const ELITES: usize = 2;
+/// Members drawn per tournament; the fittest of them becomes a parent
+const TOURNAMENT: usize = 3;
+/// Chance that a child's weight is mutated
+const MUTATION_RATE: f32 = 0.1;
+
+/// Neuroevolution: a population of greedy policy networks evolved by tournament selection,
+/// uniform crossover and Gaussian mutation of their flat weight vectors. Fitness is the mean
+/// score over episode seeds shared by the whole generation.
+pub struct GeneticAlgorithm {
+    pub population: Vec<Network>,
+    pub features: &'static FeatureSet,
+    /// Episodes each member plays per generation
+    pub episodes: u64,
+    /// Std dev of a mutated weight's Gaussian noise
+    pub mutation_sigma: f32,
+    best: Option<(f32, Network)>,
+    rng: ChaCha8Rng,
+}
+
+impl GeneticAlgorithm {
+    /// `size` freshly initialized networks; reproducible when `seed` is set
+    pub fn new(num_actions: usize, size: usize, mutation_sigma: f32, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        GeneticAlgorithm {
+            population: (0..size.max(ELITES + 1))
+                .map(|_| Network::new(features.size, num_actions, false, &mut rng))
+                .collect(),
+            features,
+            episodes: 3,
+            mutation_sigma,
+            best: None,
+            rng,
+        }
+    }
+
+    /// The fittest member seen so far (the first member before any generation)
+    pub fn best(&self) -> &Network {
+        self.best.as_ref().map_or(&self.population[0], |(_, network)| network)
+    }
+
+    pub fn best_fitness(&self) -> Option<f32> {
+        self.best.as_ref().map(|(fitness, _)| *fitness)
+    }
+
+    /// One generation: evaluates the population on engines from `make_engine` across
+    /// `threads` threads, then breeds its replacement. Returns the evaluated members in
+    /// population order.
+    pub fn generation(
+        &mut self,
+        make_engine: &(impl Fn() -> SnakeEngine + Sync),
+        threads: usize,
+    ) -> Vec<Candidate> {
+        let seeds: Vec<u64> = (0..self.episodes)
+            .map(|episode| derive_seed(self.rng.gen(), episode))
+            .collect();
+        let results =
+            evaluate_population(&self.population, self.features, make_engine, &seeds, threads);
+        let fitness: Vec<f32> = results.iter().map(|c| c.mean_score).collect();
+
+        let mut ranked: Vec<usize> = (0..fitness.len()).collect();
+        ranked.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+        if self.best_fitness().is_none_or(|best| fitness[ranked[0]] > best) {
+            self.best = Some((fitness[ranked[0]], self.population[ranked[0]].clone_weights()));
+        }
+
+        let genomes: Vec<Vec<f32>> = self.population.iter().map(parameters).collect();
+        let mut next: Vec<Network> =
+            ranked[..ELITES].iter().map(|&i| self.population[i].clone_weights()).collect();
+        while next.len() < self.population.len() {
+            let (a, b) = (self.tournament(&fitness), self.tournament(&fitness));
+            let child: Vec<f32> = genomes[a]
+                .iter()
+                .zip(&genomes[b])
+                .map(|(&x, &y)| {
+                    let gene = if self.rng.gen::<bool>() { x } else { y };
+                    if self.rng.gen::<f32>() < MUTATION_RATE {
+                        gene + self.mutation_sigma * gaussian(&mut self.rng)
+                    } else {
+                        gene
+                    }
+                })
+                .collect();
+            next.push(with_parameters(&self.population[0], &child));
+        }
+        self.population = next;
+        results
+    }
+
+    /// Index of the fittest of `TOURNAMENT` members drawn uniformly (with replacement)
+    fn tournament(&mut self, fitness: &[f32]) -> usize {
+        (0..TOURNAMENT)
+            .map(|_| self.rng.gen_range(0..fitness.len()))
+            .reduce(|best, i| if fitness[i] > fitness[best] { i } else { best })
+            .unwrap()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::agent::DQNAgent;
+use crate::engine::{SnakeEngine, ACTIONS};
+use crate::eval::run_episode;
+use crate::features::{FeatureSet, Scratch};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Share of random moves while collecting `heuristic_dataset`, so it covers states the
+/// heuristic alone wouldn't reach
+const DATASET_RANDOM_MOVES: f32 = 0.2;
+
+/// Baseline policy: the non-reversing move that gets closest to the food (Manhattan) without
+/// dying on the next step. Falls back to going straight when every move is fatal.
+pub fn greedy_food_action(engine: &SnakeEngine) -> usize {
+    let head = engine.snake[0];
+    let mut best: Option<(i32, usize)> = None;
+
+    for (action, dir) in ACTIONS[..engine.num_actions()].iter().enumerate() {
+        if dir.opposite() == engine.direction {
+            continue;
+        }
+        let (dx, dy) = dir.delta();
+        let (x, y) = (head.x + dx, head.y + dy);
+        let blocked = x < 0
+            || x >= engine.grid_size
+            || y < 0
+            || y >= engine.grid_size
+            || engine.is_obstacle(x, y)
+            || engine.snake.iter().any(|s| s.x == x && s.y == y);
+        if blocked {
+            continue;
+        }
+
+        let dist = (x - engine.food.x).abs() + (y - engine.food.y).abs();
+        if best.is_none_or(|(d, _)| dist < d) {
+            best = Some((dist, action));
+        }
+    }
+
+    best.map_or_else(
+        || ACTIONS.iter().position(|&d| d == engine.direction).unwrap_or(0),
+        |(_, action)| action,
+    )
+}
+
+/// `samples` (features, heuristic action) pairs for imitation pretraining, from episodes that
+/// mostly follow the heuristic. Reproducible for a given `seed`.
+pub fn heuristic_dataset(
+    grid_size: i32,
+    diagonals: bool,
+    feature_set: &FeatureSet,
+    samples: usize,
+    seed: u64,
+) -> (Vec<Vec<f32>>, Vec<usize>) {
+    let mut engine = SnakeEngine::new(grid_size);
+    engine.seed(seed);
+    engine.diagonals = diagonals;
+    let num_actions = engine.num_actions();
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut scratch = Scratch::default();
+    let mut states = Vec::with_capacity(samples);
+    let mut labels = Vec::with_capacity(samples);
+    while states.len() < samples {
+        run_episode(&mut engine, feature_set, &mut scratch, &mut |engine, features| {
+            let action = greedy_food_action(engine);
+            if states.len() < samples {
+                states.push(features.to_vec());
+                labels.push(action);
+            }
+            if rng.gen::<f32>() < DATASET_RANDOM_MOVES {
+                rng.gen_range(0..num_actions)
+            } else {
+                action
+            }
+        });
+    }
+    (states, labels)
+}
+
+/// Share of `samples` states, from heuristic-led episodes, where the agent's greedy action
+/// matches `greedy_food_action`: near chance for an untrained network, rising as it learns to
+/// avoid walls and itself. The same `seed` gives the same states.
+pub fn heuristic_agreement(agent: &DQNAgent, grid_size: i32, samples: usize, seed: u64) -> f32 {
+    let diagonals = agent.num_actions() == ACTIONS.len();
+    let (states, labels) = heuristic_dataset(grid_size, diagonals, agent.features, samples, seed);
+    let agree = states.iter().zip(&labels).filter(|(s, &a)| agent.act_greedy(s) == a).count();
+    agree as f32 / states.len().max(1) as f32
+}
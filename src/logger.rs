@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// One logfmt line: `key=value` pairs separated by spaces, with values containing spaces, quotes
+/// or `=` double-quoted
+pub fn logfmt(fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            if value.is_empty() || value.contains([' ', '"', '=']) {
+                format!("{}={:?}", key, value)
+            } else {
+                format!("{}={}", key, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Records of the JSONL files `paths`, in order
+pub fn read_records(paths: &[String]) -> Result<Vec<serde_json::Value>, String> {
+    let mut records = Vec::new();
+    for path in paths {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let record =
+                serde_json::from_str(line).map_err(|e| format!("{}:{}: {}", path, n + 1, e))?;
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// JSONL metrics log, one record per line. With rotation enabled, the current file is closed
+/// and a new numbered one (`log.1.jsonl`, `log.2.jsonl`, ...) started once it reaches
+/// `rotate_bytes` bytes or `rotate_episodes` records.
+pub struct MetricsLog {
+    path: String,
+    file: BufWriter<File>,
+    index: u32,
+    bytes: u64,
+    records: u64,
+    pub rotate_bytes: Option<u64>,
+    pub rotate_episodes: Option<u64>,
+}
+
+impl MetricsLog {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(MetricsLog {
+            path: path.to_string(),
+            file: BufWriter::new(File::create(path)?),
+            index: 0,
+            bytes: 0,
+            records: 0,
+            rotate_bytes: None,
+            rotate_episodes: None,
+        })
+    }
+
+    /// File currently being written
+    pub fn current_path(&self) -> String {
+        self.numbered_path(self.index)
+    }
+
+    /// Every file written so far, oldest first
+    pub fn paths(&self) -> Vec<String> {
+        (0..=self.index).map(|i| self.numbered_path(i)).collect()
+    }
+
+    fn numbered_path(&self, index: u32) -> String {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let p = Path::new(&self.path);
+        let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("log");
+        let name = match p.extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{}.{}.{}", stem, index, ext),
+            None => format!("{}.{}", stem, index),
+        };
+        p.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    pub fn write(&mut self, record: &serde_json::Value) -> std::io::Result<()> {
+        let line = record.to_string();
+        writeln!(self.file, "{}", line)?;
+        self.bytes += line.len() as u64 + 1;
+        self.records += 1;
+
+        let full = self.rotate_bytes.is_some_and(|b| self.bytes >= b)
+            || self.rotate_episodes.is_some_and(|n| self.records >= n);
+        if full {
+            self.file.flush()?;
+            self.index += 1;
+            self.file = BufWriter::new(File::create(self.current_path())?);
+            self.bytes = 0;
+            self.records = 0;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
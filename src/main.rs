@@ -1,24 +1,204 @@
+mod a2c;
 mod agent;
+mod curriculum;
+mod distill;
+mod distributional;
 mod engine;
+mod es;
+mod eval;
 mod features;
+mod ga;
+mod heuristic;
+mod logger;
+mod model_io;
 mod nn;
+mod normalize;
+mod ppo;
+mod reinforce;
+mod replay;
+mod report;
+mod sac;
+mod self_test;
+mod tabular;
+mod tools;
 
-use agent::{DQNAgent, Experience};
-use engine::SnakeEngine;
-use features::extract_features;
+use agent::{DQNAgent, Experience, OverestimationStats, TargetRule};
+use curriculum::{Curriculum, CurriculumMetric};
+use distributional::{ReturnDistribution, Support};
+use engine::{EnvMode, Point, Potential, RewardConfig, SnakeEngine, ACTIONS, ORTHOGONAL_ACTIONS};
+use eval::{EvalOptions, EvalStats};
+use features::{feature_set, FeatureSet, Scratch};
+use logger::MetricsLog;
+use model_io::{
+    export_model, export_npz, import_env_mode, load_checkpoint, load_model, prune_checkpoints,
+    save_checkpoint,
+};
+use normalize::ObsNormalizer;
 use std::collections::{HashMap, VecDeque};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 const GRID_SIZE: i32 = 20;
 
+/// States timed by `--latency-report`
+const LATENCY_SAMPLES: usize = 10_000;
+
+/// Default quantiles per action for `--qr-dqn`
+const QR_QUANTILES: usize = 51;
+
+/// Options `--agent rainbow` sets unless given explicitly: C51 with a dueling head, NoisyNet
+/// exploration, 3-step returns and TD-error prioritized replay, on top of the default Double DQN
+const RAINBOW_PRESET: [(&str, &str); 5] = [
+    ("--c51", ""),
+    ("--dueling", ""),
+    ("--noisy-nets", ""),
+    ("--n-step", "3"),
+    ("--lazy-priority-recompute", "1000"),
+];
+
+/// Options `--agent sarsa` sets unless given explicitly: SARSA targets on 1-step transitions,
+/// sampled only from the most recent ones so training stays close to on-policy
+const SARSA_PRESET: [(&str, &str); 3] = [
+    ("--target-rule", "sarsa"),
+    ("--n-step", "1"),
+    ("--max-sample-age", "256"),
+];
+
+/// States the distilled tree is fit on
+const DISTILL_SAMPLES: usize = 5_000;
+
+/// Most recent transitions summarized by `--action-histogram-every`
+const ACTION_HISTOGRAM_WINDOW: usize = 5_000;
+
+/// States compared by `--heuristic-agreement-every`, the same ones every time
+const AGREEMENT_STATES: usize = 1_000;
+
+/// (state, heuristic action) pairs collected for `--pretrain-steps`
+const PRETRAIN_STATES: usize = 20_000;
+
+/// Greedy states probed by `--export-activations` when no `--states` file is given
+const ACTIVATION_SAMPLES: usize = 1_000;
+
+/// Eval seed of every model_best.json without `--eval-seed`, so successive bests are scored on
+/// the same boards
+const BEST_EVAL_SEED: u64 = 0xBE57_E7A1;
+
+/// Options that take no value
+const FLAGS: &[&str] = &[
+    "--self-test",
+    "--parallel-eval",
+    "--target-drift-stats",
+    "--overestimation-stats",
+    "--normalize-obs",
+    "--diagonals",
+    "--no-corner-food",
+    "--baseline-eval",
+    "--update-per-episode",
+    "--no-tail-bonus",
+    "--checkpoint-replay",
+    "--no-double-dqn",
+    "--dueling",
+    "--c51",
+    "--qr-dqn",
+    "--noisy-nets",
+];
+
 struct Config {
     episodes: u64,
     print_every: u64,
     save_every: u64,
+    keep_checkpoints: Option<usize>,
+    feature_importance_every: u64,
+    action_histogram_every: u64,
+    heuristic_agreement_every: u64,
+    seed: Option<u64>,
+    resume: Option<String>,
+    checkpoint_replay: bool,
+    seed_sweep: Option<Vec<u64>>,
+    target_drift_stats: bool,
+    stats_logfmt: bool,
+    export_npz: bool,
+    overestimation_stats: bool,
+    self_test: bool,
+    policy_map: Option<String>,
+    latency_report: Option<String>,
+    distill_tree: Option<String>,
+    tree_depth: usize,
+    export_activations: Option<String>,
+    record_best: Option<String>,
+    replay: Option<String>,
+    replay_delay_ms: u64,
+    model: Option<String>,
+    states: Option<String>,
+    policy_out: Option<String>,
+    run_name: Option<String>,
+    out_dir: Option<String>,
+    food: Option<Point>,
+    terminal_fraction: f32,
+    pure_random_steps: u64,
+    pretrain_steps: u64,
+    target_update_warmup: u64,
+    warmup_heuristic_ratio: f32,
+    max_sample_age: Option<usize>,
+    lazy_priority_recompute: u64,
+    clamp_target: Option<(f32, f32)>,
+    target_rule: TargetRule,
+    dueling: bool,
+    noisy_nets: bool,
+    distribution: Option<ReturnDistribution>,
+    n_step: usize,
+    revert_on_regression: Option<f32>,
+    update_per_episode: bool,
+    normalize_obs: bool,
+    mask_features: Vec<usize>,
+    reward: RewardConfig,
+    food_bias_open: bool,
+    food_bias_anneal: u64,
+    no_corner_food: bool,
+    no_corner_food_anneal: u64,
+    sticky_actions: f32,
+    action_repeat: u32,
+    diagonals: bool,
+    truncate_at: Option<u32>,
+    doom_cutoff: Option<f32>,
+    starvation_length_scale: f32,
+    obstacles: usize,
+    food_script: Option<String>,
+    curriculum: Option<Curriculum>,
+    log_file: Option<String>,
+    episode_seeds_file: Option<String>,
+    html_report: Option<String>,
+    log_rotate_mb: Option<f64>,
+    log_rotate_episodes: Option<u64>,
+    log_trajectory_every: u64,
+    trajectory_file: String,
+    visitation_heatmap: Option<String>,
+    eval: Option<String>,
+    value_trace: Option<String>,
+    baseline_eval: bool,
+    tabular: bool,
+    ppo: bool,
+    a2c: bool,
+    a2c_envs: usize,
+    sac: bool,
+    reinforce: bool,
+    es: bool,
+    es_generations: u64,
+    es_population: usize,
+    es_sigma: f32,
+    es_episodes: u64,
+    ga: bool,
+    ga_generations: u64,
+    ga_population: usize,
+    ga_episodes: u64,
+    ga_mutation: f32,
+    eval_grid_sizes: Vec<i32>,
+    milestones: Vec<f32>,
+    eval_options: EvalOptions,
+    best_eval_episodes: u64,
 }
 
-fn parse_args() -> Config {
-    let args: Vec<String> = std::env::args().collect();
+/// Parses the command line `args` (program name first) into a `Config`
+fn parse_args(args: &[String]) -> Config {
     let mut map: HashMap<String, String> = HashMap::new();
 
     let mut i = 1;
@@ -27,13 +207,194 @@ fn parse_args() -> Config {
             println!("Usage: rust_entrenador [OPTIONS]");
             println!();
             println!("Options:");
-            println!("  --episodes <N>     Number of training episodes  [default: 100000]");
-            println!("  --print-every <N>  Print stats every N episodes [default: 100]");
-            println!("  --save-every <N>   Save model every N episodes  [default: 5000]");
-            println!("  -h, --help         Show this help");
+            println!("  --episodes <N>           Number of training episodes  [default: 100000]");
+            println!("  --print-every <N>        Print stats every N episodes [default: 100]");
+            println!("  --save-every <N>         Save model every N episodes  [default: 5000]");
+            println!("  --keep-checkpoints <N>   After each save, delete all but the N most recent model_ep*.json");
+            println!("                           (model_best/model_final are kept) [default: keep all]");
+            println!("  --resume <F>             Continue training from checkpoint F (weights, Adam state,");
+            println!("                           epsilon, LR, RNG state); checkpoint.json is saved with every model save");
+            println!("  --checkpoint-replay      Include the replay buffer in checkpoints so a resumed run matches an");
+            println!("                           uninterrupted one exactly (large files)");
+            println!("  --seed <N>               Seed weights, exploration and food for a reproducible run");
+            println!("  --seed-sweep <S1,S2,..>  Train once per seed and report mean/std of final Avg(100)");
+            println!("  --run-name <NAME>        Prefix every artifact file name with NAME_");
+            println!("  --out-dir <DIR>          Write models, checkpoints and logs under DIR (created if missing)");
+            println!("  --feature-importance-every <N>");
+            println!("                           Print first-layer weight norms per feature, 0 = off [default: 0]");
+            println!("  --action-histogram-every <N>");
+            println!("                           Print the action mix of the newest 5000 replay transitions, 0 = off [default: 0]");
+            println!("  --heuristic-agreement-every <N>");
+            println!("                           Print how often the greedy action matches the food/safety heuristic on 1000 fixed states, 0 = off [default: 0]");
+            println!("  --target-drift-stats     Print mean |Q_online - Q_target| on recent states with stats");
+            println!("  --overestimation-stats   Print mean predicted Q minus the Monte Carlo return of completed");
+            println!("                           episodes with stats (positive = overestimation)");
+            println!("  --terminal-fraction <F>  Min share of terminal transitions per batch [default: 0]");
+            println!("  --pure-random-steps <N>  Seed the buffer with N uniformly random steps before any training or");
+            println!("                           epsilon decay [default: 0]");
+            println!("  --pretrain-steps <N>     Before RL, train N minibatches to imitate the greedy-toward-food");
+            println!("                           heuristic (one-hot Q regression) [default: 0]");
+            println!("  --warmup-heuristic-ratio <F>");
+            println!("                           Share of those steps played by the greedy-toward-food heuristic");
+            println!("                           instead of uniform random [default: 0]");
+            println!("  --max-sample-age <M>     Only sample the M most recently inserted transitions [default: whole buffer]");
+            println!("  --lazy-priority-recompute <M>");
+            println!("                           Sample by TD error, recomputed over the buffer every M train steps, 0 = off [default: 0]");
+            println!("  --clamp-target <MIN:MAX> Clamp TD targets into [MIN, MAX]; plain Q-networks only [default: no clamp]");
+            println!("  --target-rule <R>        TD target rule: vanilla (max over the target network), double (main");
+            println!("                           network picks, target evaluates), clipped-double (double, evaluated");
+            println!("                           by the min of twin target networks; trains a twin online network),");
+            println!("                           sarsa (the target network evaluates the action taken next) or");
+            println!("                           expected-sarsa (its value under the epsilon-greedy policy)");
+            println!("                           [default: double]");
+            println!("  --no-double-dqn          Same as --target-rule vanilla");
+            println!("  --n-step <N>             Store N-step returns (bootstrapping from the Nth next state) [default: 3]");
+            println!("  --agent <A>              dqn, or rainbow: C51 + dueling + --noisy-nets + 3-step returns +");
+            println!("                           prioritized replay (--lazy-priority-recompute 1000); explicit options");
+            println!("                           override the preset, --qr-dqn replaces C51; or sarsa: on-policy");
+            println!("                           baseline (--target-rule sarsa --n-step 1 --max-sample-age 256); or");
+            println!("                           tabular: Q-learning on the 11-bit danger/heading/food state, then a");
+            println!("                           greedy eval over --eval-episodes (no model saved) [default: dqn]");
+            println!("  --algo <A>               dqn, or ppo: actor-critic with a softmax policy head, GAE advantages");
+            println!("                           and the clipped surrogate loss, updated in minibatch epochs every");
+            println!("                           2048 steps; or a2c: synchronous advantage actor-critic over");
+            println!("                           --a2c-envs environments, one update per 5 steps of each; or sac:");
+            println!("                           discrete soft actor-critic with twin Q-networks and an entropy");
+            println!("                           temperature tuned automatically; or reinforce: Monte-Carlo policy");
+            println!("                           gradient with a value baseline, updated after each episode; or es:");
+            println!("                           gradient-free evolution strategy over the network weights (see");
+            println!("                           --es-*); or ga: genetic algorithm over a population of networks");
+            println!("                           (see --ga-*). All of these evaluate and export the most probable");
+            println!("                           action [default: dqn]");
+            println!("  --a2c-envs <N>           Environments stepped in lockstep by --algo a2c [default: 8]");
+            println!("  --es-generations <N>     Generations for --algo es (instead of --episodes) [default: 100]");
+            println!("  --es-population <N>      Candidates per generation, mirrored +/- pairs [default: 32]");
+            println!("  --es-sigma <S>           Std dev of the weight perturbations [default: 0.1]");
+            println!("  --es-episodes <N>        Episodes each candidate plays per generation, on seeds shared");
+            println!("                           across the generation [default: 3]");
+            println!("  --ga-generations <N>     Generations for --algo ga (instead of --episodes) [default: 100]");
+            println!("  --ga-population <N>      Networks per generation; the 2 fittest carry over unchanged, the");
+            println!("                           rest are bred by 3-way tournament selection, uniform crossover and");
+            println!("                           mutation [default: 64]");
+            println!("  --ga-episodes <N>        Seeded episodes whose mean score is each member's fitness; the seeds");
+            println!("                           are shared across the generation [default: 3]");
+            println!("  --ga-mutation <S>        Std dev of the Gaussian noise on a mutated weight (10% of a child's");
+            println!("                           weights mutate) [default: 0.5]");
+            println!("  --c51                    Distributional (C51) agent: 51-atom return distribution per action,");
+            println!("                           trained with cross-entropy to the projected Bellman target");
+            println!("  --c51-v-min <V>          Lowest return on the C51 support [default: -20]");
+            println!("  --c51-v-max <V>          Highest return on the C51 support [default: 100]");
+            println!("  --qr-dqn                 Quantile regression (QR-DQN) agent: N return quantiles per action,");
+            println!("                           trained with the quantile Huber loss; no fixed value range");
+            println!("  --quantiles <N>          Quantiles per action for --qr-dqn [default: 51]");
+            println!("  --dueling                Dueling network: separate value and advantage streams, Q = V + A - mean(A)");
+            println!("                           (per atom/quantile with --c51 or --qr-dqn)");
+            println!("  --noisy-nets             NoisyNet exploration: noisy linear layers resampled every step replace");
+            println!("                           epsilon-greedy (epsilon stays 0); eval and export use the noise-free means");
+            println!("  --target-update-warmup <K>");
+            println!("                           Hard-copy the target network on every update for the first K steps,");
+            println!("                           then soft-update with tau [default: 0]");
+            println!("  --update-per-episode     Train once per episode on all of its transitions instead of every 4 steps");
+            println!("  --revert-on-regression <D>");
+            println!("                           Restore the best-avg agent when Avg(100) falls D below the best");
+            println!("  --mask-features <I1,I2,..>");
+            println!("                           Zero these feature indices in every network input, training and eval");
+            println!("  --normalize-obs          Standardize features with running mean/std (saved with the model)");
+            println!("  --anti-loop-window <N>   Recent head cells checked for loops, 0 = off [default: 0]");
+            println!("  --anti-loop-penalty <F>  Per-step penalty while looping [default: 0.5]");
+            println!("  --revisit-window <N>     Previous head cells checked for revisits, 0 = off [default: 0]");
+            println!("  --revisit-penalty <F>    Penalty for re-entering one of those cells [default: 0.2]");
+            println!("  --space-delta-bonus <W>  Reward W x change in reachable space per step [default: 0]");
+            println!("  --death-penalty-length-scale <W>");
+            println!("                           Add W to the -10 death penalty per segment grown [default: 0]");
+            println!("  --food-reward-length-slope <S>");
+            println!("                           Food reward 10 + S x snake length (S < 0: food worth less as it grows) [default: 0]");
+            println!("  --body-proximity-penalty <W>");
+            println!("                           Penalize W / distance from the head to its nearest body segment past the neck [default: 0]");
+            println!("  --coverage-bonus <W>     Reward W when the head enters a cell not yet visited this episode [default: 0]");
+            println!("  --starvation-ramp <F>    Penalize steps without food, ramping 0 to 1 per step over the final");
+            println!("                           F of the starvation limit (grid area), 0 = off [default: 0]");
+            println!("  --no-tail-bonus          Skip the tail-reachability shaping term and its BFS");
+            println!("  --potential-shaping <P>  Replace the approach reward with potential-based shaping");
+            println!("                           on P: distance (to food) or flood (reachable space)");
+            println!("  --food-bias <MODE>       Food spawn: open (favor open regions) or uniform [default: uniform]");
+            println!("  --food-bias-anneal <N>   Episodes to anneal open bias to uniform, 0 = never [default: 0]");
+            println!("  --no-corner-food         Never spawn food in the four corner cells");
+            println!("  --no-corner-food-anneal <N>");
+            println!("                           Episodes to phase corner food back in, 0 = never [default: 0]");
+            println!("  --sticky-actions <P>     Probability the engine repeats the previous action [default: 0]");
+            println!("  --action-repeat <K>      Repeat each chosen action for K steps (frame skip) [default: 1]");
+            println!("  --diagonals              Add the four diagonal moves to the action set (8 outputs)");
+            println!("  --truncate-at <N>        End episodes after N steps as truncated (not terminal)");
+            println!("  --doom-cutoff <R>        End episodes as a death once reachable space < R x snake length");
+            println!("  --starvation-length-scale <K>");
+            println!("                           Starve after area + K x snake length steps without food [default: 0]");
+            println!("  --obstacles <N>          Random obstacle cells placed each episode [default: 0]");
+            println!("  --food-script <F>        Spawn food at the X,Y lines of F in order (wrapping)");
+            println!("  --curriculum <G1,G2,..>  Train on these grid sizes in turn");
+            println!("  --curriculum-metric <M>  Stage advance trigger: score (avg score) or fill");
+            println!("                           (avg snake length / area at death) [default: score]");
+            println!("  --curriculum-threshold <X>");
+            println!("                           Rolling avg needed to advance [default: 50 score, 0.2 fill]");
+            println!("  --curriculum-epsilon-reset <E>");
+            println!("                           Raise epsilon to E on each stage advance, then resume decay");
+            println!("  --export-format <FMT>    Model exports: json (TF.js), or npz to also write NumPy .npz");
+            println!("                           weights next to each .json [default: json]");
+            println!("  --milestones <T1,T2,..>  Report the episode and steps at which Avg(100) first reaches");
+            println!("                           each score [default: 50,100,150]");
+            println!("  --stats-format <FMT>     Periodic stats as a table or logfmt key=value lines [default: table]");
+            println!("  --log-file <F>           Append per-episode metrics to JSONL file F");
+            println!("  --log-episode-seeds <F>  Reseed the environment every episode and log each seed and score");
+            println!("                           to JSONL file F, for reproducing episodes (see --eval-seed)");
+            println!("  --html-report <F>        After training, chart the --log-file metrics in a self-contained HTML file F");
+            println!("  --log-rotate-mb <N>      Start a new numbered log file every N MB");
+            println!("  --log-rotate-episodes <N>");
+            println!("                           Start a new numbered log file every N episodes");
+            println!("  --log-trajectory-every <N>");
+            println!("                           Write every Nth episode's transitions to the trajectory file, 0 = off [default: 0]");
+            println!("  --trajectory-file <F>    JSONL file for sampled trajectories [default: trajectories.jsonl]");
+            println!("  --visitation-heatmap <F> Write per-cell head-visit counts over training to F");
+            println!("  --eval <F>               Evaluate model F greedily and exit");
+            println!("  --eval-episodes <N>      Episodes per evaluation [default: 100]");
+            println!("  --eval-epsilon <F>       Random-action rate during eval [default: 0]");
+            println!("                           (>0 breaks greedy loops but makes eval non-deterministic)");
+            println!("  --value-trace <F>        With --eval, write the per-step max Q of the best of --eval-episodes");
+            println!("                           greedy games to F as CSV (step,value)");
+            println!("  --eval-seed <S>          Seed the eval environment; with --eval-episodes 1, replays the");
+            println!("                           environment of a --log-episode-seeds episode");
+            println!("  --threads <N>            Eval threads; above 1, each episode gets its own seed derived from");
+            println!("                           --eval-seed (or a random one) so scores don't depend on scheduling [default: 1]");
+            println!("  --parallel-eval          Same as --threads with one thread per available core");
+            println!("  --play-temperature <T>   Sample eval actions from softmax(Q/T) instead of greedy");
+            println!("  --best-eval-episodes <N> Greedy-evaluate each new model_best.json over N episodes and");
+            println!("                           show it with the stats, 0 = off [default: 0]");
+            println!("  --eval-grid-sizes <G1,G2,..>");
+            println!("                           Evaluate on each of these grid sizes [default: 20]");
+            println!("  --baseline-eval          Also evaluate the greedy-toward-food heuristic on the same");
+            println!("                           suite (alone: evaluate only the heuristic and exit)");
+            println!("  --self-test              Run the scripted checks of every subsystem (engine, learning, eval, export) and exit");
+            println!("  --policy-map <F>         Print the greedy action map of model F and exit");
+            println!("  --policy-out <F>         Write the policy map to F instead of stdout");
+            println!("  --food <X,Y>             Food cell for the policy map [default: center]");
+            println!("  --latency-report <F>     Time single greedy inferences of model F and exit");
+            println!("  --distill-tree <F>       Fit a decision tree to model F's greedy policy, print its rules and exit");
+            println!("  --tree-depth <D>         Max depth of the distilled tree [default: 4]");
+            println!("  --export-activations <F> Write the hidden activations of --model for a set of states");
+            println!("                           to F as JSON ([states x units] per hidden layer) and exit");
+            println!("  --record-best <F>        Play --eval-episodes greedy games of --model, save the frames of");
+            println!("                           the highest-scoring one to F (see --replay) and exit");
+            println!("  --replay <F>             Play back a --record-best recording in the terminal and exit");
+            println!("  --replay-delay-ms <N>    Delay between replayed frames [default: 100]");
+            println!("  --model <F>              Model for --export-activations and --record-best");
+            println!("  --states <F>             States for --export-activations, one comma-separated feature");
+            println!("                           vector per line [default: greedy states from --seed or 0]");
+            println!("  -h, --help               Show this help");
             std::process::exit(0);
         }
-        if args[i].starts_with("--") && i + 1 < args.len() {
+        if FLAGS.contains(&args[i].as_str()) {
+            map.insert(args[i].clone(), String::new());
+            i += 1;
+        } else if args[i].starts_with("--") && i + 1 < args.len() {
             map.insert(args[i].clone(), args[i + 1].clone());
             i += 2;
         } else {
@@ -41,6 +402,63 @@ fn parse_args() -> Config {
             std::process::exit(1);
         }
     }
+    if map.contains_key("--html-report") && !map.contains_key("--log-file") {
+        eprintln!("--html-report charts the --log-file metrics, so it needs --log-file <F>");
+        std::process::exit(1);
+    }
+    let preset: &[(&str, &str)] = match map.get("--agent").map(String::as_str) {
+        None | Some("dqn") | Some("tabular") => &[],
+        Some("rainbow") => &RAINBOW_PRESET,
+        Some("sarsa") => &SARSA_PRESET,
+        Some(other) => {
+            eprintln!("Unknown --agent {} (expected dqn, rainbow, sarsa or tabular)", other);
+            std::process::exit(1);
+        }
+    };
+    match map.get("--algo").map(String::as_str) {
+        None | Some("dqn") => {}
+        Some(algo @ ("ppo" | "a2c" | "sac" | "reinforce" | "es" | "ga")) => {
+            if map.contains_key("--resume") {
+                eprintln!("--resume isn't supported with --algo {}", algo);
+                std::process::exit(1);
+            }
+            if map.contains_key("--agent") {
+                eprintln!("--agent is a DQN variant, so it can't be combined with --algo {}", algo);
+                std::process::exit(1);
+            }
+        }
+        Some(other) => {
+            eprintln!("Unknown --algo {} (expected dqn, ppo, a2c, sac, reinforce, es or ga)", other);
+            std::process::exit(1);
+        }
+    }
+    // Explicit options win over the preset; --qr-dqn replaces Rainbow's C51 head
+    for &(key, value) in preset {
+        if !(key == "--c51" && map.contains_key("--qr-dqn")) {
+            map.entry(key.to_string()).or_insert_with(|| value.to_string());
+        }
+    }
+    if map.contains_key("--c51") && map.contains_key("--qr-dqn") {
+        eprintln!("--c51 and --qr-dqn can't be combined");
+        std::process::exit(1);
+    }
+    if map.contains_key("--no-double-dqn") && map.contains_key("--target-rule") {
+        eprintln!("--no-double-dqn and --target-rule can't be combined");
+        std::process::exit(1);
+    }
+    if map.contains_key("--clamp-target")
+        && (map.contains_key("--c51") || map.contains_key("--qr-dqn"))
+    {
+        eprintln!("--clamp-target needs a plain Q-network (no --c51 or --qr-dqn)");
+        std::process::exit(1);
+    }
+    if let Some(rule) = map.get("--target-rule") {
+        let plain_only = rule == "clipped-double" || rule == "expected-sarsa";
+        if plain_only && (map.contains_key("--c51") || map.contains_key("--qr-dqn")) {
+            eprintln!("--target-rule {} needs a plain Q-network (no --c51 or --qr-dqn)", rule);
+            std::process::exit(1);
+        }
+    }
 
     Config {
         episodes: map
@@ -55,123 +473,1606 @@ fn parse_args() -> Config {
             .get("--save-every")
             .and_then(|s| s.parse().ok())
             .unwrap_or(5_000),
+        keep_checkpoints: map
+            .get("--keep-checkpoints")
+            .and_then(|s| s.parse().ok())
+            .filter(|&n| n > 0),
+        seed: map.get("--seed").and_then(|s| s.parse().ok()),
+        resume: map.get("--resume").cloned(),
+        checkpoint_replay: map.contains_key("--checkpoint-replay"),
+        seed_sweep: map.get("--seed-sweep").map(|s| {
+            let seeds: Vec<u64> = s.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+            if seeds.is_empty() {
+                eprintln!("--seed-sweep needs at least one seed");
+                std::process::exit(1);
+            }
+            seeds
+        }),
+        feature_importance_every: map
+            .get("--feature-importance-every")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        action_histogram_every: map
+            .get("--action-histogram-every")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        heuristic_agreement_every: map
+            .get("--heuristic-agreement-every")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        target_drift_stats: map.contains_key("--target-drift-stats"),
+        stats_logfmt: match map.get("--stats-format").map(String::as_str) {
+            None | Some("table") => false,
+            Some("logfmt") => true,
+            Some(other) => {
+                eprintln!("Unknown --stats-format: {}", other);
+                std::process::exit(1);
+            }
+        },
+        export_npz: match map.get("--export-format").map(String::as_str) {
+            None | Some("json") => false,
+            Some("npz") => true,
+            Some(other) => {
+                eprintln!("Unknown --export-format: {}", other);
+                std::process::exit(1);
+            }
+        },
+        overestimation_stats: map.contains_key("--overestimation-stats"),
+        terminal_fraction: map
+            .get("--terminal-fraction")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.0),
+        max_sample_age: map.get("--max-sample-age").and_then(|s| s.parse().ok()),
+        pure_random_steps: map
+            .get("--pure-random-steps")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        pretrain_steps: map
+            .get("--pretrain-steps")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        target_update_warmup: map
+            .get("--target-update-warmup")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        warmup_heuristic_ratio: map
+            .get("--warmup-heuristic-ratio")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.0),
+        lazy_priority_recompute: map
+            .get("--lazy-priority-recompute")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        target_rule: match map.get("--target-rule").map(String::as_str) {
+            None if map.contains_key("--no-double-dqn") => TargetRule::Vanilla,
+            None | Some("double") => TargetRule::Double,
+            Some("vanilla") => TargetRule::Vanilla,
+            Some("clipped-double") => TargetRule::ClippedDouble,
+            Some("sarsa") => TargetRule::Sarsa,
+            Some("expected-sarsa") => TargetRule::ExpectedSarsa,
+            Some(other) => {
+                eprintln!("Unknown --target-rule: {}", other);
+                std::process::exit(1);
+            }
+        },
+        dueling: map.contains_key("--dueling"),
+        noisy_nets: map.contains_key("--noisy-nets"),
+        distribution: if map.contains_key("--c51") {
+            let bound = |key: &str, default: f32| {
+                map.get(key).and_then(|s| s.parse().ok()).unwrap_or(default)
+            };
+            let support = Support::new(bound("--c51-v-min", -20.0), bound("--c51-v-max", 100.0));
+            Some(ReturnDistribution::Categorical(support))
+        } else if map.contains_key("--qr-dqn") {
+            let quantiles = map
+                .get("--quantiles")
+                .and_then(|s| s.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(QR_QUANTILES);
+            Some(ReturnDistribution::Quantile(quantiles))
+        } else {
+            None
+        },
+        n_step: map
+            .get("--n-step")
+            .and_then(|s| s.parse().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(3),
+        clamp_target: map.get("--clamp-target").map(|s| {
+            let bounds = s.split_once(':').and_then(|(lo, hi)| {
+                Some((lo.trim().parse::<f32>().ok()?, hi.trim().parse::<f32>().ok()?))
+            });
+            match bounds {
+                Some((lo, hi)) if lo <= hi => (lo, hi),
+                _ => {
+                    eprintln!("Invalid --clamp-target (expected MIN:MAX): {}", s);
+                    std::process::exit(1);
+                }
+            }
+        }),
+        update_per_episode: map.contains_key("--update-per-episode"),
+        revert_on_regression: map.get("--revert-on-regression").and_then(|s| s.parse().ok()),
+        normalize_obs: map.contains_key("--normalize-obs"),
+        mask_features: map
+            .get("--mask-features")
+            .map(|s| s.split(',').filter_map(|i| i.trim().parse().ok()).collect())
+            .unwrap_or_default(),
+        reward: RewardConfig {
+            potential: match map.get("--potential-shaping").map(String::as_str) {
+                None => None,
+                Some("distance") => Some(Potential::Distance),
+                Some("flood") => Some(Potential::Flood),
+                Some(other) => {
+                    eprintln!("Unknown --potential-shaping: {}", other);
+                    std::process::exit(1);
+                }
+            },
+            // Set to the agent's gamma in `train`
+            potential_gamma: 0.0,
+            anti_loop_window: map
+                .get("--anti-loop-window")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            anti_loop_penalty: map
+                .get("--anti-loop-penalty")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.5),
+            revisit_window: map
+                .get("--revisit-window")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            revisit_penalty: map
+                .get("--revisit-penalty")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.2),
+            no_tail_bonus: map.contains_key("--no-tail-bonus"),
+            space_delta_bonus: map
+                .get("--space-delta-bonus")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            death_length_scale: map
+                .get("--death-penalty-length-scale")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            food_length_slope: map
+                .get("--food-reward-length-slope")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            coverage_bonus: map
+                .get("--coverage-bonus")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            body_proximity_penalty: map
+                .get("--body-proximity-penalty")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            starvation_ramp: map
+                .get("--starvation-ramp")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+        },
+        food_bias_open: match map.get("--food-bias").map(String::as_str) {
+            None | Some("uniform") => false,
+            Some("open") => true,
+            Some(other) => {
+                eprintln!("Unknown --food-bias: {}", other);
+                std::process::exit(1);
+            }
+        },
+        food_bias_anneal: map
+            .get("--food-bias-anneal")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        no_corner_food: map.contains_key("--no-corner-food"),
+        no_corner_food_anneal: map
+            .get("--no-corner-food-anneal")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        sticky_actions: map
+            .get("--sticky-actions")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.0),
+        action_repeat: map
+            .get("--action-repeat")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1),
+        diagonals: map.contains_key("--diagonals"),
+        truncate_at: map.get("--truncate-at").and_then(|s| s.parse().ok()),
+        doom_cutoff: map.get("--doom-cutoff").and_then(|s| s.parse().ok()),
+        starvation_length_scale: map
+            .get("--starvation-length-scale")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.0),
+        obstacles: map
+            .get("--obstacles")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        food_script: map.get("--food-script").cloned(),
+        curriculum: map.get("--curriculum").map(|s| {
+            let grid_sizes: Vec<i32> = s.split(',').filter_map(|g| g.trim().parse().ok()).collect();
+            let metric = match map.get("--curriculum-metric").map(String::as_str) {
+                None | Some("score") => CurriculumMetric::Score,
+                Some("fill") => CurriculumMetric::Fill,
+                Some(other) => {
+                    eprintln!("Unknown --curriculum-metric: {}", other);
+                    std::process::exit(1);
+                }
+            };
+            let default_threshold = match metric {
+                CurriculumMetric::Score => 50.0,
+                CurriculumMetric::Fill => 0.2,
+            };
+            let threshold = map
+                .get("--curriculum-threshold")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default_threshold);
+            if grid_sizes.is_empty() {
+                eprintln!("--curriculum needs at least one grid size");
+                std::process::exit(1);
+            }
+            let mut curriculum = Curriculum::new(grid_sizes, metric, threshold);
+            curriculum.epsilon_reset = map
+                .get("--curriculum-epsilon-reset")
+                .and_then(|s| s.parse().ok());
+            curriculum
+        }),
+        log_file: map.get("--log-file").cloned(),
+        episode_seeds_file: map.get("--log-episode-seeds").cloned(),
+        html_report: map.get("--html-report").cloned(),
+        log_rotate_mb: map.get("--log-rotate-mb").and_then(|s| s.parse().ok()),
+        log_rotate_episodes: map.get("--log-rotate-episodes").and_then(|s| s.parse().ok()),
+        log_trajectory_every: map
+            .get("--log-trajectory-every")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        trajectory_file: map
+            .get("--trajectory-file")
+            .cloned()
+            .unwrap_or_else(|| "trajectories.jsonl".to_string()),
+        visitation_heatmap: map.get("--visitation-heatmap").cloned(),
+        eval: map.get("--eval").cloned(),
+        value_trace: map.get("--value-trace").cloned(),
+        baseline_eval: map.contains_key("--baseline-eval"),
+        tabular: map.get("--agent").is_some_and(|agent| agent == "tabular"),
+        ppo: map.get("--algo").is_some_and(|algo| algo == "ppo"),
+        a2c: map.get("--algo").is_some_and(|algo| algo == "a2c"),
+        sac: map.get("--algo").is_some_and(|algo| algo == "sac"),
+        reinforce: map.get("--algo").is_some_and(|algo| algo == "reinforce"),
+        es: map.get("--algo").is_some_and(|algo| algo == "es"),
+        es_generations: map.get("--es-generations").and_then(|s| s.parse().ok()).unwrap_or(100),
+        es_population: map.get("--es-population").and_then(|s| s.parse().ok()).unwrap_or(32),
+        es_sigma: map.get("--es-sigma").and_then(|s| s.parse().ok()).unwrap_or(0.1),
+        es_episodes: map.get("--es-episodes").and_then(|s| s.parse().ok()).unwrap_or(3).max(1),
+        ga: map.get("--algo").is_some_and(|algo| algo == "ga"),
+        ga_generations: map.get("--ga-generations").and_then(|s| s.parse().ok()).unwrap_or(100),
+        ga_population: map.get("--ga-population").and_then(|s| s.parse().ok()).unwrap_or(64),
+        ga_episodes: map.get("--ga-episodes").and_then(|s| s.parse().ok()).unwrap_or(3).max(1),
+        ga_mutation: map.get("--ga-mutation").and_then(|s| s.parse().ok()).unwrap_or(0.5),
+        a2c_envs: map.get("--a2c-envs").and_then(|s| s.parse().ok()).unwrap_or(8).max(1),
+        eval_grid_sizes: map
+            .get("--eval-grid-sizes")
+            .map(|s| s.split(',').filter_map(|g| g.trim().parse().ok()).collect())
+            .filter(|sizes: &Vec<i32>| !sizes.is_empty())
+            .unwrap_or_else(|| vec![GRID_SIZE]),
+        milestones: map
+            .get("--milestones")
+            .map_or("50,100,150", String::as_str)
+            .split(',')
+            .filter_map(|t| t.trim().parse().ok())
+            .collect(),
+        best_eval_episodes: map
+            .get("--best-eval-episodes")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        eval_options: EvalOptions {
+            episodes: map
+                .get("--eval-episodes")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100),
+            epsilon: map
+                .get("--eval-epsilon")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0.0),
+            temperature: map.get("--play-temperature").and_then(|s| s.parse().ok()),
+            seed: map.get("--eval-seed").and_then(|s| s.parse().ok()),
+            threads: if map.contains_key("--parallel-eval") {
+                std::thread::available_parallelism().map_or(1, |n| n.get())
+            } else {
+                map.get("--threads").and_then(|s| s.parse().ok()).unwrap_or(1)
+            },
+        },
+        self_test: map.contains_key("--self-test"),
+        policy_map: map.get("--policy-map").cloned(),
+        policy_out: map.get("--policy-out").cloned(),
+        run_name: map.get("--run-name").cloned(),
+        out_dir: map.get("--out-dir").cloned(),
+        latency_report: map.get("--latency-report").cloned(),
+        distill_tree: map.get("--distill-tree").cloned(),
+        tree_depth: map.get("--tree-depth").and_then(|s| s.parse().ok()).unwrap_or(4),
+        export_activations: map.get("--export-activations").cloned(),
+        record_best: map.get("--record-best").cloned(),
+        replay: map.get("--replay").cloned(),
+        replay_delay_ms: map
+            .get("--replay-delay-ms")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(100),
+        model: map.get("--model").cloned(),
+        states: map.get("--states").cloned(),
+        food: map.get("--food").and_then(|s| {
+            let (x, y) = s.split_once(',')?;
+            Some(Point {
+                x: x.trim().parse().ok()?,
+                y: y.trim().parse().ok()?,
+            })
+        }),
     }
 }
 
-fn export_model(agent: &DQNAgent, filename: &str) {
-    let mut weight_bytes: Vec<u8> = Vec::new();
-    let mut weight_specs: Vec<serde_json::Value> = Vec::new();
-
-    let layer_names = ["dense", "dense_1", "dense_2"];
-
-    for i in 0..agent.network.num_layers() {
-        let (weights, biases, in_size, out_size) = agent.network.layer_info(i);
-
-        // Weights: stored as [in_size × out_size] row-major, TF.js expects same layout
-        for &val in weights.iter() {
-            weight_bytes.extend_from_slice(&val.to_le_bytes());
-        }
-        weight_specs.push(serde_json::json!({
-            "name": format!("{}/kernel", layer_names[i]),
-            "shape": [in_size, out_size],
-            "dtype": "float32"
-        }));
-
-        // Biases
-        for &val in biases.iter() {
-            weight_bytes.extend_from_slice(&val.to_le_bytes());
-        }
-        weight_specs.push(serde_json::json!({
-            "name": format!("{}/bias", layer_names[i]),
-            "shape": [out_size],
-            "dtype": "float32"
-        }));
-    }
-
-    let model_topology = serde_json::json!({
-        "class_name": "Sequential",
-        "config": {
-            "name": "sequential",
-            "layers": [
-                {
-                    "class_name": "Dense",
-                    "config": {
-                        "units": 256, "activation": "relu", "use_bias": true,
-                        "name": "dense", "batch_input_shape": [null, 28],
-                        "dtype": "float32"
-                    }
-                },
-                {
-                    "class_name": "Dense",
-                    "config": {
-                        "units": 64, "activation": "relu", "use_bias": true,
-                        "name": "dense_1", "dtype": "float32"
-                    }
-                },
-                {
-                    "class_name": "Dense",
-                    "config": {
-                        "units": 4, "activation": "linear", "use_bias": true,
-                        "name": "dense_2", "dtype": "float32"
-                    }
-                }
-            ]
-        }
+fn load_agent(config: &Config, path: &str) -> DQNAgent {
+    let mut agent = load_model(path).unwrap_or_else(|e| {
+        eprintln!("Failed to load model: {}", e);
+        std::process::exit(1);
     });
+    set_feature_mask(&mut agent, &config.mask_features);
+    agent
+}
+
+/// Applies `--mask-features`, rejecting indices outside the agent's feature set
+fn set_feature_mask(agent: &mut DQNAgent, mask: &[usize]) {
+    if let Some(&bad) = mask.iter().find(|&&i| i >= agent.features.size) {
+        eprintln!(
+            "--mask-features: index {} out of range (feature version {} has {} features)",
+            bad, agent.features.version, agent.features.size
+        );
+        std::process::exit(1);
+    }
+    agent.feature_mask = mask.to_vec();
+}
+
+fn main() {
+    let config = parse_args(&std::env::args().collect::<Vec<_>>());
+
+    if config.self_test {
+        match self_test::run(GRID_SIZE) {
+            Ok(checks) => println!("Self-test: PASS ({} checks)", checks),
+            Err(e) => {
+                eprintln!("Self-test: FAIL: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Per-size labels only when evaluating on more than the default grid
+    let sized = |label: &str, grid_size: i32| {
+        if config.eval_grid_sizes == [GRID_SIZE] {
+            label.to_string()
+        } else {
+            format!("{} @ {}x{}", label, grid_size, grid_size)
+        }
+    };
+
+    if let Some(path) = &config.eval {
+        let agent = load_agent(&config, path);
+        let trained = import_env_mode(path);
+        let mode = eval_env_mode(&config, &agent, &trained);
+        warn_env_mismatch(path, trained, &mode);
+        for (grid_size, stats, baseline) in eval_grid_sizes(&config, &agent) {
+            print_eval(&sized(path, grid_size), &stats, &config.eval_options);
+            if let Some(baseline) = baseline {
+                print_eval(&sized("baseline", grid_size), &baseline, &config.eval_options);
+            }
+        }
+        if let Some(out) = &config.value_trace {
+            let recording = tools::record_best_game(
+                &agent,
+                GRID_SIZE,
+                config.eval_options.episodes,
+                config.eval_options.seed,
+            );
+            std::fs::write(out, tools::value_trace_csv(&recording)).unwrap();
+            let (low, high) = recording
+                .values
+                .iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            println!(
+                ">>> Saved: {} (best game: score {}, {} steps, value {:.2} to {:.2})",
+                out, recording.score, recording.steps, low, high
+            );
+        }
+        return;
+    }
+
+    if config.baseline_eval {
+        for &grid_size in &config.eval_grid_sizes {
+            let make_engine = || configured_engine(&config, grid_size, 1.0);
+            let baseline = eval::evaluate_baseline(&make_engine, &config.eval_options);
+            print_eval(&sized("baseline", grid_size), &baseline, &config.eval_options);
+        }
+        return;
+    }
+
+    if let Some(path) = &config.policy_map {
+        let agent = load_agent(&config, path);
+        let food = config.food.unwrap_or(Point {
+            x: GRID_SIZE / 2,
+            y: GRID_SIZE / 2,
+        });
+        let rendered = tools::policy_map(&agent, GRID_SIZE, food);
+        match &config.policy_out {
+            Some(out) => {
+                std::fs::write(out, rendered).unwrap();
+                println!(">>> Saved: {}", out);
+            }
+            None => print!("{}", rendered),
+        }
+        return;
+    }
+
+    if let Some(path) = &config.latency_report {
+        let agent = load_agent(&config, path);
+        println!("{}", tools::latency_report(&agent, GRID_SIZE, LATENCY_SAMPLES));
+        return;
+    }
+
+    if let Some(path) = &config.distill_tree {
+        let agent = load_agent(&config, path);
+        let depth = config.tree_depth;
+        let tree = distill::distill_tree(&agent, GRID_SIZE, DISTILL_SAMPLES, depth, config.seed);
+        print!("{}", tree.render());
+        return;
+    }
+
+    if let Some(out) = &config.export_activations {
+        let Some(path) = &config.model else {
+            eprintln!("--export-activations needs --model <F>");
+            std::process::exit(1);
+        };
+        let agent = load_agent(&config, path);
+        let states = match &config.states {
+            Some(file) => load_states(file, agent.features.size).unwrap_or_else(|e| {
+                eprintln!("Failed to load states: {}", e);
+                std::process::exit(1);
+            }),
+            None => tools::sample_greedy_states(
+                &agent,
+                GRID_SIZE,
+                ACTIVATION_SAMPLES,
+                Some(config.seed.unwrap_or(0)),
+            ),
+        };
+        let activations = tools::activations_json(&agent, &states);
+        std::fs::write(out, serde_json::to_string(&activations).unwrap()).unwrap();
+        println!(">>> Saved: {} ({} states)", out, states.len());
+        return;
+    }
+
+    if let Some(out) = &config.record_best {
+        let Some(path) = &config.model else {
+            eprintln!("--record-best needs --model <F>");
+            std::process::exit(1);
+        };
+        let agent = load_agent(&config, path);
+        let recording = tools::record_best_game(
+            &agent,
+            GRID_SIZE,
+            config.eval_options.episodes,
+            config.seed,
+        );
+        std::fs::write(out, serde_json::to_string(&recording).unwrap()).unwrap();
+        println!(
+            ">>> Saved: {} (score {}, {} frames)",
+            out,
+            recording.score,
+            recording.frames.len()
+        );
+        return;
+    }
+
+    if let Some(path) = &config.replay {
+        let recording: tools::Recording = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load recording: {}", e);
+                std::process::exit(1);
+            });
+        tools::play_recording(&recording, Duration::from_millis(config.replay_delay_ms));
+        return;
+    }
+
+    if config.tabular {
+        train_tabular(&config);
+        return;
+    }
+    if config.ppo {
+        train_ppo(&config);
+        return;
+    }
+    if config.a2c {
+        train_a2c(&config);
+        return;
+    }
+    if config.sac {
+        train_sac(&config);
+        return;
+    }
+    if config.reinforce {
+        train_reinforce(&config);
+        return;
+    }
+    if config.es {
+        train_es(&config);
+        return;
+    }
+    if config.ga {
+        train_ga(&config);
+        return;
+    }
+
+    match &config.seed_sweep {
+        Some(seeds) => seed_sweep(&config, seeds),
+        None => {
+            train(&config, config.seed, "");
+        }
+    }
+}
+
+/// Environment `--eval` plays `agent` in, to compare with the one it was `trained` in. Grid
+/// sizes are chosen explicitly, so only a mismatch with the default grid is reported.
+fn eval_env_mode(
+    config: &Config,
+    agent: &DQNAgent,
+    trained: &Result<Option<EnvMode>, String>,
+) -> EnvMode {
+    let grid_size = match trained {
+        Ok(Some(env)) if config.eval_grid_sizes != [GRID_SIZE] => env.grid_size,
+        _ => GRID_SIZE,
+    };
+    eval_engine(config, agent, grid_size)().mode()
+}
+
+/// `--eval` scores of `agent` on each `--eval-grid-sizes` board, with the heuristic's on the
+/// same boards when `--baseline-eval` is set
+fn eval_grid_sizes(config: &Config, agent: &DQNAgent) -> Vec<(i32, EvalStats, Option<EvalStats>)> {
+    config
+        .eval_grid_sizes
+        .iter()
+        .map(|&grid_size| {
+            let make_engine = eval_engine(config, agent, grid_size);
+            let stats = eval::evaluate(agent, &make_engine, &config.eval_options);
+            let baseline = config
+                .baseline_eval
+                .then(|| eval::evaluate_baseline(&make_engine, &config.eval_options));
+            (grid_size, stats, baseline)
+        })
+        .collect()
+}
+
+/// Warns when a model or checkpoint was trained in a different environment than `current`
+fn warn_env_mismatch(path: &str, trained: Result<Option<EnvMode>, String>, current: &EnvMode) {
+    match trained {
+        Ok(Some(trained)) => {
+            for difference in trained.differences(current) {
+                eprintln!("Warning: {} was trained with {}", path, difference);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: {}: {}", path, e),
+    }
+}
+
+fn print_eval(label: &str, stats: &EvalStats, options: &EvalOptions) {
+    println!(
+        "Eval {} | episodes: {} | epsilon: {} | mean: {:.1} | max: {} | min: {}",
+        label,
+        stats.scores.len(),
+        options.epsilon,
+        stats.mean(),
+        stats.max(),
+        stats.min()
+    );
+}
 
-    let export = serde_json::json!({
-        "modelTopology": model_topology,
-        "weightSpecs": weight_specs,
-        "weightData": weight_bytes,
-        "meta": { "epsilon": agent.epsilon }
+/// Applies the environment options (rewards, dynamics, obstacles, food script) to `engine`;
+/// `gamma` is the learner's discount, which potential shaping must match
+fn configure_engine(config: &Config, engine: &mut SnakeEngine, gamma: f32) {
+    engine.reward = config.reward.clone();
+    engine.reward.potential_gamma = gamma;
+    engine.sticky_actions = config.sticky_actions;
+    engine.diagonals = config.diagonals;
+    engine.truncate_at = config.truncate_at;
+    engine.doom_cutoff = config.doom_cutoff;
+    engine.starvation_length_scale = config.starvation_length_scale;
+    engine.num_obstacles = config.obstacles;
+    if let Some(path) = &config.food_script {
+        engine.food_script = load_food_script(path, engine.grid_size).unwrap_or_else(|e| {
+            eprintln!("Failed to load food script: {}", e);
+            std::process::exit(1);
+        });
+    }
+}
+
+/// Size of the action space: 8 with `--diagonals`, the 4 orthogonal moves otherwise
+fn num_actions(config: &Config) -> usize {
+    if config.diagonals {
+        ACTIONS.len()
+    } else {
+        ORTHOGONAL_ACTIONS
+    }
+}
+
+/// Training engine on the configured board (the curriculum's current grid size, if any) with
+/// the configured environment. With a `seed` it draws from a stream distinct from the agent's.
+fn trainer_engine(config: &Config, seed: Option<u64>, gamma: f32) -> SnakeEngine {
+    let grid_size = config.curriculum.as_ref().map_or(GRID_SIZE, |c| c.grid_size());
+    let mut engine = configured_engine(config, grid_size, gamma);
+    if let Some(seed) = seed {
+        engine.seed(seed ^ 0x5EED_F00D);
+    }
+    engine
+}
+
+/// Unseeded engine on a `grid_size` board with the configured environment
+fn configured_engine(config: &Config, grid_size: i32, gamma: f32) -> SnakeEngine {
+    let mut engine = SnakeEngine::new(grid_size);
+    configure_engine(config, &mut engine, gamma);
+    engine
+}
+
+/// Engines for evaluating `agent` on a `grid_size` board: the configured environment with the
+/// action space the model was trained on, so a seed logged in training replays that episode
+fn eval_engine<'a>(
+    config: &'a Config,
+    agent: &'a DQNAgent,
+    grid_size: i32,
+) -> impl Fn() -> SnakeEngine + Sync + 'a {
+    move || {
+        let mut engine = configured_engine(config, grid_size, agent.gamma);
+        engine.diagonals = agent.num_actions() == ACTIONS.len();
+        engine
+    }
+}
+
+/// Creates `--out-dir` if set, exiting on failure
+fn create_out_dir(config: &Config) {
+    if let Some(dir) = &config.out_dir {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+            eprintln!("Failed to create output directory {}: {}", dir, e);
+            std::process::exit(1);
+        });
+    }
+}
+
+/// Best score and rolling Avg(100) of a trainer's finished episodes, for its progress table
+struct Progress {
+    recent: VecDeque<i32>,
+    max_score: i32,
+    print_every: u64,
+}
+
+impl Progress {
+    fn new(config: &Config) -> Self {
+        Progress {
+            recent: VecDeque::with_capacity(100),
+            max_score: 0,
+            print_every: config.print_every,
+        }
+    }
+
+    /// Records `episode`'s score; returns the Avg(100) when the episode gets a table row
+    /// (the first and every `--print-every`th)
+    fn record(&mut self, episode: u64, score: i32) -> Option<f32> {
+        self.max_score = self.max_score.max(score);
+        if self.recent.len() == 100 {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(score);
+        (episode.is_multiple_of(self.print_every) || episode == 1)
+            .then(|| self.recent.iter().sum::<i32>() as f32 / self.recent.len() as f32)
+    }
+}
+
+/// One transition of a training episode, as `run_training_episode` hands it to a learner
+struct Step<'a> {
+    state: Vec<f32>,
+    action: usize,
+    reward: f32,
+    /// The episode ended here, by dying or by `--truncate-at`
+    done: bool,
+    /// The episode was cut off rather than ended: the return still bootstraps from `next_state`
+    truncated: bool,
+    next_state: &'a [f32],
+}
+
+/// Plays one episode on `engine` from a reset: `act` picks each action from the current
+/// features and `learn` sees every transition, the last one with `done` set
+fn run_training_episode<A>(
+    agent: &mut A,
+    engine: &mut SnakeEngine,
+    features: &FeatureSet,
+    scratch: &mut Scratch,
+    act: impl Fn(&mut A, &[f32]) -> usize,
+    mut learn: impl FnMut(&mut A, Step),
+) {
+    let mut state = Vec::new();
+    let mut next_state = Vec::new();
+    engine.reset();
+    features.extract(engine, &mut state, scratch);
+    loop {
+        let action = act(agent, &state);
+        let (reward, done) = engine.step(action);
+        features.extract(engine, &mut next_state, scratch);
+        let step = Step {
+            state: std::mem::take(&mut state),
+            action,
+            reward,
+            done,
+            truncated: engine.truncated,
+            next_state: &next_state,
+        };
+        learn(agent, step);
+        if done {
+            return;
+        }
+        std::mem::swap(&mut state, &mut next_state);
+    }
+}
+
+/// `--agent tabular`: trains the tabular Q-learning baseline in the configured environment,
+/// then evaluates it greedily
+fn train_tabular(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut agent = tabular::TabularAgent::new(num_actions, config.seed);
+    let mut engine = trainer_engine(config, config.seed, agent.gamma);
+    let mut scratch = Scratch::default();
+
+    println!("=== Snake tabular Q-learning baseline ===");
+    println!(
+        "Grid: {}x{} | 11-bit state x {} actions | Episodes: {} | alpha={} gamma={}",
+        engine.grid_size, engine.grid_size, num_actions, config.episodes, agent.alpha, agent.gamma
+    );
+    println!(
+        "{:<10} {:<8} {:<8} {:<10} {:<10} {:<8}",
+        "Episode", "Score", "Max", "Avg(100)", "Epsilon", "States"
+    );
+    println!("{}", "-".repeat(58));
+    let start = Instant::now();
+    let mut progress = Progress::new(config);
+    for episode in 1..=config.episodes {
+        let score = agent.train_episode(&mut engine, &mut scratch);
+        if let Some(avg) = progress.record(episode, score) {
+            println!(
+                "{:<10} {:<8} {:<8} {:<10.1} {:<10.4} {:<8}",
+                episode,
+                score,
+                progress.max_score,
+                avg,
+                agent.epsilon,
+                agent.visited_states()
+            );
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    if let Some(seed) = config.eval_options.seed {
+        engine.seed(seed);
+    }
+    let stats = EvalStats {
+        scores: (0..config.eval_options.episodes)
+            .map(|_| agent.play_greedy(&mut engine, &mut scratch))
+            .collect(),
+    };
+    print_eval("tabular (greedy)", &stats, &config.eval_options);
+}
+
+/// `--algo ppo`: trains a PPO actor-critic in the configured environment, updating after every
+/// `ppo::ROLLOUT_STEPS` steps, then evaluates the most probable action and exports the policy
+fn train_ppo(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut agent = ppo::PpoAgent::new(num_actions, config.seed);
+    let mut engine = trainer_engine(config, config.seed, agent.gamma);
+    create_out_dir(config);
+    let mut scratch = Scratch::default();
+
+    println!("=== Snake PPO (actor-critic) ===");
+    println!(
+        "Grid: {}x{} | Episodes: {} | rollout={} gamma={} lambda={} clip={} lr={}",
+        engine.grid_size,
+        engine.grid_size,
+        config.episodes,
+        ppo::ROLLOUT_STEPS,
+        agent.gamma,
+        agent.lambda,
+        agent.loss.clip,
+        agent.learning_rate
+    );
+    println!(
+        "{:<10} {:<8} {:<8} {:<10} {:<8}",
+        "Episode", "Score", "Max", "Avg(100)", "Updates"
+    );
+    println!("{}", "-".repeat(48));
+    let start = Instant::now();
+    let mut progress = Progress::new(config);
+    let mut updates = 0;
+    let features = agent.features;
+    for episode in 1..=config.episodes {
+        let act = |agent: &mut ppo::PpoAgent, state: &[f32]| agent.act(state);
+        let learn = |agent: &mut ppo::PpoAgent, step: Step| {
+            agent.remember(step.reward, step.done, step.truncated, step.next_state);
+            if agent.rollout_len() == ppo::ROLLOUT_STEPS {
+                agent.update(step.next_state);
+                updates += 1;
+            }
+        };
+        run_training_episode(&mut agent, &mut engine, features, &mut scratch, act, learn);
+        if let Some(avg) = progress.record(episode, engine.score) {
+            println!(
+                "{:<10} {:<8} {:<8} {:<10.1} {:<8}",
+                episode, engine.score, progress.max_score, avg, updates
+            );
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    eval_and_export_policy(config, "ppo", &agent.policy, &mut engine, &mut scratch);
+}
+
+/// `--algo a2c`: trains a synchronous advantage actor-critic on `--a2c-envs` environments
+/// stepped in lockstep, updating every `a2c::ROLLOUT_STEPS` steps, then evaluates the most
+/// probable action and exports the policy. `--episodes` counts episodes over all environments.
+fn train_a2c(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut agent = a2c::A2cAgent::new(num_actions, config.a2c_envs, config.seed);
+    let mut engines: Vec<SnakeEngine> = (0..config.a2c_envs)
+        .map(|env| {
+            let seed = config.seed.map(|seed| eval::derive_seed(seed, env as u64));
+            let mut engine = trainer_engine(config, seed, agent.gamma);
+            engine.reset();
+            engine
+        })
+        .collect();
+    create_out_dir(config);
+    let mut scratch = Scratch::default();
+
+    println!("=== Snake A2C (advantage actor-critic) ===");
+    println!(
+        "Grid: {}x{} | Episodes: {} | envs={} rollout={} gamma={} lr={}",
+        engines[0].grid_size,
+        engines[0].grid_size,
+        config.episodes,
+        config.a2c_envs,
+        a2c::ROLLOUT_STEPS,
+        agent.gamma,
+        agent.learning_rate
+    );
+    println!(
+        "{:<10} {:<8} {:<8} {:<10} {:<8}",
+        "Episode", "Score", "Max", "Avg(100)", "Updates"
+    );
+    println!("{}", "-".repeat(48));
+    let start = Instant::now();
+    let mut progress = Progress::new(config);
+    let (mut episode, mut updates) = (0, 0);
+    let mut states: Vec<Vec<f32>> = engines
+        .iter()
+        .map(|engine| {
+            let mut state = Vec::new();
+            agent.features.extract(engine, &mut state, &mut scratch);
+            state
+        })
+        .collect();
+    while episode < config.episodes {
+        for (env, engine) in engines.iter_mut().enumerate() {
+            let action = agent.act(env, &states[env]);
+            let (reward, done) = engine.step(action);
+            agent.features.extract(engine, &mut states[env], &mut scratch);
+            agent.remember(env, reward, done, engine.truncated, &states[env]);
+            if !done {
+                continue;
+            }
+            episode += 1;
+            if let Some(avg) = progress.record(episode, engine.score) {
+                println!(
+                    "{:<10} {:<8} {:<8} {:<10.1} {:<8}",
+                    episode, engine.score, progress.max_score, avg, updates
+                );
+            }
+            engine.reset();
+            agent.features.extract(engine, &mut states[env], &mut scratch);
+        }
+        if agent.rollout_len() == a2c::ROLLOUT_STEPS {
+            agent.update(&states);
+            updates += 1;
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    let engine = &mut engines[0];
+    eval_and_export_policy(config, "a2c", &agent.policy, engine, &mut scratch);
+}
+
+/// `--algo sac`: trains a discrete soft actor-critic in the configured environment, one update
+/// per step once its replay warmup is stored, then evaluates the most probable action and
+/// exports the policy
+fn train_sac(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut agent = sac::SacAgent::new(num_actions, config.seed);
+    let mut engine = trainer_engine(config, config.seed, agent.gamma);
+    create_out_dir(config);
+    let mut scratch = Scratch::default();
+
+    println!("=== Snake discrete SAC ===");
+    println!(
+        "Grid: {}x{} | Episodes: {} | gamma={} lr={} tau={} target entropy={:.3}",
+        engine.grid_size,
+        engine.grid_size,
+        config.episodes,
+        agent.gamma,
+        agent.learning_rate,
+        agent.tau,
+        agent.target_entropy
+    );
+    println!(
+        "{:<10} {:<8} {:<8} {:<10} {:<8}",
+        "Episode", "Score", "Max", "Avg(100)", "Alpha"
+    );
+    println!("{}", "-".repeat(48));
+    let start = Instant::now();
+    let mut progress = Progress::new(config);
+    let features = agent.features;
+    for episode in 1..=config.episodes {
+        let act = |agent: &mut sac::SacAgent, state: &[f32]| agent.act(state);
+        let learn = |agent: &mut sac::SacAgent, step: Step| {
+            agent.remember(Experience {
+                state: step.state,
+                action: step.action,
+                reward: step.reward,
+                next_state: step.next_state.to_vec(),
+                done: step.done && !step.truncated,
+                span: 1,
+                next_action: None,
+            })
+        };
+        run_training_episode(&mut agent, &mut engine, features, &mut scratch, act, learn);
+        if let Some(avg) = progress.record(episode, engine.score) {
+            println!(
+                "{:<10} {:<8} {:<8} {:<10.1} {:<8.4}",
+                episode,
+                engine.score,
+                progress.max_score,
+                avg,
+                agent.alpha()
+            );
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    eval_and_export_policy(config, "sac", &agent.policy, &mut engine, &mut scratch);
+}
+
+/// `--algo reinforce`: trains REINFORCE with a value baseline in the configured environment,
+/// learning after every episode, then evaluates the most probable action and exports the policy
+fn train_reinforce(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut agent = reinforce::ReinforceAgent::new(num_actions, config.seed);
+    let mut engine = trainer_engine(config, config.seed, agent.gamma);
+    create_out_dir(config);
+    let mut scratch = Scratch::default();
+
+    println!("=== Snake REINFORCE with baseline ===");
+    println!(
+        "Grid: {}x{} | Episodes: {} | gamma={} lr={}",
+        engine.grid_size, engine.grid_size, config.episodes, agent.gamma, agent.learning_rate
+    );
+    println!("{:<10} {:<8} {:<8} {:<10}", "Episode", "Score", "Max", "Avg(100)");
+    println!("{}", "-".repeat(39));
+    let start = Instant::now();
+    let mut progress = Progress::new(config);
+    let features = agent.features;
+    for episode in 1..=config.episodes {
+        let act = |agent: &mut reinforce::ReinforceAgent, state: &[f32]| agent.act(state);
+        let learn = |agent: &mut reinforce::ReinforceAgent, step: Step| {
+            agent.remember(step.reward, step.done, step.truncated, step.next_state)
+        };
+        run_training_episode(&mut agent, &mut engine, features, &mut scratch, act, learn);
+        if let Some(avg) = progress.record(episode, engine.score) {
+            let max_score = progress.max_score;
+            println!("{:<10} {:<8} {:<8} {:<10.1}", episode, engine.score, max_score, avg);
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    eval_and_export_policy(config, "reinforce", &agent.policy, &mut engine, &mut scratch);
+}
+
+/// `--algo es`: evolves a greedy policy network with an evolution strategy for
+/// `--es-generations` generations, evaluating each population across all cores, then
+/// evaluates and exports the fittest candidate seen
+fn train_es(config: &Config) {
+    let num_actions = num_actions(config);
+    let mut es = es::EvolutionStrategy::new(
+        num_actions,
+        config.es_population,
+        config.es_sigma,
+        config.seed,
+    );
+    es.episodes = config.es_episodes;
+    // Fitness is the undiscounted return, which potential shaping leaves unchanged at gamma 1
+    let make_engine = || trainer_engine(config, config.seed, 1.0);
+    create_out_dir(config);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let grid_size = make_engine().grid_size;
+
+    println!("=== Snake evolution strategy ===");
+    println!(
+        "Grid: {}x{} | Generations: {} | population={} sigma={} lr={} episodes/candidate={}",
+        grid_size,
+        grid_size,
+        config.es_generations,
+        es.population,
+        es.sigma,
+        es.learning_rate,
+        es.episodes
+    );
+    println!(
+        "{:<10} {:<12} {:<12} {:<12} {:<12}",
+        "Gen", "MeanFit", "TopFit", "MeanScore", "BestEver"
+    );
+    println!("{}", "-".repeat(58));
+    let start = Instant::now();
+    for generation in 1..=config.es_generations {
+        let candidates = es.generation(&make_engine, threads);
+        let n = candidates.len() as f32;
+        let mean_fitness = candidates.iter().map(|c| c.fitness).sum::<f32>() / n;
+        let top = candidates.iter().map(|c| c.fitness).fold(f32::NEG_INFINITY, f32::max);
+        let mean_score = candidates.iter().map(|c| c.mean_score).sum::<f32>() / n;
+        if generation % config.print_every == 0 || generation == 1 {
+            println!(
+                "{:<10} {:<12.2} {:<12.2} {:<12.1} {:<12.2}",
+                generation,
+                mean_fitness,
+                top,
+                mean_score,
+                es.best_fitness().unwrap_or(top)
+            );
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    let mut engine = make_engine();
+    let mut scratch = Scratch::default();
+    eval_and_export_policy(config, "es (best)", es.best(), &mut engine, &mut scratch);
+}
+
+/// `--algo ga`: evolves a population of greedy policy networks with a genetic algorithm for
+/// `--ga-generations` generations, evaluating each population across all cores, then
+/// evaluates and exports the fittest member seen
+fn train_ga(config: &Config) {
+    let num_actions = num_actions(config);
+    let (size, mutation) = (config.ga_population, config.ga_mutation);
+    let mut ga = ga::GeneticAlgorithm::new(num_actions, size, mutation, config.seed);
+    ga.episodes = config.ga_episodes;
+    let make_engine = || trainer_engine(config, config.seed, 1.0);
+    create_out_dir(config);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let grid_size = make_engine().grid_size;
+
+    println!("=== Snake genetic algorithm ===");
+    println!(
+        "Grid: {}x{} | Generations: {} | population={} mutation sigma={} episodes/member={}",
+        grid_size,
+        grid_size,
+        config.ga_generations,
+        ga.population.len(),
+        ga.mutation_sigma,
+        ga.episodes
+    );
+    println!("{:<10} {:<12} {:<12} {:<12}", "Gen", "MeanScore", "TopScore", "BestEver");
+    println!("{}", "-".repeat(46));
+    let start = Instant::now();
+    for generation in 1..=config.ga_generations {
+        let members = ga.generation(&make_engine, threads);
+        let mean = members.iter().map(|c| c.mean_score).sum::<f32>() / members.len() as f32;
+        let top = members.iter().map(|c| c.mean_score).fold(0.0, f32::max);
+        if generation % config.print_every == 0 || generation == 1 {
+            println!(
+                "{:<10} {:<12.1} {:<12.1} {:<12.1}",
+                generation,
+                mean,
+                top,
+                ga.best_fitness().unwrap_or(top)
+            );
+        }
+    }
+    println!("Done. Total time: {:?}", start.elapsed());
+
+    let mut engine = make_engine();
+    let mut scratch = Scratch::default();
+    eval_and_export_policy(config, "ga (best)", ga.best(), &mut engine, &mut scratch);
+}
+
+/// Greedy eval of a policy network's most probable action over `--eval-episodes`, then its
+/// export as `model_final.json`
+fn eval_and_export_policy(
+    config: &Config,
+    label: &str,
+    policy: &nn::Network,
+    engine: &mut SnakeEngine,
+    scratch: &mut Scratch,
+) {
+    if let Some(seed) = config.eval_options.seed {
+        engine.seed(seed);
+    }
+    let mut act = |_: &SnakeEngine, features: &[f32]| ppo::most_probable(policy, features);
+    let features = features::current_feature_set();
+    let stats = EvalStats {
+        scores: (0..config.eval_options.episodes)
+            .map(|_| eval::run_episode(engine, features, scratch, &mut act))
+            .collect(),
+    };
+    print_eval(&format!("{} (greedy)", label), &stats, &config.eval_options);
+    let final_name = artifact_path(config, "model_final.json", "");
+    save_model(config, &ppo::policy_agent(policy), &engine.mode(), &final_name);
+}
+
+/// Full training run per seed, then per-seed final Avg(100) and their mean / std
+fn seed_sweep(config: &Config, seeds: &[u64]) {
+    let (finals, mean, std) = sweep_seeds(seeds, |seed| {
+        println!("=== Seed {} ===", seed);
+        train(config, Some(seed), &format!("seed{}_", seed))
     });
 
-    std::fs::write(filename, serde_json::to_string(&export).unwrap()).unwrap();
+    println!("=== Seed sweep ===");
+    for (seed, avg) in seeds.iter().zip(finals.iter()) {
+        println!("Seed {:<10} final Avg(100): {:.1}", seed, avg);
+    }
+    println!("Mean: {:.2} | Std: {:.2} | Runs: {}", mean, std, finals.len());
 }
 
-fn main() {
-    let config = parse_args();
+/// Runs `train` once per seed, in order; returns each run's final Avg(100) with their mean
+/// and (population) std
+fn sweep_seeds(seeds: &[u64], train: impl FnMut(u64) -> f32) -> (Vec<f32>, f32, f32) {
+    let finals: Vec<f32> = seeds.iter().copied().map(train).collect();
+    let n = finals.len() as f32;
+    let mean = finals.iter().sum::<f32>() / n;
+    let std = (finals.iter().map(|f| (f - mean).powi(2)).sum::<f32>() / n).sqrt();
+    (finals, mean, std)
+}
+
+/// Food positions, one `X,Y` per line (blank lines and `#` comments ignored), all of which
+/// must lie inside a `grid_size` board
+fn load_food_script(path: &str, grid_size: i32) -> Result<Vec<Point>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut points = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let point = line.split_once(',').and_then(|(x, y)| {
+            Some(Point {
+                x: x.trim().parse().ok()?,
+                y: y.trim().parse().ok()?,
+            })
+        });
+        match point {
+            Some(p) if p.x >= 0 && p.x < grid_size && p.y >= 0 && p.y < grid_size => points.push(p),
+            Some(_) => return Err(format!("{}:{}: {} is out of bounds", path, n + 1, line)),
+            None => return Err(format!("{}:{}: expected X,Y, got {}", path, n + 1, line)),
+        }
+    }
+    if points.is_empty() {
+        return Err(format!("{}: no positions", path));
+    }
+    Ok(points)
+}
+
+/// Feature vectors from `path`, one per line as comma-separated values (`#` starts a comment),
+/// each with `size` entries
+fn load_states(path: &str, size: usize) -> Result<Vec<Vec<f32>>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut states = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let state: Vec<f32> = line
+            .split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{}:{}: expected comma-separated numbers", path, n + 1))?;
+        if state.len() != size {
+            return Err(format!(
+                "{}:{}: {} values, the model expects {}",
+                path,
+                n + 1,
+                state.len(),
+                size
+            ));
+        }
+        states.push(state);
+    }
+    if states.is_empty() {
+        return Err(format!("{}: no states", path));
+    }
+    Ok(states)
+}
+
+/// Where a run writes artifact `path`: under `--out-dir` when set (relative paths only), with
+/// `<run-name>_` and then `prefix` prepended to its file name
+fn artifact_path(config: &Config, path: &str, prefix: &str) -> String {
+    let mut p = std::path::PathBuf::from(path);
+    if let Some(dir) = config.out_dir.as_ref().filter(|_| p.is_relative()) {
+        p = std::path::Path::new(dir).join(p);
+    }
+    let run = config.run_name.as_ref().map_or(String::new(), |name| format!("{}_", name));
+    match p.file_name() {
+        Some(name) => p
+            .with_file_name(format!("{}{}{}", run, prefix, name.to_string_lossy()))
+            .to_string_lossy()
+            .into_owned(),
+        None => p.to_string_lossy().into_owned(),
+    }
+}
+
+/// Exports `agent` to `filename` (TF.js JSON), plus a `.npz` copy of the weights with
+/// `--export-format npz`
+fn save_model(config: &Config, agent: &DQNAgent, env: &EnvMode, filename: &str) {
+    export_model(agent, env, filename);
+    if config.export_npz {
+        let npz = std::path::Path::new(filename).with_extension("npz");
+        export_npz(agent, &npz.to_string_lossy());
+    }
+}
+
+/// Environment seed for `episode` with `--log-episode-seeds`: derived from the run seed when
+/// there is one, so a seeded run logs the same seeds every time
+fn episode_seed(run_seed: Option<u64>, episode: u64) -> u64 {
+    match run_seed {
+        Some(run_seed) => eval::derive_seed(run_seed ^ 0xE915_0DE5, episode),
+        None => rand::random(),
+    }
+}
+
+/// Whether `episode` (1-based) is one `--log-trajectory-every` samples: every `every`th
+/// episode, none when `every` is 0
+fn logs_trajectory(every: u64, episode: u64) -> bool {
+    every > 0 && episode.is_multiple_of(every)
+}
+
+/// Banner line on how far ahead `gamma` lets the agent look, in engine steps: the effective
+/// horizon 1/(1-gamma), the reward half-life, and where future rewards drop below 1% weight,
+/// next to the largest head-to-food distance on the board for scale
+fn horizon_summary(gamma: f32, action_repeat: u32, grid_size: i32) -> String {
+    if gamma >= 1.0 {
+        return format!("Horizon: unbounded (gamma = {})", gamma);
+    }
+    // gamma discounts per decision; with action repeat each decision spans several steps
+    let steps = action_repeat.max(1) as f32;
+    let horizon = steps / (1.0 - gamma);
+    let half_life = steps * 0.5f32.ln() / gamma.ln();
+    let one_percent = steps * 0.01f32.ln() / gamma.ln();
+    format!(
+        "Horizon: ~{:.0} steps (1/(1-gamma), gamma = {}) | half-life {:.0} steps | <1% weight beyond {:.0} steps | board crossing {} steps",
+        horizon,
+        gamma,
+        half_life,
+        one_percent,
+        2 * (grid_size - 1)
+    )
+}
+
+/// The periodic stats row as a logfmt line (`--stats-format logfmt`)
+fn stats_logfmt(
+    episode: u64,
+    score: i32,
+    max_score: i32,
+    avg: f32,
+    agent: &DQNAgent,
+    elapsed_secs: u64,
+) -> String {
+    logger::logfmt(&[
+        ("episode", episode.to_string()),
+        ("score", score.to_string()),
+        ("max", max_score.to_string()),
+        ("avg", format!("{:.2}", avg)),
+        ("epsilon", format!("{:.4}", agent.epsilon)),
+        ("lr", format!("{:.6}", agent.learning_rate)),
+        ("buffer", agent.buffer_len().to_string()),
+        ("elapsed_s", elapsed_secs.to_string()),
+    ])
+}
+
+/// One full training run; returns the final Avg(100). Artifacts go to `artifact_path`, with
+/// `prefix` prepended to their file names.
+fn train(config: &Config, seed: Option<u64>, prefix: &str) -> f32 {
     let num_episodes = config.episodes;
     let print_every = config.print_every;
     let save_every = config.save_every;
 
-    let mut agent = DQNAgent::new();
-    let mut engine = SnakeEngine::new(GRID_SIZE);
+    let num_actions = num_actions(config);
+    let mut agent = match (config.distribution, seed) {
+        (Some(distribution), seed) => {
+            DQNAgent::distributional(num_actions, distribution, config.dueling, seed)
+        }
+        (None, Some(seed)) => DQNAgent::with_seed(seed, num_actions, config.dueling),
+        (None, None) => DQNAgent::new(num_actions, config.dueling),
+    };
+    if config.noisy_nets {
+        agent.enable_noisy_nets();
+    }
+    let mut first_episode = 1;
+    let mut resumed_env = None;
+    let mut resumed_engine_rng = None;
+    let mut resumed_best = None;
+    if let Some(path) = &config.resume {
+        let checkpoint = load_checkpoint(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load checkpoint: {}", e);
+            std::process::exit(1);
+        });
+        if checkpoint.distribution != config.distribution {
+            eprintln!(
+                "Checkpoint {} has a different output distribution than this run (check --c51, \
+                 --c51-v-min/--c51-v-max, --qr-dqn and --quantiles)",
+                path
+            );
+            std::process::exit(1);
+        }
+        let per_action = checkpoint.distribution.map_or(1, |d| d.outputs_per_action());
+        if checkpoint.network.output_size() != num_actions * per_action {
+            eprintln!(
+                "Checkpoint {} has {} actions, this run uses {} (check --diagonals)",
+                path,
+                checkpoint.network.output_size() / per_action,
+                num_actions
+            );
+            std::process::exit(1);
+        }
+        if checkpoint.network.is_dueling() != config.dueling {
+            eprintln!(
+                "Checkpoint {} {} a dueling network (check --dueling)",
+                path,
+                if checkpoint.network.is_dueling() { "has" } else { "doesn't have" }
+            );
+            std::process::exit(1);
+        }
+        if checkpoint.network.is_noisy() != config.noisy_nets {
+            eprintln!(
+                "Checkpoint {} {} noisy layers (check --noisy-nets)",
+                path,
+                if checkpoint.network.is_noisy() { "has" } else { "doesn't have" }
+            );
+            std::process::exit(1);
+        }
+        let features = feature_set(checkpoint.feature_version, checkpoint.network.input_size())
+            .unwrap_or_else(|e| {
+                eprintln!("Checkpoint {}: {}", path, e);
+                std::process::exit(1);
+            });
+        if let Some(rule) = checkpoint.target_rule.filter(|&rule| rule != config.target_rule) {
+            println!(
+                ">>> Checkpoint {} was trained with the {:?} target rule, continuing with {:?}",
+                path, rule, config.target_rule
+            );
+        }
+        first_episode = checkpoint.episode + 1;
+        resumed_env = checkpoint.env.clone();
+        resumed_engine_rng = checkpoint.engine_rng.clone();
+        resumed_best = checkpoint.best.clone();
+        agent.restore(checkpoint);
+        agent.features = features;
+        println!(">>> Resumed: {} at episode {}", path, first_episode);
+    }
+    agent.terminal_fraction = config.terminal_fraction;
+    agent.pure_random_steps = config.pure_random_steps;
+    agent.target_update_warmup = config.target_update_warmup;
+    agent.warmup_heuristic_ratio = config.warmup_heuristic_ratio;
+    agent.max_sample_age = config.max_sample_age;
+    agent.lazy_priority_recompute = config.lazy_priority_recompute;
+    agent.target_clamp = config.clamp_target;
+    agent.set_target_rule(config.target_rule);
+    agent.n_step = config.n_step;
+    agent.update_per_episode = config.update_per_episode;
+    if config.normalize_obs && agent.obs_norm.is_none() {
+        agent.obs_norm = Some(ObsNormalizer::new(agent.features.size));
+    }
+    set_feature_mask(&mut agent, &config.mask_features);
+    let mut curriculum = config.curriculum.clone();
+    let mut engine = trainer_engine(config, seed, agent.gamma);
+    let grid_size = engine.grid_size;
+    if let Some(path) = &config.resume {
+        warn_env_mismatch(path, Ok(resumed_env), &engine.mode());
+    }
+    if let Some(rng) = resumed_engine_rng {
+        engine.set_rng(rng);
+    }
+
+    create_out_dir(config);
+
+    let mut log = config.log_file.as_ref().map(|path| {
+        let path = &artifact_path(config, path, prefix);
+        let mut log = MetricsLog::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to create log {}: {}", path, e);
+            std::process::exit(1);
+        });
+        log.rotate_bytes = config.log_rotate_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+        log.rotate_episodes = config.log_rotate_episodes;
+        log
+    });
+
+    let mut seed_log = config.episode_seeds_file.as_ref().map(|path| {
+        let path = &artifact_path(config, path, prefix);
+        MetricsLog::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to create episode seed log {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+
+    let mut trajectory_log = (config.log_trajectory_every > 0).then(|| {
+        let path = &artifact_path(config, &config.trajectory_file, prefix);
+        MetricsLog::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to create trajectory log {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+
+    let mut heatmap = config.visitation_heatmap.as_ref().map(|_| {
+        let largest = curriculum
+            .as_ref()
+            .and_then(|c| c.grid_sizes.iter().copied().max())
+            .unwrap_or(grid_size);
+        tools::VisitHeatmap::new(largest)
+    });
 
     let mut max_score: i32 = 0;
-    let mut best_avg: f32 = 0.0;
-    let mut recent_scores: VecDeque<i32> = VecDeque::new();
+    let mut best = resumed_best.unwrap_or_default();
+    let mut milestones = tools::Milestones::new(config.milestones.clone());
+    // Environment steps this run (counting every repeated step with --action-repeat)
+    let mut env_steps: u64 = 0;
+    let mut overestimation = config.overestimation_stats.then(OverestimationStats::default);
     let start = Instant::now();
 
-    println!("=== Snake DQN Trainer (Rust) ===");
-    println!(
-        "Grid: {}x{} | MLP 28→256→64→4 | Episodes: {} | DoubleDQN soft_tau=0.001 LR_decay",
-        GRID_SIZE, GRID_SIZE, num_episodes
-    );
+    // Feature buffers reused across steps
+    let mut scratch = Scratch::default();
+    let mut state: Vec<f32> = Vec::with_capacity(agent.features.size);
+    let mut next_state: Vec<f32> = Vec::with_capacity(agent.features.size);
+
+    match &config.run_name {
+        Some(name) => println!("=== Snake DQN Trainer (Rust): {} ===", name),
+        None => println!("=== Snake DQN Trainer (Rust) ==="),
+    }
+    let dueling = if agent.network.is_dueling() { ", dueling" } else { "" };
+    let head = match agent.distribution() {
+        Some(ReturnDistribution::Categorical(support)) => {
+            format!("{}x{} (C51{})", num_actions, support.atoms, dueling)
+        }
+        Some(ReturnDistribution::Quantile(n)) => {
+            format!("{}x{} (QR-DQN{})", num_actions, n, dueling)
+        }
+        None if agent.network.is_dueling() => format!("{} (dueling)", num_actions),
+        None => num_actions.to_string(),
+    };
     println!(
-        "{:<10} {:<8} {:<8} {:<10} {:<10} {:<10} {:<8} {:<10}",
-        "Episode", "Score", "Max", "Avg(100)", "Epsilon", "LR", "Buffer", "Time"
+        "Grid: {}x{} | Features v{} | MLP {}→256→64→{} | Episodes: {} | {}{} soft_tau=0.001 LR_decay",
+        grid_size,
+        grid_size,
+        agent.features.version,
+        agent.features.size,
+        head,
+        num_episodes,
+        match agent.target_rule() {
+            TargetRule::Vanilla => "DQN",
+            TargetRule::Double => "DoubleDQN",
+            TargetRule::ClippedDouble => "ClippedDoubleDQN",
+            TargetRule::Sarsa => "SARSA",
+            TargetRule::ExpectedSarsa => "ExpectedSARSA",
+        },
+        if agent.is_noisy() { " NoisyNet" } else { "" }
     );
-    println!("{}", "-".repeat(78));
+    println!("{}", horizon_summary(agent.gamma, config.action_repeat, grid_size));
+    if config.pretrain_steps > 0 && config.resume.is_none() {
+        let (states, labels) = heuristic::heuristic_dataset(
+            grid_size,
+            config.diagonals,
+            agent.features,
+            PRETRAIN_STATES,
+            seed.unwrap_or_else(rand::random),
+        );
+        agent.pretrain(&states, &labels, config.pretrain_steps);
+        let agree = states.iter().zip(&labels).filter(|(s, &a)| agent.act_greedy(s) == a).count();
+        println!(
+            ">>> Pretrained on the heuristic: {} steps | agreement {:.1}% on {} states",
+            config.pretrain_steps,
+            100.0 * agree as f32 / states.len() as f32,
+            states.len()
+        );
+    }
+    if !config.stats_logfmt {
+        println!(
+            "{:<10} {:<8} {:<8} {:<10} {:<10} {:<10} {:<8} {:<10}",
+            "Episode", "Score", "Max", "Avg(100)", "Epsilon", "LR", "Buffer", "Time"
+        );
+        println!("{}", "-".repeat(78));
+    }
 
-    for episode in 1..=num_episodes {
+    for episode in first_episode..=num_episodes {
+        if config.food_bias_open {
+            engine.food_open_bias = if config.food_bias_anneal > 0 {
+                (1.0 - (episode - 1) as f32 / config.food_bias_anneal as f32).max(0.0)
+            } else {
+                1.0
+            };
+        }
+        if config.no_corner_food {
+            engine.corner_food_exclusion = if config.no_corner_food_anneal > 0 {
+                (1.0 - (episode - 1) as f32 / config.no_corner_food_anneal as f32).max(0.0)
+            } else {
+                1.0
+            };
+        }
+        let episode_seed = seed_log.as_ref().map(|_| episode_seed(seed, episode));
+        if let Some(episode_seed) = episode_seed {
+            engine.seed(episode_seed);
+        }
         engine.reset();
-        let mut state = extract_features(&engine);
+        agent.features.extract(&engine, &mut state, &mut scratch);
+        if episode == first_episode {
+            // Fail before the first act/remember rather than deep inside a forward pass
+            if let Err(e) = agent.check_input(&state) {
+                eprintln!("Feature/network mismatch: {}", e);
+                std::process::exit(1);
+            }
+        }
+        let mut steps: u64 = 0;
+        // Summed in f64: long episodes add many small shaping rewards, which f32 would round away
+        let mut episode_return: f64 = 0.0;
+        // Transitions of this episode, collected only when it's one of the sampled ones
+        let mut trajectory: Option<Vec<serde_json::Value>> =
+            logs_trajectory(config.log_trajectory_every, episode).then(Vec::new);
         loop {
-            let action = agent.act(&state);
-            let (reward, done) = engine.step(action);
-            let next_state = extract_features(&engine);
+            steps += 1;
+            let action = agent.act_guided(&state, || heuristic::greedy_food_action(&engine));
+            let predicted_q = overestimation.as_ref().map(|_| agent.q_values(&state));
+            let (reward, done, span) =
+                engine.step_repeat(action, config.action_repeat, agent.gamma);
+            episode_return += reward as f64;
+            // Sticky actions may have replaced the request; store what actually ran
+            let action = engine.last_action;
+            if let (Some(stats), Some(q)) = (overestimation.as_mut(), predicted_q) {
+                stats.record(q[action], reward, agent.gamma.powi(span as i32));
+            }
+            if let Some(heatmap) = heatmap.as_mut() {
+                heatmap.record_step(&engine);
+            }
+            agent.features.extract(&engine, &mut next_state, &mut scratch);
+
+            if let Some(trajectory) = trajectory.as_mut() {
+                trajectory.push(serde_json::json!({
+                    "state": state,
+                    "action": action,
+                    "reward": reward,
+                    "done": done,
+                    "span": span,
+                }));
+            }
 
             agent.remember(Experience {
                 state: state.clone(),
                 action,
                 reward,
                 next_state: next_state.clone(),
-                done,
+                // Truncated episodes still bootstrap from next_state
+                done: done && !engine.truncated,
+                span,
+                next_action: None,
             });
 
             agent.step_and_train();
-            state = next_state;
+            std::mem::swap(&mut state, &mut next_state);
 
             if done {
                 break;
@@ -179,49 +2080,229 @@ fn main() {
         }
 
         agent.end_episode();
+        if let Some(stats) = overestimation.as_mut() {
+            stats.end_episode(!engine.truncated);
+        }
+
+        if let (Some(log), Some(transitions)) = (trajectory_log.as_mut(), trajectory) {
+            let record = serde_json::json!({ "episode": episode, "transitions": transitions });
+            if let Err(e) = log.write(&record) {
+                eprintln!("Warning: failed to write {}: {}", log.current_path(), e);
+            }
+        }
 
         let score = engine.score;
+        if let (Some(log), Some(episode_seed)) = (seed_log.as_mut(), episode_seed) {
+            let record =
+                serde_json::json!({ "episode": episode, "seed": episode_seed, "score": score });
+            if let Err(e) = log.write(&record) {
+                eprintln!("Warning: failed to write {}: {}", log.current_path(), e);
+            }
+        }
+
+        if let Some(c) = curriculum.as_mut() {
+            if c.record(score, engine.snake.len(), &mut agent.epsilon) {
+                engine.grid_size = c.grid_size();
+                println!(
+                    ">>> Curriculum: stage {}/{} | grid {}x{}",
+                    c.stage + 1,
+                    c.grid_sizes.len(),
+                    engine.grid_size,
+                    engine.grid_size
+                );
+            }
+        }
+
         if score > max_score {
             max_score = score;
         }
 
-        recent_scores.push_back(score);
-        if recent_scores.len() > 100 {
-            recent_scores.pop_front();
+        let avg = best.record(score);
+        env_steps += engine.steps as u64;
+        if best.is_full() {
+            milestones.record(avg, episode, env_steps);
         }
-        let avg = recent_scores.iter().sum::<i32>() as f32 / recent_scores.len() as f32;
+        track_best(config, prefix, &mut agent, &engine, &mut best, episode, avg);
 
-        if recent_scores.len() >= 100 && avg > best_avg {
-            best_avg = avg;
-            export_model(&agent, "model_best.json");
+        if let Some(log) = log.as_mut() {
+            let record = serde_json::json!({
+                "episode": episode,
+                "score": score,
+                "max": max_score,
+                "avg": avg,
+                "steps": steps,
+                "return": episode_return,
+                "truncated": engine.truncated,
+                "epsilon": agent.epsilon,
+                "lr": agent.learning_rate,
+                "buffer": agent.buffer_len(),
+                "grid": engine.grid_size,
+                "secs": start.elapsed().as_secs_f32(),
+            });
+            if let Err(e) = log.write(&record) {
+                eprintln!("Warning: failed to write {}: {}", log.current_path(), e);
+            }
         }
 
         if episode % print_every == 0 || episode == 1 {
             let elapsed = start.elapsed().as_secs();
-            let mins = elapsed / 60;
-            let secs = elapsed % 60;
+            if config.stats_logfmt {
+                println!("{}", stats_logfmt(episode, score, max_score, avg, &agent, elapsed));
+            } else {
+                println!(
+                    "{:<10} {:<8} {:<8} {:<10.1} {:<10.4} {:<10.6} {:<8} {:02}:{:02}",
+                    episode,
+                    score,
+                    max_score,
+                    avg,
+                    agent.epsilon,
+                    agent.learning_rate,
+                    agent.buffer_len(),
+                    elapsed / 60,
+                    elapsed % 60
+                );
+            }
+            if config.target_drift_stats {
+                println!(">>> Target drift: {:.5}", agent.target_drift(256));
+            }
+            if let Some((eval, saved_at)) = best.best_eval {
+                println!(
+                    ">>> Best model: Avg(100) {:.1} | greedy eval {:.1} (saved at episode {})",
+                    best.best_avg, eval, saved_at
+                );
+            }
+            if let Some((bias, n)) = overestimation.as_mut().and_then(|s| s.take_mean()) {
+                println!(">>> Q overestimation: {:+.3} (mean Q - MC return over {} steps)", bias, n);
+            }
+        }
+
+        if config.feature_importance_every > 0 && episode % config.feature_importance_every == 0 {
+            tools::print_feature_importance(&agent.network);
+        }
+
+        if config.action_histogram_every > 0 && episode % config.action_histogram_every == 0 {
+            let counts = agent
+                .replay_buffer()
+                .action_histogram(ACTION_HISTOGRAM_WINDOW, num_actions);
             println!(
-                "{:<10} {:<8} {:<8} {:<10.1} {:<10.4} {:<10.6} {:<8} {:02}:{:02}",
-                episode,
-                score,
-                max_score,
-                avg,
-                agent.epsilon,
-                agent.learning_rate,
-                agent.buffer_len(),
-                mins,
-                secs
+                ">>> Replay actions (last {} transitions): {}",
+                counts.iter().sum::<usize>(),
+                tools::format_action_histogram(&counts)
+            );
+        }
+
+        if config.heuristic_agreement_every > 0 && episode % config.heuristic_agreement_every == 0
+        {
+            let agreement =
+                heuristic::heuristic_agreement(&agent, engine.grid_size, AGREEMENT_STATES, 0);
+            println!(
+                ">>> Heuristic agreement: {:.1}% of {} states",
+                100.0 * agreement,
+                AGREEMENT_STATES
             );
         }
 
         if episode % save_every == 0 {
-            let filename = format!("model_ep{}.json", episode);
-            export_model(&agent, &filename);
-            println!(">>> Saved: {} | Best avg: {:.1}", filename, best_avg);
+            let filename = artifact_path(config, &format!("model_ep{}.json", episode), prefix);
+            save_model(config, &agent, &engine.mode(), &filename);
+            save_checkpoint(
+                &agent,
+                &engine,
+                &best,
+                episode,
+                config.checkpoint_replay,
+                &artifact_path(config, "checkpoint.json", prefix),
+            );
+            println!(">>> Saved: {} | Best avg: {:.1}", filename, best.best_avg);
+            if let Some(keep) = config.keep_checkpoints {
+                prune_checkpoints(&filename, keep);
+            }
         }
     }
 
-    export_model(&agent, "model_final.json");
-    println!(">>> Saved: model_final.json | Best avg: {:.1}", best_avg);
+    for log in log.iter_mut().chain(trajectory_log.iter_mut()).chain(seed_log.iter_mut()) {
+        log.flush().unwrap();
+    }
+
+    if let (Some(out), Some(log)) = (&config.html_report, &log) {
+        let out = artifact_path(config, out, prefix);
+        match logger::read_records(&log.paths()) {
+            Ok(records) => {
+                let title = config.run_name.as_deref().unwrap_or("Snake DQN training");
+                std::fs::write(&out, report::html_report(title, &records)).unwrap();
+                println!(">>> Saved: {}", out);
+            }
+            Err(e) => eprintln!("Warning: no HTML report, failed to read the log: {}", e),
+        }
+    }
+
+    if let (Some(path), Some(heatmap)) = (&config.visitation_heatmap, &heatmap) {
+        let path = artifact_path(config, path, prefix);
+        std::fs::write(&path, heatmap.render()).unwrap();
+        println!(">>> Saved: {}", path);
+    }
+
+    let final_name = artifact_path(config, "model_final.json", prefix);
+    save_model(config, &agent, &engine.mode(), &final_name);
+    save_checkpoint(
+        &agent,
+        &engine,
+        &best,
+        num_episodes,
+        config.checkpoint_replay,
+        &artifact_path(config, "checkpoint.json", prefix),
+    );
+    println!(">>> Saved: {} | Best avg: {:.1}", final_name, best.best_avg);
+    if !config.milestones.is_empty() {
+        milestones.print();
+    }
     println!("Done. Total time: {:?}", start.elapsed());
+
+    best.avg()
+}
+
+/// After `episode`, whose Avg(100) is `avg`: a new best saves model_best.json with its greedy
+/// eval (on the configured environment, always on the same seeds) and, for
+/// --revert-on-regression, a snapshot, which is restored once Avg(100) falls too far below it
+fn track_best(
+    config: &Config,
+    prefix: &str,
+    agent: &mut DQNAgent,
+    engine: &SnakeEngine,
+    best: &mut tools::BestModel,
+    episode: u64,
+    avg: f32,
+) {
+    if best.is_full() && avg > best.best_avg {
+        best.best_avg = avg;
+        let path = artifact_path(config, "model_best.json", prefix);
+        save_model(config, agent, &engine.mode(), &path);
+        if config.best_eval_episodes > 0 {
+            let options = EvalOptions {
+                episodes: config.best_eval_episodes,
+                epsilon: 0.0,
+                temperature: None,
+                seed: Some(config.eval_options.seed.unwrap_or(BEST_EVAL_SEED)),
+                threads: 1,
+            };
+            let make_engine = eval_engine(config, agent, engine.grid_size);
+            let stats = eval::evaluate(agent, &make_engine, &options);
+            best.best_eval = Some((stats.mean(), episode));
+        }
+        if config.revert_on_regression.is_some() {
+            best.snapshot = Some(Box::new(agent.checkpoint(episode)));
+        }
+    }
+
+    if let (Some(drop), Some(snapshot)) = (config.revert_on_regression, &best.snapshot) {
+        if best.is_full() && best.best_avg - avg > drop {
+            println!(
+                ">>> Regression: Avg(100) {:.1} vs best {:.1}, reverting to episode {}",
+                avg, best.best_avg, snapshot.episode
+            );
+            agent.restore(*snapshot.clone());
+            // Judge the restored agent on a fresh window
+            best.clear_window();
+        }
+    }
 }
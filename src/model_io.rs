@@ -0,0 +1,526 @@
+use crate::agent::{Checkpoint, DQNAgent};
+use crate::distributional::ReturnDistribution;
+use crate::engine::{EnvMode, SnakeEngine, ACTIONS, ORTHOGONAL_ACTIONS};
+use crate::features::feature_set;
+use crate::nn::Network;
+use crate::normalize::ObsNormalizer;
+use crate::tools::BestModel;
+use std::collections::HashMap;
+
+/// A dueling network is exported as its folded plain equivalent (see `Network::folded`), so
+/// the TF.js model keeps the same three Dense layers. A C51 or QR-DQN network's last layer
+/// outputs atom logits or quantiles per action; `meta.distribution` says which, and how to turn
+/// them into Q-values.
+pub fn export_model(agent: &DQNAgent, env: &EnvMode, filename: &str) {
+    let network = agent.network.folded();
+    let mut weight_bytes: Vec<u8> = Vec::new();
+    let mut weight_specs: Vec<serde_json::Value> = Vec::new();
+
+    let layer_names = ["dense", "dense_1", "dense_2"];
+    let units: Vec<usize> = (0..network.num_layers())
+        .map(|i| network.layer_info(i).3)
+        .collect();
+
+    for (i, name) in layer_names.iter().enumerate().take(network.num_layers()) {
+        let (weights, biases, in_size, out_size) = network.layer_info(i);
+
+        // Weights: stored as [in_size × out_size] row-major, TF.js expects same layout
+        for &val in weights.iter() {
+            weight_bytes.extend_from_slice(&val.to_le_bytes());
+        }
+        weight_specs.push(serde_json::json!({
+            "name": format!("{}/kernel", name),
+            "shape": [in_size, out_size],
+            "dtype": "float32"
+        }));
+
+        // Biases
+        for &val in biases.iter() {
+            weight_bytes.extend_from_slice(&val.to_le_bytes());
+        }
+        weight_specs.push(serde_json::json!({
+            "name": format!("{}/bias", name),
+            "shape": [out_size],
+            "dtype": "float32"
+        }));
+    }
+
+    let model_topology = serde_json::json!({
+        "class_name": "Sequential",
+        "config": {
+            "name": "sequential",
+            "layers": [
+                {
+                    "class_name": "Dense",
+                    "config": {
+                        "units": units[0], "activation": "relu", "use_bias": true,
+                        "name": "dense",
+                        "batch_input_shape": [null, agent.network.input_size()],
+                        "dtype": "float32"
+                    }
+                },
+                {
+                    "class_name": "Dense",
+                    "config": {
+                        "units": units[1], "activation": "relu", "use_bias": true,
+                        "name": "dense_1", "dtype": "float32"
+                    }
+                },
+                {
+                    "class_name": "Dense",
+                    "config": {
+                        "units": units[2], "activation": "linear", "use_bias": true,
+                        "name": "dense_2", "dtype": "float32"
+                    }
+                }
+            ]
+        }
+    });
+
+    let mut meta = serde_json::json!({
+        "epsilon": agent.epsilon,
+        "env": env,
+        "feature_version": agent.features.version
+    });
+    if let Some(distribution) = agent.distribution() {
+        // Categorical: Q = sum_i softmax(action's logits)_i * (v_min + i * (v_max - v_min) /
+        // (atoms - 1)); Quantile: Q = mean of the action's quantiles
+        meta["distribution"] = serde_json::json!(distribution);
+    }
+    if let Some(norm) = &agent.obs_norm {
+        // Inputs must be standardized as (x - mean) / std, clipped to [-5, 5], before inference
+        meta["obs_norm"] = serde_json::json!({ "mean": norm.mean(), "std": norm.std() });
+    }
+
+    let export = serde_json::json!({
+        "modelTopology": model_topology,
+        "weightSpecs": weight_specs,
+        "weightData": weight_bytes,
+        "meta": meta
+    });
+
+    std::fs::write(filename, serde_json::to_string(&export).unwrap()).unwrap();
+}
+
+/// Writes the weights as a NumPy `.npz` archive (`numpy.load`-able): float32 arrays
+/// `dense_<i>_kernel` ([in × out]) and `dense_<i>_bias` ([out]) per layer, folding a dueling
+/// head like `export_model`
+pub fn export_npz(agent: &DQNAgent, filename: &str) {
+    let network = agent.network.folded();
+    let mut entries = Vec::new();
+    for i in 0..network.num_layers() {
+        let (weights, biases, in_size, out_size) = network.layer_info(i);
+        entries.push((format!("dense_{}_kernel.npy", i), npy(weights, &[in_size, out_size])));
+        entries.push((format!("dense_{}_bias.npy", i), npy(biases, &[out_size])));
+    }
+    std::fs::write(filename, zip_stored(&entries)).unwrap();
+}
+
+/// A little-endian float32 `.npy` (format 1.0) file
+fn npy(values: &[f32], shape: &[usize]) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    let shape = match dims.len() {
+        1 => format!("({},)", dims[0]),
+        _ => format!("({})", dims.join(", ")),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // Magic (6) + version (2) + length (2) + header, padded to a multiple of 64 ending in \n
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut out = b"\x93NUMPY\x01\x00".to_vec();
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    for v in values {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    out
+}
+
+/// An uncompressed ("stored") zip archive of `(name, contents)` entries
+fn zip_stored(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    // version needed 2.0, no flags, method 0 (stored), time 0:00, date 1980-01-01
+    const FIELDS: [u16; 5] = [20, 0, 0, 0, 0x21];
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in entries {
+        let offset = out.len() as u32;
+        let crc = crc32(data);
+        let mut common = Vec::new();
+        for f in FIELDS {
+            common.extend_from_slice(&f.to_le_bytes());
+        }
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&common);
+        // comment length, disk number, internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]); // disk numbers
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    out
+}
+
+/// CRC-32 (IEEE), as zip requires
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Loads a TF.js-format model (ours or from another tool with the same topology).
+///
+/// `weightData` may be a JSON byte array or a base64 string. Tensors are matched to the Dense
+/// layers of `modelTopology` by name (`<layer>/kernel`, `<layer>/bias`, with any scope prefix)
+/// and checked against the expected shapes and activations. The output layer must have
+/// `atoms` outputs per action (1 for a plain Q-network).
+pub fn import_model(filename: &str, atoms: usize) -> Result<Network, String> {
+    let text = std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let json: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", filename, e))?;
+
+    let bytes = weight_bytes(&json["weightData"])?;
+    let specs = json["weightSpecs"].as_array().ok_or("missing weightSpecs")?;
+
+    // Tensors are packed back to back in weightSpecs order
+    let mut tensors: HashMap<String, (Vec<usize>, Vec<f32>)> = HashMap::new();
+    let mut offset = 0;
+    for spec in specs {
+        let name = spec["name"].as_str().ok_or("weightSpec missing name")?;
+        let dtype = spec["dtype"].as_str().unwrap_or("float32");
+        if dtype != "float32" {
+            return Err(format!("{}: unsupported dtype {}", name, dtype));
+        }
+        let shape: Vec<usize> = spec["shape"]
+            .as_array()
+            .ok_or_else(|| format!("{}: missing shape", name))?
+            .iter()
+            .map(|d| d.as_u64().map(|d| d as usize))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{}: invalid shape", name))?;
+        let end = offset + shape.iter().product::<usize>() * 4;
+        if end > bytes.len() {
+            return Err(format!("weightData too short for {}", name));
+        }
+        let values = bytes[offset..end]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        tensors.insert(name.to_string(), (shape, values));
+        offset = end;
+    }
+
+    let dense = dense_layers(&json["modelTopology"])?;
+    let mut layers = Vec::with_capacity(dense.len());
+    // The input size depends on the feature version; take it from the first kernel
+    let mut in_size = match dense.first() {
+        Some((name, _, _)) => {
+            let shape = &tensors[&tensor_key(&tensors, name, "kernel")?].0;
+            shape.first().copied().unwrap_or(0)
+        }
+        None => 0,
+    };
+    for (i, (name, units, activation)) in dense.iter().enumerate() {
+        let expected = if i + 1 == dense.len() { "linear" } else { "relu" };
+        if activation != expected {
+            return Err(format!(
+                "layer {}: expected {} activation, got {}",
+                name, expected, activation
+            ));
+        }
+        let weights = take_tensor(&mut tensors, name, "kernel", &[in_size, *units])?;
+        let biases = take_tensor(&mut tensors, name, "bias", &[*units])?;
+        layers.push((weights, biases));
+        in_size = *units;
+    }
+
+    let network = Network::from_layers(layers)?;
+    let outputs = network.output_size();
+    if outputs != ORTHOGONAL_ACTIONS * atoms && outputs != ACTIONS.len() * atoms {
+        return Err(format!(
+            "{} outputs: expected {} or {} actions x {} atoms",
+            outputs,
+            ORTHOGONAL_ACTIONS,
+            ACTIONS.len(),
+            atoms
+        ));
+    }
+    Ok(network)
+}
+
+/// `meta` object of an exported model (null when absent)
+fn read_meta(filename: &str) -> Result<serde_json::Value, String> {
+    let text = std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let mut json: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", filename, e))?;
+    Ok(json["meta"].take())
+}
+
+/// Loads an exported model as a ready-to-run agent: network (C51 or QR-DQN when
+/// `meta.distribution` is set), observation normalization and the feature set matching the model's
+/// `meta.feature_version`
+pub fn load_model(filename: &str) -> Result<DQNAgent, String> {
+    let meta = read_meta(filename)?;
+    let distribution: Option<ReturnDistribution> = match &meta["distribution"] {
+        serde_json::Value::Null => None,
+        value => Some(
+            serde_json::from_value(value.clone())
+                .map_err(|e| format!("{}: meta.distribution: {}", filename, e))?,
+        ),
+    };
+    let network = import_model(filename, distribution.map_or(1, |d| d.outputs_per_action()))?;
+    let mut agent = match distribution {
+        Some(distribution) => {
+            DQNAgent::distributional(ORTHOGONAL_ACTIONS, distribution, false, None)
+        }
+        None => DQNAgent::new(ORTHOGONAL_ACTIONS, false),
+    };
+    agent.network = network;
+    let version = meta["feature_version"].as_u64().map(|v| v as u32);
+    agent.features = feature_set(version, agent.network.input_size())
+        .map_err(|e| format!("{}: {}", filename, e))?;
+    agent.obs_norm = import_obs_norm(filename, agent.features.size)?;
+    Ok(agent)
+}
+
+/// Training environment stored in an exported model's `meta.env` (None for older exports)
+pub fn import_env_mode(filename: &str) -> Result<Option<EnvMode>, String> {
+    let env = read_meta(filename)?["env"].take();
+    if env.is_null() {
+        return Ok(None);
+    }
+    serde_json::from_value(env).map(Some).map_err(|e| format!("meta.env: {}", e))
+}
+
+/// Observation normalization stored in an exported model's `meta.obs_norm`, if any, for a
+/// model with `size` inputs
+fn import_obs_norm(filename: &str, size: usize) -> Result<Option<ObsNormalizer>, String> {
+    let meta = read_meta(filename)?;
+    let stats = &meta["obs_norm"];
+    if stats.is_null() {
+        return Ok(None);
+    }
+    let read = |key: &str| -> Result<Vec<f64>, String> {
+        let values: Vec<f64> = stats[key]
+            .as_array()
+            .ok_or_else(|| format!("obs_norm: missing {}", key))?
+            .iter()
+            .map(|v| v.as_f64())
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("obs_norm: invalid {}", key))?;
+        if values.len() != size {
+            return Err(format!(
+                "obs_norm: {} has {} entries, expected {}",
+                key,
+                values.len(),
+                size
+            ));
+        }
+        Ok(values)
+    };
+    Ok(Some(ObsNormalizer::from_mean_std(read("mean")?, read("std")?)))
+}
+
+/// `weightData` as raw bytes, from either a JSON byte array or a base64 string
+fn weight_bytes(data: &serde_json::Value) -> Result<Vec<u8>, String> {
+    if let Some(encoded) = data.as_str() {
+        return decode_base64(encoded);
+    }
+    data.as_array()
+        .ok_or("missing weightData")?
+        .iter()
+        .map(|v| {
+            v.as_u64()
+                .filter(|&b| b <= 255)
+                .map(|b| b as u8)
+                .ok_or_else(|| "weightData must be a byte array or base64 string".to_string())
+        })
+        .collect()
+}
+
+/// (name, units, activation) of each Dense layer in a Sequential topology
+fn dense_layers(topology: &serde_json::Value) -> Result<Vec<(String, usize, String)>, String> {
+    // tfjs `model.toJSON()` nests the Keras config under `model_config`
+    let model = if topology["model_config"].is_object() {
+        &topology["model_config"]
+    } else {
+        topology
+    };
+    let layers = model["config"]["layers"]
+        .as_array()
+        .ok_or("modelTopology has no layers")?;
+
+    let mut dense = Vec::new();
+    for layer in layers {
+        let config = &layer["config"];
+        let name = config["name"].as_str().unwrap_or("?").to_string();
+        match layer["class_name"].as_str() {
+            Some("Dense") => {}
+            Some("InputLayer") => continue,
+            other => return Err(format!("layer {}: unsupported class {:?}", name, other)),
+        }
+        let units = config["units"]
+            .as_u64()
+            .ok_or_else(|| format!("layer {}: missing units", name))? as usize;
+        let activation = config["activation"].as_str().unwrap_or("linear").to_string();
+        dense.push((name, units, activation));
+    }
+    Ok(dense)
+}
+
+/// Key of the `<layer>/<kind>` tensor, matching any scope prefix
+fn tensor_key(
+    tensors: &HashMap<String, (Vec<usize>, Vec<f32>)>,
+    layer: &str,
+    kind: &str,
+) -> Result<String, String> {
+    let suffix = format!("{}/{}", layer, kind);
+    tensors
+        .keys()
+        .find(|k| *k == &suffix || k.ends_with(&format!("/{}", suffix)))
+        .cloned()
+        .ok_or_else(|| format!("missing tensor {}", suffix))
+}
+
+/// Removes the `<layer>/<kind>` tensor (matching any scope prefix) and checks its shape
+fn take_tensor(
+    tensors: &mut HashMap<String, (Vec<usize>, Vec<f32>)>,
+    layer: &str,
+    kind: &str,
+    shape: &[usize],
+) -> Result<Vec<f32>, String> {
+    let key = tensor_key(tensors, layer, kind)?;
+    let (found, values) = tensors.remove(&key).unwrap();
+    if found != shape {
+        return Err(format!("{}: expected shape {:?}, got {:?}", key, shape, found));
+    }
+    Ok(values)
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in encoded.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err(format!("invalid base64 character {:?}", c as char)),
+        };
+        acc = ((acc << 6) | v as u32) & 0xFF_FFFF;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Saves `agent`, the engine's mode and RNG state and the run's `best` model tracking; with
+/// `include_replay` the replay buffer too, so the resumed run continues exactly as an
+/// uninterrupted one would
+pub fn save_checkpoint(
+    agent: &DQNAgent,
+    engine: &SnakeEngine,
+    best: &BestModel,
+    episode: u64,
+    include_replay: bool,
+    filename: &str,
+) {
+    let mut checkpoint = agent.checkpoint(episode);
+    checkpoint.env = Some(engine.mode());
+    checkpoint.engine_rng = Some(engine.rng().clone());
+    checkpoint.best = Some(best.clone());
+    if include_replay {
+        checkpoint.replay = Some(agent.replay_buffer().clone());
+    }
+    std::fs::write(filename, serde_json::to_string(&checkpoint).unwrap()).unwrap();
+}
+
+/// Deletes all but the `keep` most recent episode checkpoints in the series `latest` belongs
+/// to: files in its directory named like it with a different episode number
+/// (`<prefix>model_ep<N>.json`, plus any `.npz` copy). Other files, such as `model_best.json`
+/// and `model_final.json`, are never touched. Failed deletions are warned about and skipped.
+/// Returns the deleted paths.
+pub fn prune_checkpoints(latest: &str, keep: usize) -> Vec<std::path::PathBuf> {
+    let latest = std::path::Path::new(latest);
+    let dir = match latest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let name = latest.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let series = name.trim_end_matches(".json").trim_end_matches(|c: char| c.is_ascii_digit());
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Warning: can't prune checkpoints in {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut checkpoints: Vec<(u64, std::path::PathBuf)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let file_name = path.file_name()?.to_string_lossy().into_owned();
+            let episode = file_name.strip_prefix(series)?.strip_suffix(".json")?.parse().ok()?;
+            Some((episode, path))
+        })
+        .collect();
+    checkpoints.sort_by_key(|c| std::cmp::Reverse(c.0));
+
+    let mut removed = Vec::new();
+    for (_, path) in checkpoints.into_iter().skip(keep) {
+        for path in [path.with_extension("npz"), path] {
+            if !path.exists() {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed.push(path),
+                Err(e) => eprintln!("Warning: failed to delete {}: {}", path.display(), e),
+            }
+        }
+    }
+    removed
+}
+
+pub fn load_checkpoint(filename: &str) -> Result<Checkpoint, String> {
+    let text = std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {}", filename, e))
+}
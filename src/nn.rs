@@ -1,25 +1,92 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
-pub const INPUT_SIZE: usize = 28;
+pub const INPUT_SIZE: usize = 39;
 const HIDDEN1: usize = 256;
 const HIDDEN2: usize = 64;
-pub const OUTPUT_SIZE: usize = 4;
 
+/// Accumulator for gradient sums and Adam moments. The `f64-accum` feature widens it to f64
+/// to reduce rounding error in the batched reductions; weights stay f32 either way.
+#[cfg(feature = "f64-accum")]
+type Acc = f64;
+#[cfg(not(feature = "f64-accum"))]
+type Acc = f32;
+
+/// Float type a gradient reduction can accumulate in (f32 or f64)
+pub trait Accumulator:
+    Copy + From<f32> + std::ops::AddAssign + std::ops::Mul<Output = Self> + std::ops::DivAssign
+{
+}
+
+impl<T> Accumulator for T where
+    T: Copy + From<f32> + std::ops::AddAssign + std::ops::Mul<Output = T> + std::ops::DivAssign
+{
+}
+
+#[derive(Serialize, Deserialize)]
 struct DenseLayer {
     weights: Vec<f32>, // [in_size × out_size], row-major: w[i * out + j]
     biases: Vec<f32>,
     relu: bool,
     in_size: usize,
     out_size: usize,
-    m_w: Vec<f32>,
-    v_w: Vec<f32>,
-    m_b: Vec<f32>,
-    v_b: Vec<f32>,
+    m_w: Vec<Acc>,
+    v_w: Vec<Acc>,
+    m_b: Vec<Acc>,
+    v_b: Vec<Acc>,
+    /// NoisyNet layer: learned parameter noise on top of `weights`/`biases` (the means)
+    #[serde(default)]
+    noise: Option<Noise>,
+}
+
+/// Factorized Gaussian noise of a noisy linear layer: the noisy weights are
+/// w_ij = mu_ij + sigma_ij * e_in_i * e_out_j and the biases b_j = mu_j + sigma_j * e_out_j,
+/// where e = sign(x) sqrt(|x|) of a standard normal sample x
+#[derive(Clone, Serialize, Deserialize)]
+struct Noise {
+    sigma_w: Vec<f32>,
+    sigma_b: Vec<f32>,
+    m_w: Vec<Acc>,
+    v_w: Vec<Acc>,
+    m_b: Vec<Acc>,
+    v_b: Vec<Acc>,
+    // Current sample, used by every noisy forward pass until the next resample
+    e_in: Vec<f32>,
+    e_out: Vec<f32>,
+}
+
+impl Noise {
+    /// sigma initialized to `sigma0` / sqrt(in_size), with a zero noise sample
+    fn new(in_size: usize, out_size: usize, sigma0: f32) -> Self {
+        let sigma = sigma0 / (in_size as f32).sqrt();
+        let n = in_size * out_size;
+        Noise {
+            sigma_w: vec![sigma; n],
+            sigma_b: vec![sigma; out_size],
+            m_w: vec![0.0; n],
+            v_w: vec![0.0; n],
+            m_b: vec![0.0; out_size],
+            v_b: vec![0.0; out_size],
+            e_in: vec![0.0; in_size],
+            e_out: vec![0.0; out_size],
+        }
+    }
+
+    fn resample(&mut self, rng: &mut impl Rng) {
+        let mut scaled_normal = || {
+            // Box-Muller
+            let (u1, u2): (f32, f32) = (rng.gen::<f32>().max(1e-12), rng.gen());
+            let x = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos();
+            x.signum() * x.abs().sqrt()
+        };
+        self.e_in.iter_mut().for_each(|e| *e = scaled_normal());
+        self.e_out.iter_mut().for_each(|e| *e = scaled_normal());
+    }
 }
 
 impl DenseLayer {
-    fn new(in_size: usize, out_size: usize, relu: bool) -> Self {
-        let mut rng = rand::thread_rng();
+    fn new(in_size: usize, out_size: usize, relu: bool, rng: &mut impl Rng) -> Self {
         let limit = (6.0 / (in_size + out_size) as f32).sqrt();
         let n = in_size * out_size;
         let weights: Vec<f32> = (0..n)
@@ -36,30 +103,76 @@ impl DenseLayer {
             v_w: vec![0.0; n],
             m_b: vec![0.0; out_size],
             v_b: vec![0.0; out_size],
+            noise: None,
+        }
+    }
+
+    fn from_weights(weights: Vec<f32>, biases: Vec<f32>, in_size: usize, relu: bool) -> Self {
+        let out_size = biases.len();
+        let n = weights.len();
+        DenseLayer {
+            weights,
+            biases,
+            relu,
+            in_size,
+            out_size,
+            m_w: vec![0.0; n],
+            v_w: vec![0.0; n],
+            m_b: vec![0.0; out_size],
+            v_b: vec![0.0; out_size],
+            noise: None,
+        }
+    }
+
+    /// Weights and biases a forward pass uses: with `noisy`, a noisy layer's current noise
+    /// sample is added to the means; otherwise (and for plain layers) the means alone
+    fn params(&self, noisy: bool) -> (Cow<'_, [f32]>, Cow<'_, [f32]>) {
+        match self.noise.as_ref().filter(|_| noisy) {
+            Some(noise) => {
+                let out = self.out_size;
+                let weights = self
+                    .weights
+                    .iter()
+                    .zip(&noise.sigma_w)
+                    .enumerate()
+                    .map(|(k, (mu, sigma))| mu + sigma * noise.e_in[k / out] * noise.e_out[k % out])
+                    .collect();
+                let biases = self
+                    .biases
+                    .iter()
+                    .zip(&noise.sigma_b)
+                    .zip(&noise.e_out)
+                    .map(|((mu, sigma), e)| mu + sigma * e)
+                    .collect();
+                (Cow::Owned(weights), Cow::Owned(biases))
+            }
+            None => (Cow::Borrowed(&self.weights), Cow::Borrowed(&self.biases)),
         }
     }
 
-    fn forward_single(&self, input: &[f32], output: &mut [f32]) {
+    fn forward_single(&self, input: &[f32], output: &mut [f32], noisy: bool) {
+        let (weights, biases) = self.params(noisy);
         for j in 0..self.out_size {
-            let mut sum = self.biases[j];
+            let mut sum = biases[j];
             for i in 0..self.in_size {
-                sum += input[i] * self.weights[i * self.out_size + j];
+                sum += input[i] * weights[i * self.out_size + j];
             }
             output[j] = if self.relu { sum.max(0.0) } else { sum };
         }
     }
 
     /// Returns (z, a) flattened as [bs * out_size]
-    fn forward_batch(&self, input: &[f32], bs: usize) -> (Vec<f32>, Vec<f32>) {
+    fn forward_batch(&self, input: &[f32], bs: usize, noisy: bool) -> (Vec<f32>, Vec<f32>) {
+        let (weights, biases) = self.params(noisy);
         let mut z = vec![0.0f32; bs * self.out_size];
         let mut a = vec![0.0f32; bs * self.out_size];
 
         for b in 0..bs {
             let inp = &input[b * self.in_size..(b + 1) * self.in_size];
             for j in 0..self.out_size {
-                let mut sum = self.biases[j];
+                let mut sum = biases[j];
                 for i in 0..self.in_size {
-                    sum += inp[i] * self.weights[i * self.out_size + j];
+                    sum += inp[i] * weights[i * self.out_size + j];
                 }
                 let idx = b * self.out_size + j;
                 z[idx] = sum;
@@ -70,91 +183,469 @@ impl DenseLayer {
         (z, a)
     }
 
-    fn adam_update(&mut self, gw: &[f32], gb: &[f32], lr: f32, t: usize) {
-        let (b1, b2, eps) = (0.9f32, 0.999f32, 1e-8f32);
-        let bc1 = 1.0 - b1.powi(t as i32);
-        let bc2 = 1.0 - b2.powi(t as i32);
-
-        for i in 0..self.weights.len() {
-            self.m_w[i] = b1 * self.m_w[i] + (1.0 - b1) * gw[i];
-            self.v_w[i] = b2 * self.v_w[i] + (1.0 - b2) * gw[i] * gw[i];
-            let mh = self.m_w[i] / bc1;
-            let vh = self.v_w[i] / bc2;
-            self.weights[i] -= lr * mh / (vh.sqrt() + eps);
+    /// Copy of the layer, with its Adam moments or with fresh ones
+    fn copy(&self, with_moments: bool) -> Self {
+        let moments = |m: &Vec<Acc>| if with_moments { m.clone() } else { vec![0.0; m.len()] };
+        DenseLayer {
+            weights: self.weights.clone(),
+            biases: self.biases.clone(),
+            relu: self.relu,
+            in_size: self.in_size,
+            out_size: self.out_size,
+            m_w: moments(&self.m_w),
+            v_w: moments(&self.v_w),
+            m_b: moments(&self.m_b),
+            v_b: moments(&self.v_b),
+            noise: self.noise.as_ref().map(|noise| Noise {
+                m_w: moments(&noise.m_w),
+                v_w: moments(&noise.v_w),
+                m_b: moments(&noise.m_b),
+                v_b: moments(&noise.v_b),
+                ..noise.clone()
+            }),
         }
+    }
 
-        for i in 0..self.biases.len() {
-            self.m_b[i] = b1 * self.m_b[i] + (1.0 - b1) * gb[i];
-            self.v_b[i] = b2 * self.v_b[i] + (1.0 - b2) * gb[i] * gb[i];
-            let mh = self.m_b[i] / bc1;
-            let vh = self.v_b[i] / bc2;
-            self.biases[i] -= lr * mh / (vh.sqrt() + eps);
+    /// Adam step on the means from their gradients `gw`/`gb`; a noisy layer's sigmas get theirs
+    /// from the same gradients times the noise sample the batch was run with
+    fn adam_update(&mut self, gw: &[Acc], gb: &[Acc], lr: f32, t: usize) {
+        adam_step(&mut self.weights, &mut self.m_w, &mut self.v_w, gw, lr, t);
+        adam_step(&mut self.biases, &mut self.m_b, &mut self.v_b, gb, lr, t);
+        if let Some(noise) = self.noise.as_mut() {
+            let out = self.out_size;
+            let gsw: Vec<Acc> = gw
+                .iter()
+                .enumerate()
+                .map(|(k, g)| g * (noise.e_in[k / out] * noise.e_out[k % out]) as Acc)
+                .collect();
+            let gsb: Vec<Acc> = gb.iter().zip(&noise.e_out).map(|(g, &e)| g * e as Acc).collect();
+            adam_step(&mut noise.sigma_w, &mut noise.m_w, &mut noise.v_w, &gsw, lr, t);
+            adam_step(&mut noise.sigma_b, &mut noise.m_b, &mut noise.v_b, &gsb, lr, t);
         }
     }
 }
 
+/// One Adam update of `params` with gradients `grads` and moments `m`/`v` at step `t`
+fn adam_step(params: &mut [f32], m: &mut [Acc], v: &mut [Acc], grads: &[Acc], lr: f32, t: usize) {
+    let (b1, b2, eps): (Acc, Acc, Acc) = (0.9, 0.999, 1e-8);
+    let lr = lr as Acc;
+    let bc1 = 1.0 - b1.powi(t as i32);
+    let bc2 = 1.0 - b2.powi(t as i32);
+
+    for i in 0..params.len() {
+        m[i] = b1 * m[i] + (1.0 - b1) * grads[i];
+        v[i] = b2 * v[i] + (1.0 - b2) * grads[i] * grads[i];
+        let mh = m[i] / bc1;
+        let vh = v[i] / bc2;
+        let step: Acc = lr * mh / (vh.sqrt() + eps);
+        params[i] -= step as f32;
+    }
+}
+
+/// Pre- (z) and post-activation (a) outputs of the hidden layers for a batch, and the final
+/// outputs (recombined when dueling), flattened as [bs * units]
+struct BatchTrace {
+    z0: Vec<f32>,
+    a0: Vec<f32>,
+    z1: Vec<f32>,
+    a1: Vec<f32>,
+    out: Vec<f32>,
+}
+
+/// Serializes with its Adam state (moments and step `t`) so resumed training continues with
+/// the same bias correction
+#[derive(Serialize, Deserialize)]
 pub struct Network {
     layers: Vec<DenseLayer>,
+    /// Dueling architecture: a state-value stream next to the last layer, which then gives the
+    /// advantages, recombined as Q = V + A - mean(A). The value stream has one output per
+    /// output of an action (more than one for a distributional head, recombined per atom).
+    #[serde(default)]
+    value: Option<DenseLayer>,
     t: usize,
 }
 
 impl Network {
-    pub fn new() -> Self {
+    /// Fresh network with `inputs` features and one output (Q-value) per action; `dueling`
+    /// splits the head after the last hidden layer into value and advantage streams
+    pub fn new(inputs: usize, outputs: usize, dueling: bool, rng: &mut impl Rng) -> Self {
+        Self::with_head(inputs, outputs, 1, dueling, rng)
+    }
+
+    /// Fresh network with `inputs` features and `per_action` outputs (atoms or quantiles) for
+    /// each of `actions`, action-major. A dueling value stream gets `per_action` outputs.
+    pub fn with_head(
+        inputs: usize,
+        actions: usize,
+        per_action: usize,
+        dueling: bool,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let layers = vec![
+            DenseLayer::new(inputs, HIDDEN1, true, rng),
+            DenseLayer::new(HIDDEN1, HIDDEN2, true, rng),
+            DenseLayer::new(HIDDEN2, actions * per_action, false, rng),
+        ];
         Network {
-            layers: vec![
-                DenseLayer::new(INPUT_SIZE, HIDDEN1, true),
-                DenseLayer::new(HIDDEN1, HIDDEN2, true),
-                DenseLayer::new(HIDDEN2, OUTPUT_SIZE, false),
-            ],
+            layers,
+            value: dueling.then(|| DenseLayer::new(HIDDEN2, per_action, false, rng)),
             t: 0,
         }
     }
 
-    pub fn forward(&self, input: &[f32]) -> [f32; OUTPUT_SIZE] {
-        let mut buf1 = vec![0.0f32; HIDDEN1];
-        let mut buf2 = vec![0.0f32; HIDDEN2];
-        let mut out = [0.0f32; OUTPUT_SIZE];
+    /// Whether the output is split into value and advantage streams
+    pub fn is_dueling(&self) -> bool {
+        self.value.is_some()
+    }
+
+    /// Turns every layer into a noisy linear layer (NoisyNet) with sigma0 = `sigma0`, keeping
+    /// the current weights as the means. The noise sample starts at zero.
+    pub fn make_noisy(&mut self, sigma0: f32) {
+        for l in self.layers.iter_mut().chain(self.value.as_mut()) {
+            l.noise = Some(Noise::new(l.in_size, l.out_size, sigma0));
+        }
+    }
 
-        self.layers[0].forward_single(input, &mut buf1);
-        self.layers[1].forward_single(&buf1, &mut buf2);
-        self.layers[2].forward_single(&buf2, &mut out);
+    pub fn is_noisy(&self) -> bool {
+        self.layers[0].noise.is_some()
+    }
+
+    /// Draws a new noise sample for every noisy layer
+    pub fn resample_noise(&mut self, rng: &mut impl Rng) {
+        for l in self.layers.iter_mut().chain(self.value.as_mut()) {
+            if let Some(noise) = l.noise.as_mut() {
+                noise.resample(rng);
+            }
+        }
+    }
 
+    /// Equivalent plain network: a dueling head is folded into a single linear output layer
+    /// (Q_j = h·(A_j - mean(A) + V) + ...), since both streams are linear in the last hidden
+    /// layer, and noisy layers keep only their means. Adam state is not kept.
+    pub fn folded(&self) -> Network {
+        let mut layers: Vec<DenseLayer> = self.layers.iter().map(|l| l.copy(false)).collect();
+        layers.iter_mut().for_each(|l| l.noise = None);
+        if let (Some(value), Some(out)) = (&self.value, layers.last_mut()) {
+            // The recombination is linear, so it applies to each weight row and the biases
+            let (n, v) = (out.out_size, value.out_size);
+            for i in 0..out.in_size {
+                let row = &mut out.weights[i * n..(i + 1) * n];
+                combine_dueling(row, &value.weights[i * v..(i + 1) * v]);
+            }
+            combine_dueling(&mut out.biases, &value.biases);
+        }
+        Network {
+            layers,
+            value: None,
+            t: 0,
+        }
+    }
+
+    /// Rebuild a network from exported (weights, biases) per layer, in order. The input size
+    /// is taken from the first layer's weights and the output size from the last layer's biases.
+    pub fn from_layers(layers: Vec<(Vec<f32>, Vec<f32>)>) -> Result<Self, String> {
+        let inputs = layers.first().map_or(0, |(weights, _)| weights.len() / HIDDEN1);
+        let outputs = layers.last().map_or(0, |(_, biases)| biases.len());
+        let sizes = [inputs, HIDDEN1, HIDDEN2, outputs];
+        if layers.len() != sizes.len() - 1 {
+            return Err(format!(
+                "expected {} layers, got {}",
+                sizes.len() - 1,
+                layers.len()
+            ));
+        }
+
+        let last = layers.len() - 1;
+        let mut built = Vec::with_capacity(layers.len());
+        for (i, (weights, biases)) in layers.into_iter().enumerate() {
+            let (in_size, out_size) = (sizes[i], sizes[i + 1]);
+            if weights.len() != in_size * out_size || biases.len() != out_size {
+                return Err(format!(
+                    "layer {}: expected {}x{} weights and {} biases, got {} and {}",
+                    i,
+                    in_size,
+                    out_size,
+                    out_size,
+                    weights.len(),
+                    biases.len()
+                ));
+            }
+            built.push(DenseLayer::from_weights(weights, biases, in_size, i != last));
+        }
+
+        Ok(Network {
+            layers: built,
+            value: None,
+            t: 0,
+        })
+    }
+
+    /// Number of inputs, i.e. features
+    pub fn input_size(&self) -> usize {
+        self.layers[0].in_size
+    }
+
+    /// Number of outputs, i.e. actions
+    pub fn output_size(&self) -> usize {
+        self.layers[self.layers.len() - 1].out_size
+    }
+
+    pub fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let [_, _, out] = self.forward_with_activations(input);
         out
     }
 
-    pub fn predict_batch(&self, inputs: &[Vec<f32>]) -> Vec<[f32; OUTPUT_SIZE]> {
+    /// Output with the current noise sample of noisy layers ([`Network::forward`] uses the
+    /// means alone)
+    pub fn forward_noisy(&self, input: &[f32]) -> Vec<f32> {
+        let [_, _, out] = self.activations(input, true);
+        out
+    }
+
+    /// Post-activation output of every layer: [hidden1 (relu), hidden2 (relu), Q-values]
+    pub fn forward_with_activations(&self, input: &[f32]) -> [Vec<f32>; 3] {
+        self.activations(input, false)
+    }
+
+    fn activations(&self, input: &[f32], noisy: bool) -> [Vec<f32>; 3] {
+        let mut buf1 = vec![0.0f32; HIDDEN1];
+        let mut buf2 = vec![0.0f32; HIDDEN2];
+        let mut out = vec![0.0f32; self.output_size()];
+
+        self.layers[0].forward_single(input, &mut buf1, noisy);
+        self.layers[1].forward_single(&buf1, &mut buf2, noisy);
+        self.layers[2].forward_single(&buf2, &mut out, noisy);
+        if let Some(value) = &self.value {
+            let mut v = vec![0.0f32; value.out_size];
+            value.forward_single(&buf2, &mut v, noisy);
+            combine_dueling(&mut out, &v);
+        }
+
+        [buf1, buf2, out]
+    }
+
+    pub fn predict_batch(&self, inputs: &[Vec<f32>]) -> Vec<Vec<f32>> {
         inputs.iter().map(|inp| self.forward(inp)).collect()
     }
 
-    pub fn train_batch(&mut self, inputs: &[Vec<f32>], targets: &[Vec<f32>], lr: f32) {
-        self.t += 1;
+    /// One Adam step on the squared error between output `actions[b]` and `targets[b]`. Only
+    /// the taken action's output gets a gradient; the others are masked out.
+    pub fn train_batch(&mut self, inputs: &[Vec<f32>], targets: &[f32], actions: &[usize], lr: f32) {
         let bs = inputs.len();
-        let bsf = bs as f32;
+        let flat_in: Vec<f32> = inputs.iter().flat_map(|v| v.iter().copied()).collect();
+        let trace = self.trace_batch(&flat_in, bs);
+
+        // dL/dout = (out - target) * 2/output_size on the taken action, 0 elsewhere. The
+        // 2/output_size scale is the mean over outputs the loss used before masking, kept so
+        // learning rates carry over.
+        let outputs = self.output_size();
+        let mut dout = vec![0.0f32; bs * outputs];
+        for b in 0..bs {
+            let idx = b * outputs + actions[b];
+            dout[idx] = (trace.out[idx] - targets[b]) * (2.0 / outputs as f32);
+        }
 
-        // Flatten inputs: [bs * INPUT_SIZE]
+        self.backward(&flat_in, &trace, dout, lr);
+    }
+
+    /// One Adam step on the cross-entropy between the taken action's categorical distribution
+    /// (a softmax over its block of `targets[b].len()` output logits) and `targets[b]`
+    pub fn train_batch_distributional(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        actions: &[usize],
+        lr: f32,
+    ) {
+        let bs = inputs.len();
         let flat_in: Vec<f32> = inputs.iter().flat_map(|v| v.iter().copied()).collect();
+        let trace = self.trace_batch(&flat_in, bs);
 
-        // Forward all layers, cache z (pre-activation) and a (post-activation)
-        let (z0, a0) = self.layers[0].forward_batch(&flat_in, bs);
-        let (z1, a1) = self.layers[1].forward_batch(&a0, bs);
-        let (_z2, a2) = self.layers[2].forward_batch(&a1, bs);
+        // dL/dlogit = softmax(logits) - target within the taken action's block, 0 elsewhere
+        let outputs = self.output_size();
+        let mut dout = vec![0.0f32; bs * outputs];
+        for b in 0..bs {
+            let atoms = targets[b].len();
+            let start = b * outputs + actions[b] * atoms;
+            let logits = &trace.out[start..start + atoms];
+            let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let sum: f32 = logits.iter().map(|l| (l - max).exp()).sum();
+            for (i, &target) in targets[b].iter().enumerate() {
+                dout[start + i] = (logits[i] - max).exp() / sum - target;
+            }
+        }
+
+        self.backward(&flat_in, &trace, dout, lr);
+    }
+
+    /// One Adam step on the quantile Huber loss (kappa = 1) between the taken action's block
+    /// of `targets[b].len()` quantile outputs, at midpoints tau_i = (2i + 1) / 2N, and the
+    /// target quantiles `targets[b]`: sum_i mean_j |tau_i - [u_ij < 0]| * huber(u_ij), where
+    /// u_ij = target_j - quantile_i
+    pub fn train_batch_quantile(
+        &mut self,
+        inputs: &[Vec<f32>],
+        targets: &[Vec<f32>],
+        actions: &[usize],
+        lr: f32,
+    ) {
+        let bs = inputs.len();
+        let flat_in: Vec<f32> = inputs.iter().flat_map(|v| v.iter().copied()).collect();
+        let trace = self.trace_batch(&flat_in, bs);
+
+        // dL/dquantile_i = -mean_j |tau_i - [u_ij < 0]| * clamp(u_ij, -1, 1), 0 outside the
+        // taken action's block
+        let outputs = self.output_size();
+        let mut dout = vec![0.0f32; bs * outputs];
+        for b in 0..bs {
+            let n = targets[b].len();
+            let start = b * outputs + actions[b] * n;
+            for i in 0..n {
+                let tau = (2 * i + 1) as f32 / (2 * n) as f32;
+                let quantile = trace.out[start + i];
+                let grad: f32 = targets[b]
+                    .iter()
+                    .map(|&target| {
+                        let u = target - quantile;
+                        let weight = if u < 0.0 { 1.0 - tau } else { tau };
+                        -weight * u.clamp(-1.0, 1.0)
+                    })
+                    .sum();
+                dout[start + i] = grad / n as f32;
+            }
+        }
+
+        self.backward(&flat_in, &trace, dout, lr);
+    }
+
+    /// One Adam step on the PPO loss of a softmax policy head over the outputs (logits): the
+    /// clipped surrogate -min(r A, clip(r, 1 - eps, 1 + eps) A), where r = pi(a) / pi_old(a)
+    /// for `actions[b]`, `advantages[b]` and `old_log_probs[b]`, minus `entropy_coef` times the
+    /// policy's entropy
+    pub fn train_batch_ppo(
+        &mut self,
+        inputs: &[Vec<f32>],
+        actions: &[usize],
+        advantages: &[f32],
+        old_log_probs: &[f32],
+        loss: &PolicyLoss,
+        lr: f32,
+    ) {
+        let bs = inputs.len();
+        let flat_in: Vec<f32> = inputs.iter().flat_map(|v| v.iter().copied()).collect();
+        let trace = self.trace_batch(&flat_in, bs);
+
+        // Surrogate: dL/dlogit_j = -A r (1[j = a] - pi_j) while the unclipped term is the
+        // minimum, 0 once the ratio is clipped. Entropy: dL/dlogit_j = c pi_j (log pi_j + H).
+        let outputs = self.output_size();
+        let mut dout = vec![0.0f32; bs * outputs];
+        for b in 0..bs {
+            let probs = softmax(&trace.out[b * outputs..(b + 1) * outputs]);
+            let (a, advantage) = (actions[b], advantages[b]);
+            let ratio = (probs[a].max(1e-12).ln() - old_log_probs[b]).exp();
+            let clipped = (advantage > 0.0 && ratio > 1.0 + loss.clip)
+                || (advantage < 0.0 && ratio < 1.0 - loss.clip);
+            let log_probs: Vec<f32> = probs.iter().map(|p| p.max(1e-12).ln()).collect();
+            let entropy: f32 = -probs.iter().zip(&log_probs).map(|(p, l)| p * l).sum::<f32>();
+            let row = &mut dout[b * outputs..(b + 1) * outputs];
+            for j in 0..outputs {
+                if !clipped {
+                    let indicator = if j == a { 1.0 } else { 0.0 };
+                    row[j] -= advantage * ratio * (indicator - probs[j]);
+                }
+                row[j] += loss.entropy_coef * probs[j] * (log_probs[j] + entropy);
+            }
+        }
+
+        self.backward(&flat_in, &trace, dout, lr);
+    }
 
-        // --- Backprop ---
+    /// One Adam step on the discrete soft actor-critic policy loss of a softmax head over the
+    /// outputs: sum_a pi(a) (alpha log pi(a) - Q(a)), the KL to the Boltzmann policy of
+    /// `q_values[b]` at temperature `alpha` up to a constant
+    pub fn train_batch_soft_policy(
+        &mut self,
+        inputs: &[Vec<f32>],
+        q_values: &[Vec<f32>],
+        alpha: f32,
+        lr: f32,
+    ) {
+        let bs = inputs.len();
+        let flat_in: Vec<f32> = inputs.iter().flat_map(|v| v.iter().copied()).collect();
+        let trace = self.trace_batch(&flat_in, bs);
 
-        // dL/dz2 = (a2 - target) * 2/output_size  (layer 2 is linear, so dL/dz = dL/da)
-        let mut dz = vec![0.0f32; bs * OUTPUT_SIZE];
+        // With g_a = alpha log pi_a - Q_a: dL/dlogit_j = pi_j (g_j - sum_a pi_a g_a)
+        let outputs = self.output_size();
+        let mut dout = vec![0.0f32; bs * outputs];
         for b in 0..bs {
-            for j in 0..OUTPUT_SIZE {
-                let idx = b * OUTPUT_SIZE + j;
-                dz[idx] = (a2[idx] - targets[b][j]) * (2.0 / OUTPUT_SIZE as f32);
+            let probs = softmax(&trace.out[b * outputs..(b + 1) * outputs]);
+            let g: Vec<f32> = probs
+                .iter()
+                .zip(&q_values[b])
+                .map(|(p, q)| alpha * p.max(1e-12).ln() - q)
+                .collect();
+            let mean: f32 = probs.iter().zip(&g).map(|(p, g)| p * g).sum();
+            for j in 0..outputs {
+                dout[b * outputs + j] = probs[j] * (g[j] - mean);
+            }
+        }
+
+        self.backward(&flat_in, &trace, dout, lr);
+    }
+
+    /// Forward pass over a flattened batch, caching what backprop needs. Noisy layers use
+    /// their current noise sample.
+    fn trace_batch(&self, flat_in: &[f32], bs: usize) -> BatchTrace {
+        let (z0, a0) = self.layers[0].forward_batch(flat_in, bs, true);
+        let (z1, a1) = self.layers[1].forward_batch(&a0, bs, true);
+        let (_z2, mut out) = self.layers[2].forward_batch(&a1, bs, true);
+        if let Some(value) = &self.value {
+            let outputs = self.output_size();
+            let v = value.forward_batch(&a1, bs, true).1;
+            for (row, v) in out.chunks_mut(outputs).zip(v.chunks(value.out_size)) {
+                combine_dueling(row, v);
+            }
+        }
+        BatchTrace { z0, a0, z1, a1, out }
+    }
+
+    /// Backprop of `dout` (dL/d output, [bs * output_size]) and one Adam step on every layer
+    fn backward(&mut self, flat_in: &[f32], trace: &BatchTrace, mut dz: Vec<f32>, lr: f32) {
+        self.t += 1;
+        let outputs = self.output_size();
+        let bs = dz.len() / outputs;
+        let bsf = bs as f32;
+        let BatchTrace { z0, a0, z1, a1, .. } = trace;
+
+        // Dueling, per value output k: dL/dV_k = sum_j dL/dQ_jk and
+        // dL/dA_ik = dL/dQ_ik - mean_j dL/dQ_jk, with the value stream's delta added to the
+        // advantage stream's below
+        let mut value_grads = None;
+        if let Some(value) = &self.value {
+            let v = value.out_size;
+            let actions = (outputs / v) as f32;
+            let mut dv = vec![0.0f32; bs * v];
+            for (row, dv) in dz.chunks_mut(outputs).zip(dv.chunks_mut(v)) {
+                for (k, g) in dv.iter_mut().enumerate() {
+                    *g = row.iter().skip(k).step_by(v).sum();
+                    row.iter_mut().skip(k).step_by(v).for_each(|d| *d -= *g / actions);
+                }
             }
+            let gwv = matmul_at_b(a1, &dv, value.in_size, v, bs, bsf);
+            let gbv = sum_cols(&dv, v, bs, bsf);
+            let delta = matmul_a_bt(&dv, &value.params(true).0, v, value.in_size, bs);
+            value_grads = Some((gwv, gbv, delta));
         }
 
-        // Layer 2: gw2 = a1^T @ dz / bs, gb2 = sum(dz) / bs, delta = dz @ W2^T
+        // Layer 2 (linear, so dL/dz2 = dL/da2): gw2 = a1^T @ dz / bs, gb2 = sum(dz) / bs,
+        // delta = dz @ W2^T (the noisy W2 the forward pass used, for a noisy layer)
         let l = &self.layers[2];
-        let gw2 = matmul_at_b(&a1, &dz, l.in_size, l.out_size, bs, bsf);
+        let gw2 = matmul_at_b(a1, &dz, l.in_size, l.out_size, bs, bsf);
         let gb2 = sum_cols(&dz, l.out_size, bs, bsf);
-        let mut delta = matmul_a_bt(&dz, &l.weights, l.out_size, l.in_size, bs);
+        let mut delta = matmul_a_bt(&dz, &l.params(true).0, l.out_size, l.in_size, bs);
+        if let Some((_, _, value_delta)) = &value_grads {
+            delta.iter_mut().zip(value_delta).for_each(|(d, v)| *d += v);
+        }
 
         // Apply relu'(z1) to get dL/dz1
         for i in 0..delta.len() {
@@ -166,9 +657,9 @@ impl Network {
 
         // Layer 1: gw1 = a0^T @ dz1 / bs, gb1 = sum(dz1) / bs, delta = dz1 @ W1^T
         let l = &self.layers[1];
-        let gw1 = matmul_at_b(&a0, &dz1, l.in_size, l.out_size, bs, bsf);
+        let gw1 = matmul_at_b(a0, &dz1, l.in_size, l.out_size, bs, bsf);
         let gb1 = sum_cols(&dz1, l.out_size, bs, bsf);
-        let mut delta = matmul_a_bt(&dz1, &l.weights, l.out_size, l.in_size, bs);
+        let mut delta = matmul_a_bt(&dz1, &l.params(true).0, l.out_size, l.in_size, bs);
 
         // Apply relu'(z0) to get dL/dz0
         for i in 0..delta.len() {
@@ -180,10 +671,13 @@ impl Network {
 
         // Layer 0: gw0 = input^T @ dz0 / bs, gb0 = sum(dz0) / bs
         let l = &self.layers[0];
-        let gw0 = matmul_at_b(&flat_in, &dz0, l.in_size, l.out_size, bs, bsf);
+        let gw0 = matmul_at_b(flat_in, &dz0, l.in_size, l.out_size, bs, bsf);
         let gb0 = sum_cols(&dz0, l.out_size, bs, bsf);
 
         // Adam updates
+        if let (Some(value), Some((gwv, gbv, _))) = (self.value.as_mut(), &value_grads) {
+            value.adam_update(gwv, gbv, lr, self.t);
+        }
         self.layers[2].adam_update(&gw2, &gb2, lr, self.t);
         self.layers[1].adam_update(&gw1, &gb1, lr, self.t);
         self.layers[0].adam_update(&gw0, &gb0, lr, self.t);
@@ -191,33 +685,41 @@ impl Network {
 
     /// Polyak soft update: target = (1-tau)*target + tau*self
     pub fn soft_update_into(&self, target: &mut Network, tau: f32) {
-        for (src, dst) in self.layers.iter().zip(target.layers.iter_mut()) {
+        let value = self.value.iter().zip(target.value.iter_mut());
+        for (src, dst) in self.layers.iter().zip(target.layers.iter_mut()).chain(value) {
             for (s, d) in src.weights.iter().zip(dst.weights.iter_mut()) {
                 *d = (1.0 - tau) * *d + tau * *s;
             }
             for (s, d) in src.biases.iter().zip(dst.biases.iter_mut()) {
                 *d = (1.0 - tau) * *d + tau * *s;
             }
+            if let (Some(src), Some(dst)) = (&src.noise, dst.noise.as_mut()) {
+                let sigmas = src.sigma_w.iter().chain(&src.sigma_b);
+                for (s, d) in sigmas.zip(dst.sigma_w.iter_mut().chain(&mut dst.sigma_b)) {
+                    *d = (1.0 - tau) * *d + tau * *s;
+                }
+            }
+        }
+    }
+
+    /// Adam steps taken so far (the bias-correction `t`)
+    pub fn adam_steps(&self) -> usize {
+        self.t
+    }
+
+    /// Full copy, including Adam moments and step count
+    pub fn clone_with_optimizer(&self) -> Self {
+        Network {
+            layers: self.layers.iter().map(|l| l.copy(true)).collect(),
+            value: self.value.as_ref().map(|l| l.copy(true)),
+            t: self.t,
         }
     }
 
     pub fn clone_weights(&self) -> Self {
         Network {
-            layers: self
-                .layers
-                .iter()
-                .map(|l| DenseLayer {
-                    weights: l.weights.clone(),
-                    biases: l.biases.clone(),
-                    relu: l.relu,
-                    in_size: l.in_size,
-                    out_size: l.out_size,
-                    m_w: vec![0.0; l.m_w.len()],
-                    v_w: vec![0.0; l.v_w.len()],
-                    m_b: vec![0.0; l.m_b.len()],
-                    v_b: vec![0.0; l.v_b.len()],
-                })
-                .collect(),
+            layers: self.layers.iter().map(|l| l.copy(false)).collect(),
+            value: self.value.as_ref().map(|l| l.copy(false)),
             t: 0,
         }
     }
@@ -231,22 +733,98 @@ impl Network {
     pub fn num_layers(&self) -> usize {
         self.layers.len()
     }
+
+    /// Weights and biases of layer `idx` (weights row-major, `w[i * out + j]`)
+    #[cfg(feature = "test-hooks")]
+    pub fn layer_weights(&self, idx: usize) -> (&[f32], &[f32]) {
+        let l = &self.layers[idx];
+        (&l.weights, &l.biases)
+    }
+
+    /// Overwrites layer `idx` with known weights and biases, which must match its shape. The
+    /// layer's Adam moments are cleared so later training steps are deterministic too.
+    #[cfg(feature = "test-hooks")]
+    pub fn set_layer_weights(
+        &mut self,
+        idx: usize,
+        weights: Vec<f32>,
+        biases: Vec<f32>,
+    ) -> Result<(), String> {
+        let num_layers = self.layers.len();
+        let l = self
+            .layers
+            .get_mut(idx)
+            .ok_or_else(|| format!("layer {} out of range ({} layers)", idx, num_layers))?;
+        if weights.len() != l.in_size * l.out_size || biases.len() != l.out_size {
+            return Err(format!(
+                "layer {}: expected {}x{} weights and {} biases, got {} and {}",
+                idx,
+                l.in_size,
+                l.out_size,
+                l.out_size,
+                weights.len(),
+                biases.len()
+            ));
+        }
+        l.weights = weights;
+        l.biases = biases;
+        l.m_w.fill(0.0);
+        l.v_w.fill(0.0);
+        l.m_b.fill(0.0);
+        l.v_b.fill(0.0);
+        Ok(())
+    }
+}
+
+/// Coefficients of the PPO policy loss (see `Network::train_batch_ppo`)
+pub struct PolicyLoss {
+    /// Surrogate clip range eps: the probability ratio counts within [1 - eps, 1 + eps]
+    pub clip: f32,
+    /// Weight of the entropy bonus
+    pub entropy_coef: f32,
+}
+
+/// Probabilities of a softmax over `logits`
+pub fn softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits.iter().map(|l| (l - max).exp()).collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|e| e / sum).collect()
+}
+
+/// Dueling recombination in place: `advantages` (action-major, `value.len()` outputs per
+/// action) become Q_jk = V_k + A_jk - mean_j(A_jk)
+fn combine_dueling(advantages: &mut [f32], value: &[f32]) {
+    let actions = (advantages.len() / value.len()) as f32;
+    for (k, v) in value.iter().enumerate() {
+        let column = advantages.iter().skip(k).step_by(value.len());
+        let shift = v - column.sum::<f32>() / actions;
+        advantages.iter_mut().skip(k).step_by(value.len()).for_each(|a| *a += shift);
+    }
 }
 
-/// Compute A^T @ B / scale, where A is [bs × m] and B is [bs × n], result is [m × n]
-fn matmul_at_b(a: &[f32], b: &[f32], m: usize, n: usize, bs: usize, scale: f32) -> Vec<f32> {
-    let mut out = vec![0.0f32; m * n];
+/// Compute A^T @ B / scale, where A is [bs × m] and B is [bs × n], result is [m × n]. The
+/// sums accumulate in `T`: training uses `Acc`, the self-test compares f32 against f64.
+pub fn matmul_at_b<T: Accumulator>(
+    a: &[f32],
+    b: &[f32],
+    m: usize,
+    n: usize,
+    bs: usize,
+    scale: f32,
+) -> Vec<T> {
+    let mut out = vec![T::from(0.0); m * n];
     for s in 0..bs {
         let a_row = &a[s * m..(s + 1) * m];
         let b_row = &b[s * n..(s + 1) * n];
         for i in 0..m {
             for j in 0..n {
-                out[i * n + j] += a_row[i] * b_row[j];
+                out[i * n + j] += T::from(a_row[i]) * T::from(b_row[j]);
             }
         }
     }
     for v in out.iter_mut() {
-        *v /= scale;
+        *v /= T::from(scale);
     }
     out
 }
@@ -273,15 +851,15 @@ fn matmul_a_bt(a: &[f32], w: &[f32], out_size: usize, in_size: usize, bs: usize)
 }
 
 /// Sum columns across batch: result[j] = sum_b(data[b * cols + j]) / scale
-fn sum_cols(data: &[f32], cols: usize, bs: usize, scale: f32) -> Vec<f32> {
-    let mut out = vec![0.0f32; cols];
+fn sum_cols(data: &[f32], cols: usize, bs: usize, scale: f32) -> Vec<Acc> {
+    let mut out: Vec<Acc> = vec![0.0; cols];
     for b in 0..bs {
         for j in 0..cols {
-            out[j] += data[b * cols + j];
+            out[j] += data[b * cols + j] as Acc;
         }
     }
     for v in out.iter_mut() {
-        *v /= scale;
+        *v /= scale as Acc;
     }
     out
 }
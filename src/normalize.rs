@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+/// Normalized features are clipped to [-CLIP, CLIP] so a rare flag on a near-constant feature
+/// can't blow up the input scale
+const CLIP: f32 = 5.0;
+const VAR_EPS: f64 = 1e-8;
+
+/// Running per-feature mean / variance (Welford), used to standardize observations before the
+/// network sees them. Updated only from training transitions, so it's frozen during eval.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ObsNormalizer {
+    count: f64,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+}
+
+impl ObsNormalizer {
+    pub fn new(size: usize) -> Self {
+        ObsNormalizer {
+            count: 0.0,
+            mean: vec![0.0; size],
+            m2: vec![0.0; size],
+        }
+    }
+
+    /// Frozen normalizer with the given statistics (as stored in an exported model)
+    pub fn from_mean_std(mean: Vec<f64>, std: Vec<f64>) -> Self {
+        ObsNormalizer {
+            count: 1.0,
+            m2: std.iter().map(|s| s * s).collect(),
+            mean,
+        }
+    }
+
+    pub fn update(&mut self, features: &[f32]) {
+        self.count += 1.0;
+        for (i, &x) in features.iter().enumerate() {
+            let x = x as f64;
+            let delta = x - self.mean[i];
+            self.mean[i] += delta / self.count;
+            self.m2[i] += delta * (x - self.mean[i]);
+        }
+    }
+
+    pub fn mean(&self) -> &[f64] {
+        &self.mean
+    }
+
+    /// Population standard deviation of each feature (1 before any update)
+    pub fn std(&self) -> Vec<f64> {
+        if self.count == 0.0 {
+            return vec![1.0; self.mean.len()];
+        }
+        self.m2.iter().map(|m2| (m2 / self.count).sqrt()).collect()
+    }
+
+    /// Standardizes `features` in place: (x - mean) / std, clipped
+    pub fn normalize(&self, features: &mut [f32]) {
+        if self.count == 0.0 {
+            return;
+        }
+        for (i, x) in features.iter_mut().enumerate() {
+            let var = self.m2[i] / self.count;
+            let z = (*x as f64 - self.mean[i]) / (var + VAR_EPS).sqrt();
+            *x = (z as f32).clamp(-CLIP, CLIP);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use crate::agent::DQNAgent;
+use crate::features::{current_feature_set, FeatureSet};
+use crate::nn::{softmax, Network, PolicyLoss};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Environment steps collected per PPO update
+pub const ROLLOUT_STEPS: usize = 2048;
+/// Passes over each rollout per update
+const EPOCHS: usize = 4;
+const MINIBATCH: usize = 64;
+
+/// Generalized advantage estimation over one rollout: A_t = sum_k (gamma lambda)^k delta_t+k,
+/// where delta_t = r_t + gamma V(s_t+1) - V(s_t), with no bootstrap past a `done` step.
+/// `last_value` is V of the state after the final step. Returns (advantages, value targets
+/// A_t + V(s_t)).
+pub fn gae(
+    rewards: &[f32],
+    values: &[f32],
+    dones: &[bool],
+    last_value: f32,
+    gamma: f32,
+    lambda: f32,
+) -> (Vec<f32>, Vec<f32>) {
+    let mut advantages = vec![0.0; rewards.len()];
+    let mut next_value = last_value;
+    let mut running = 0.0;
+    for t in (0..rewards.len()).rev() {
+        let live = if dones[t] { 0.0 } else { 1.0 };
+        let delta = rewards[t] + gamma * next_value * live - values[t];
+        running = delta + gamma * lambda * live * running;
+        advantages[t] = running;
+        next_value = values[t];
+    }
+    let returns = advantages.iter().zip(values).map(|(a, v)| a + v).collect();
+    (advantages, returns)
+}
+
+/// Action drawn from the policy's probabilities over `logits`, with its log-probability
+pub fn sample_action(logits: &[f32], rng: &mut impl Rng) -> (usize, f32) {
+    let probs = softmax(logits);
+    let mut pick = rng.gen::<f32>();
+    let action = probs
+        .iter()
+        .position(|&p| {
+            pick -= p;
+            pick < 0.0
+        })
+        .unwrap_or(probs.len() - 1);
+    (action, probs[action].max(1e-12).ln())
+}
+
+/// Most probable action under `policy`
+pub fn most_probable(policy: &Network, features: &[f32]) -> usize {
+    let logits = policy.forward(features);
+    (0..logits.len()).fold(0, |best, a| if logits[a] > logits[best] { a } else { best })
+}
+
+/// `policy` as a `DQNAgent` whose greedy action is the most probable one (the logits stand in
+/// for Q-values), for model export
+pub fn policy_agent(policy: &Network) -> DQNAgent {
+    let mut agent = DQNAgent::new(policy.output_size(), false);
+    agent.network = policy.clone_weights();
+    agent.target_network = policy.clone_weights();
+    agent
+}
+
+/// On-policy transitions since the last update, in step order
+#[derive(Default)]
+pub struct Rollout {
+    pub states: Vec<Vec<f32>>,
+    pub actions: Vec<usize>,
+    pub log_probs: Vec<f32>,
+    pub values: Vec<f32>,
+    pub rewards: Vec<f32>,
+    pub dones: Vec<bool>,
+}
+
+/// PPO actor-critic: a softmax policy network and a separate state-value network, trained on
+/// each full rollout with GAE advantages, the clipped surrogate loss and minibatch epochs
+pub struct PpoAgent {
+    pub policy: Network,
+    pub value: Network,
+    pub features: &'static FeatureSet,
+    pub gamma: f32,
+    pub lambda: f32,
+    pub learning_rate: f32,
+    pub loss: PolicyLoss,
+    rollout: Rollout,
+    rng: ChaCha8Rng,
+}
+
+impl PpoAgent {
+    /// Fresh networks; reproducible when `seed` is set
+    pub fn new(num_actions: usize, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        PpoAgent {
+            policy: Network::new(features.size, num_actions, false, &mut rng),
+            value: Network::new(features.size, 1, false, &mut rng),
+            features,
+            gamma: 0.99,
+            lambda: 0.95,
+            learning_rate: 3e-4,
+            loss: PolicyLoss {
+                clip: 0.2,
+                entropy_coef: 0.01,
+            },
+            rollout: Rollout::default(),
+            rng,
+        }
+    }
+
+    /// Samples an action from the policy and starts a rollout step with it; `remember` must
+    /// follow with the step's outcome
+    pub fn act(&mut self, features: &[f32]) -> usize {
+        let (action, log_prob) = sample_action(&self.policy.forward(features), &mut self.rng);
+        self.rollout.states.push(features.to_vec());
+        self.rollout.actions.push(action);
+        self.rollout.log_probs.push(log_prob);
+        self.rollout.values.push(self.value.forward(features)[0]);
+        action
+    }
+
+    /// Completes the step started by `act`. A truncated episode is `done` too, but its reward
+    /// bootstraps from V(`next_features`) since the return didn't end there.
+    pub fn remember(&mut self, reward: f32, done: bool, truncated: bool, next_features: &[f32]) {
+        let reward = if truncated {
+            reward + self.gamma * self.value.forward(next_features)[0]
+        } else {
+            reward
+        };
+        self.rollout.rewards.push(reward);
+        self.rollout.dones.push(done);
+    }
+
+    pub fn rollout_len(&self) -> usize {
+        self.rollout.rewards.len()
+    }
+
+    /// PPO update on the rollout, which is then cleared; `next_features` is the state after
+    /// its last step. Advantages are normalized per rollout.
+    pub fn update(&mut self, next_features: &[f32]) {
+        let rollout = std::mem::take(&mut self.rollout);
+        let last_value = self.value.forward(next_features)[0];
+        let (mut advantages, returns) = gae(
+            &rollout.rewards,
+            &rollout.values,
+            &rollout.dones,
+            last_value,
+            self.gamma,
+            self.lambda,
+        );
+        let n = advantages.len() as f32;
+        let mean = advantages.iter().sum::<f32>() / n;
+        let std = (advantages.iter().map(|a| (a - mean).powi(2)).sum::<f32>() / n).sqrt();
+        advantages.iter_mut().for_each(|a| *a = (*a - mean) / (std + 1e-8));
+
+        let mut order: Vec<usize> = (0..advantages.len()).collect();
+        for _ in 0..EPOCHS {
+            order.shuffle(&mut self.rng);
+            for batch in order.chunks(MINIBATCH) {
+                let inputs: Vec<Vec<f32>> =
+                    batch.iter().map(|&i| rollout.states[i].clone()).collect();
+                let actions: Vec<usize> = batch.iter().map(|&i| rollout.actions[i]).collect();
+                let batch_advantages: Vec<f32> = batch.iter().map(|&i| advantages[i]).collect();
+                let old_log_probs: Vec<f32> =
+                    batch.iter().map(|&i| rollout.log_probs[i]).collect();
+                let targets: Vec<f32> = batch.iter().map(|&i| returns[i]).collect();
+                self.policy.train_batch_ppo(
+                    &inputs,
+                    &actions,
+                    &batch_advantages,
+                    &old_log_probs,
+                    &self.loss,
+                    self.learning_rate,
+                );
+                let value_outputs = vec![0; batch.len()];
+                self.value.train_batch(&inputs, &targets, &value_outputs, self.learning_rate);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::features::{current_feature_set, FeatureSet};
+use crate::nn::{Network, PolicyLoss};
+use crate::ppo::{gae, sample_action, Rollout};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// REINFORCE with a learned baseline, the simplest policy gradient: after each episode, every
+/// step's log-probability is pushed up in proportion to its Monte-Carlo return minus V(s), and
+/// V is regressed onto those returns. Meant for teaching and demos; PPO and A2C are the same
+/// idea with bootstrapping, batching and clipping layered on.
+pub struct ReinforceAgent {
+    pub policy: Network,
+    pub value: Network,
+    pub features: &'static FeatureSet,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    pub entropy_coef: f32,
+    // The current episode so far
+    episode: Rollout,
+    rng: ChaCha8Rng,
+}
+
+impl ReinforceAgent {
+    /// Fresh networks; reproducible when `seed` is set
+    pub fn new(num_actions: usize, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        ReinforceAgent {
+            policy: Network::new(features.size, num_actions, false, &mut rng),
+            value: Network::new(features.size, 1, false, &mut rng),
+            features,
+            gamma: 0.99,
+            learning_rate: 1e-3,
+            entropy_coef: 0.01,
+            episode: Rollout::default(),
+            rng,
+        }
+    }
+
+    /// Samples an action from the policy and records the step; `remember` must follow with
+    /// its outcome
+    pub fn act(&mut self, features: &[f32]) -> usize {
+        let (action, log_prob) = sample_action(&self.policy.forward(features), &mut self.rng);
+        self.episode.states.push(features.to_vec());
+        self.episode.actions.push(action);
+        self.episode.log_probs.push(log_prob);
+        self.episode.values.push(self.value.forward(features)[0]);
+        action
+    }
+
+    /// Completes the step started by `act`, and learns from the whole episode once it is
+    /// `done`. A truncated episode's return bootstraps from V(`next_features`).
+    pub fn remember(&mut self, reward: f32, done: bool, truncated: bool, next_features: &[f32]) {
+        let reward = if truncated {
+            reward + self.gamma * self.value.forward(next_features)[0]
+        } else {
+            reward
+        };
+        self.episode.rewards.push(reward);
+        self.episode.dones.push(done);
+        if done {
+            self.update();
+        }
+    }
+
+    /// One policy-gradient and one value step on the finished episode
+    fn update(&mut self) {
+        let episode = std::mem::take(&mut self.episode);
+        // lambda = 1 on a complete episode: advantages are G_t - V(s_t), targets are G_t
+        let (advantages, returns) =
+            gae(&episode.rewards, &episode.values, &episode.dones, 0.0, self.gamma, 1.0);
+        // On-policy, so every probability ratio is 1 and nothing is clipped: the surrogate's
+        // gradient is the REINFORCE gradient
+        let loss = PolicyLoss {
+            clip: f32::INFINITY,
+            entropy_coef: self.entropy_coef,
+        };
+        let (inputs, actions, lr) = (&episode.states, &episode.actions, self.learning_rate);
+        self.policy.train_batch_ppo(inputs, actions, &advantages, &episode.log_probs, &loss, lr);
+        self.value.train_batch(inputs, &returns, &vec![0; inputs.len()], lr);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::agent::Experience;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Added to |TD error| so no transition's priority is zero
+const PRIORITY_EPS: f32 = 0.01;
+
+/// FIFO replay buffer that also indexes terminal (`done`) transitions for stratified sampling,
+/// and optionally samples proportionally to externally computed priorities
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayBuffer {
+    items: VecDeque<Experience>,
+    // Sampling priority per transition; only used once `set_priorities` has been called
+    priorities: VecDeque<f32>,
+    prioritized: bool,
+    max_priority: f32,
+    capacity: usize,
+    // Insertion ids of terminal transitions still in the buffer, oldest first
+    terminal_ids: VecDeque<u64>,
+    // Insertion ids at which an episode began (set by `end_episode`), oldest first
+    #[serde(default)]
+    episode_starts: VecDeque<u64>,
+    next_id: u64,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            items: VecDeque::with_capacity(capacity),
+            priorities: VecDeque::with_capacity(capacity),
+            prioritized: false,
+            max_priority: 1.0,
+            capacity,
+            terminal_ids: VecDeque::new(),
+            episode_starts: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, exp: Experience) {
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+            self.priorities.pop_front();
+            let oldest = self.oldest_id();
+            while self.terminal_ids.front().is_some_and(|&id| id < oldest) {
+                self.terminal_ids.pop_front();
+            }
+            while self.episode_starts.front().is_some_and(|&id| id < oldest) {
+                self.episode_starts.pop_front();
+            }
+        }
+        if exp.done {
+            self.terminal_ids.push_back(self.next_id);
+        }
+        self.items.push_back(exp);
+        // New transitions get the max priority so they are sampled before the next recompute
+        self.priorities.push_back(self.max_priority);
+        self.next_id += 1;
+    }
+
+    /// Marks an episode boundary: the next transition pushed starts a new episode
+    pub fn end_episode(&mut self) {
+        if self.episode_starts.back() != Some(&self.next_id) {
+            self.episode_starts.push_back(self.next_id);
+        }
+    }
+
+    /// Replaces every transition's priority with |td_error| + eps (in buffer order) and switches
+    /// sampling to priority-proportional
+    pub fn set_priorities(&mut self, td_errors: Vec<f32>) {
+        self.priorities = td_errors.into_iter().map(|e| e + PRIORITY_EPS).collect();
+        self.max_priority = self.priorities.iter().cloned().fold(PRIORITY_EPS, f32::max);
+        self.prioritized = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn get(&self, idx: usize) -> &Experience {
+        &self.items[idx]
+    }
+
+    /// The most recently inserted transition
+    pub fn last_mut(&mut self) -> Option<&mut Experience> {
+        self.items.back_mut()
+    }
+
+    /// Number of transitions taking each action among the `recent` most recently inserted ones
+    pub fn action_histogram(&self, recent: usize, num_actions: usize) -> Vec<usize> {
+        let mut counts = vec![0; num_actions];
+        for exp in self.items.range(self.items.len().saturating_sub(recent)..) {
+            counts[exp.action] += 1;
+        }
+        counts
+    }
+
+    /// Id of the transition currently at index 0
+    fn oldest_id(&self) -> u64 {
+        self.next_id - self.items.len() as u64
+    }
+
+    /// Uniform sample of `batch` indices, guaranteeing at least `terminal_fraction` of them are
+    /// terminal transitions when the buffer holds enough of them. With `max_age`, only the
+    /// `max_age` most recently inserted transitions can be drawn.
+    pub fn sample_indices(
+        &self,
+        batch: usize,
+        terminal_fraction: f32,
+        max_age: Option<usize>,
+        rng: &mut impl Rng,
+    ) -> Vec<usize> {
+        let len = self.items.len();
+        let start = max_age.map_or(0, |age| len.saturating_sub(age.max(1)));
+        let oldest = self.oldest_id();
+        // Terminal ids are in insertion order, so the recent ones are a suffix
+        let first_terminal = self.terminal_ids.partition_point(|&id| id < oldest + start as u64);
+        let recent_terminals = self.terminal_ids.len() - first_terminal;
+        let wanted = (terminal_fraction * batch as f32).ceil() as usize;
+        let n_terminal = wanted.min(batch).min(recent_terminals);
+
+        let mut indices: Vec<usize> = (0..n_terminal)
+            .map(|_| {
+                let id = self.terminal_ids[rng.gen_range(first_terminal..self.terminal_ids.len())];
+                (id - oldest) as usize
+            })
+            .collect();
+        if self.prioritized {
+            // Proportional sampling by inverse CDF over the cumulative priorities
+            let mut cumulative = Vec::with_capacity(len - start);
+            let mut total = 0.0f32;
+            for &p in self.priorities.range(start..) {
+                total += p;
+                cumulative.push(total);
+            }
+            indices.extend((n_terminal..batch).map(|_| {
+                let pick = rng.gen::<f32>() * total;
+                start + cumulative.partition_point(|&c| c <= pick).min(len - start - 1)
+            }));
+        } else {
+            indices.extend((n_terminal..batch).map(|_| rng.gen_range(start..len)));
+        }
+        indices
+    }
+
+    /// `batch` uniformly drawn windows of `length` consecutive buffer indices, each inside a
+    /// single episode. Empty when no episode in the buffer has `length` transitions.
+    pub fn sample_sequences(
+        &self,
+        batch: usize,
+        length: usize,
+        rng: &mut impl Rng,
+    ) -> Vec<Vec<usize>> {
+        let len = self.items.len();
+        if length == 0 || length > len {
+            return Vec::new();
+        }
+        let oldest = self.oldest_id();
+        // A window is valid when no episode starts after its first transition and at or
+        // before its last
+        let starts: Vec<usize> = (0..=len - length)
+            .filter(|&i| {
+                let first = oldest + i as u64;
+                let next_start = self.episode_starts.partition_point(|&id| id <= first);
+                self.episode_starts
+                    .get(next_start)
+                    .is_none_or(|&id| id >= first + length as u64)
+            })
+            .collect();
+        if starts.is_empty() {
+            return Vec::new();
+        }
+        (0..batch)
+            .map(|_| {
+                let start = starts[rng.gen_range(0..starts.len())];
+                (start..start + length).collect()
+            })
+            .collect()
+    }
+}
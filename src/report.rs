@@ -0,0 +1,87 @@
+/// Metric series charted by `html_report`, from the `--log-file` records
+pub const REPORT_SERIES: [&str; 5] = ["score", "avg", "epsilon", "lr", "loss"];
+
+/// Points per chart; longer runs are thinned to every Nth record
+const MAX_POINTS: usize = 2000;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 200.0;
+
+/// Self-contained HTML page with one inline SVG line chart per metric in `REPORT_SERIES`,
+/// plotted against episode. Series missing from every record (e.g. `loss`, which the trainer
+/// doesn't log) are left out.
+pub fn html_report(title: &str, records: &[serde_json::Value]) -> String {
+    let stride = records.len().div_ceil(MAX_POINTS).max(1);
+    let sampled: Vec<&serde_json::Value> = records.iter().step_by(stride).collect();
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape(title)));
+    html.push_str(
+        "<style>body { font-family: sans-serif; margin: 2em; } \
+         svg { background: #fafafa; border: 1px solid #ddd; } \
+         polyline { fill: none; stroke: #1f77b4; stroke-width: 1.5; } \
+         .range { color: #666; font-size: 0.9em; }</style>\n",
+    );
+    html.push_str(&format!("</head>\n<body>\n<h1>{}</h1>\n", escape(title)));
+    html.push_str(&format!("<p>{} episodes</p>\n", records.len()));
+
+    for series in REPORT_SERIES {
+        let points: Vec<(f64, f64)> = sampled
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| {
+                let x = r["episode"].as_f64().unwrap_or((i * stride) as f64);
+                Some((x, r[series].as_f64()?))
+            })
+            .collect();
+        if points.is_empty() {
+            continue;
+        }
+        html.push_str(&chart(series, &points));
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// `<h2>` heading, value range and SVG polyline of one series
+fn chart(series: &str, points: &[(f64, f64)]) -> String {
+    let bounds = |values: &mut dyn Iterator<Item = f64>| {
+        values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)))
+    };
+    let (x_min, x_max) = bounds(&mut points.iter().map(|p| p.0));
+    let (y_min, y_max) = bounds(&mut points.iter().map(|p| p.1));
+    // A flat series is drawn through the middle
+    let scale = |v: f64, lo: f64, hi: f64| if hi > lo { (v - lo) / (hi - lo) } else { 0.5 };
+
+    let coords: Vec<String> = points
+        .iter()
+        .map(|&(x, y)| {
+            let px = scale(x, x_min, x_max) * WIDTH;
+            let py = HEIGHT - scale(y, y_min, y_max) * HEIGHT;
+            format!("{:.1},{:.1}", px, py)
+        })
+        .collect();
+
+    format!(
+        "<h2>{}</h2>\n\
+         <p class=\"range\">episodes {} to {} | min {} | max {}</p>\n\
+         <svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+         <polyline data-series=\"{}\" points=\"{}\"/>\n\
+         </svg>\n",
+        series,
+        x_min,
+        x_max,
+        // Logged as f32, so print at f32 precision
+        y_min as f32,
+        y_max as f32,
+        series,
+        coords.join(" "),
+        w = WIDTH,
+        h = HEIGHT
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
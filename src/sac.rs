@@ -0,0 +1,138 @@
+use crate::agent::Experience;
+use crate::features::{current_feature_set, FeatureSet};
+use crate::nn::{softmax, Network};
+use crate::ppo::sample_action;
+use crate::replay::ReplayBuffer;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+const REPLAY_CAPACITY: usize = 100_000;
+/// Transitions stored before the first update
+const WARMUP: usize = 1_000;
+/// Target policy entropy as a fraction of the uniform policy's, ln(actions)
+const TARGET_ENTROPY_SCALE: f32 = 0.5;
+
+/// Discrete soft actor-critic: a categorical policy trained toward the Boltzmann distribution
+/// of twin Q-networks, whose targets are the soft value sum_a pi(a) (min_i Q'_i(a) -
+/// alpha log pi(a)) of slowly tracking target copies. The temperature alpha is tuned
+/// automatically so the policy's entropy stays near the target, which keeps exploration alive
+/// after any fixed schedule would have decayed.
+pub struct SacAgent {
+    pub policy: Network,
+    q: [Network; 2],
+    q_target: [Network; 2],
+    pub features: &'static FeatureSet,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    /// Soft target-update rate
+    pub tau: f32,
+    pub batch_size: usize,
+    pub target_entropy: f32,
+    log_alpha: f32,
+    replay: ReplayBuffer,
+    rng: ChaCha8Rng,
+}
+
+impl SacAgent {
+    /// Fresh networks with alpha = 1; reproducible when `seed` is set
+    pub fn new(num_actions: usize, seed: Option<u64>) -> Self {
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
+        let features = current_feature_set();
+        let size = features.size;
+        let policy = Network::new(size, num_actions, false, &mut rng);
+        let q = [
+            Network::new(size, num_actions, false, &mut rng),
+            Network::new(size, num_actions, false, &mut rng),
+        ];
+        let q_target = [q[0].clone_weights(), q[1].clone_weights()];
+        SacAgent {
+            policy,
+            q,
+            q_target,
+            features,
+            gamma: 0.99,
+            learning_rate: 3e-4,
+            tau: 0.005,
+            batch_size: 64,
+            target_entropy: TARGET_ENTROPY_SCALE * (num_actions as f32).ln(),
+            log_alpha: 0.0,
+            replay: ReplayBuffer::new(REPLAY_CAPACITY),
+            rng,
+        }
+    }
+
+    /// Entropy temperature
+    pub fn alpha(&self) -> f32 {
+        self.log_alpha.exp()
+    }
+
+    /// Action sampled from the policy
+    pub fn act(&mut self, features: &[f32]) -> usize {
+        sample_action(&self.policy.forward(features), &mut self.rng).0
+    }
+
+    /// Stores `exp` and, once `WARMUP` transitions are stored, takes a training step
+    pub fn remember(&mut self, exp: Experience) {
+        self.replay.push(exp);
+        if self.replay.len() >= WARMUP {
+            self.train_step();
+        }
+    }
+
+    /// One update of both Q-networks, the policy and alpha on a uniform replay batch, then a
+    /// soft update of the target networks. Returns the batch's mean policy entropy.
+    pub fn train_step(&mut self) -> f32 {
+        let indices = self.replay.sample_indices(self.batch_size, 0.0, None, &mut self.rng);
+        let batch: Vec<&Experience> = indices.iter().map(|&i| self.replay.get(i)).collect();
+        let states: Vec<Vec<f32>> = batch.iter().map(|e| e.state.clone()).collect();
+        let next_states: Vec<Vec<f32>> = batch.iter().map(|e| e.next_state.clone()).collect();
+        let actions: Vec<usize> = batch.iter().map(|e| e.action).collect();
+        let alpha = self.alpha();
+
+        let next_probs: Vec<Vec<f32>> =
+            self.policy.predict_batch(&next_states).iter().map(|l| softmax(l)).collect();
+        let next_q = min_q(&self.q_target, &next_states);
+        let targets: Vec<f32> = batch
+            .iter()
+            .enumerate()
+            .map(|(b, exp)| {
+                if exp.done {
+                    return exp.reward;
+                }
+                let soft_value: f32 = next_probs[b]
+                    .iter()
+                    .zip(&next_q[b])
+                    .map(|(p, q)| p * (q - alpha * p.max(1e-12).ln()))
+                    .sum();
+                exp.reward + exp.bootstrap_discount(self.gamma) * soft_value
+            })
+            .collect();
+        for q in &mut self.q {
+            q.train_batch(&states, &targets, &actions, self.learning_rate);
+        }
+
+        let probs: Vec<Vec<f32>> =
+            self.policy.predict_batch(&states).iter().map(|l| softmax(l)).collect();
+        let entropy = probs
+            .iter()
+            .map(|p| -p.iter().map(|p| p * p.max(1e-12).ln()).sum::<f32>())
+            .sum::<f32>()
+            / probs.len() as f32;
+        let q_values = min_q(&self.q, &states);
+        self.policy.train_batch_soft_policy(&states, &q_values, alpha, self.learning_rate);
+        // d/dlog(alpha) of log(alpha) (H - target): alpha falls while the policy is more
+        // random than the target and rises once it is less
+        self.log_alpha -= self.learning_rate * (entropy - self.target_entropy);
+
+        for (q, target) in self.q.iter().zip(&mut self.q_target) {
+            q.soft_update_into(target, self.tau);
+        }
+        entropy
+    }
+}
+
+/// Element-wise minimum of the twin networks' outputs over a batch
+fn min_q(nets: &[Network; 2], inputs: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let (a, b) = (nets[0].predict_batch(inputs), nets[1].predict_batch(inputs));
+    a.iter().zip(&b).map(|(a, b)| a.iter().zip(b).map(|(x, y)| x.min(*y)).collect()).collect()
+}
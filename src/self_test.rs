@@ -0,0 +1,2276 @@
+use crate::a2c::A2cAgent;
+use crate::agent::{DQNAgent, Experience, OverestimationStats, TargetRule};
+use crate::curriculum::{Curriculum, CurriculumMetric};
+use crate::distributional::{ReturnDistribution, Support};
+use crate::engine::{Direction, EnvMode, Point, Potential, SnakeEngine, ORTHOGONAL_ACTIONS};
+use crate::es::{centered_ranks, parameters, EvolutionStrategy};
+use crate::distill::distill_tree;
+use crate::eval::{
+    derive_seed, evaluate, evaluate_seeds, run_episode, softmax_sample, EvalOptions,
+};
+use crate::features::{
+    extract_features, extract_features_into, feature_set, Scratch, FEATURE_NAMES, FEATURE_SETS,
+};
+use crate::ga::GeneticAlgorithm;
+use crate::heuristic::{greedy_food_action, heuristic_agreement, heuristic_dataset};
+use crate::logger::{logfmt, read_records, MetricsLog};
+use crate::model_io::{
+    export_model, export_npz, import_model, load_checkpoint, load_model, prune_checkpoints,
+    save_checkpoint,
+};
+use crate::nn::{matmul_at_b, softmax, Network, PolicyLoss, INPUT_SIZE};
+use crate::normalize::ObsNormalizer;
+use crate::ppo::gae;
+use crate::reinforce::ReinforceAgent;
+use crate::replay::ReplayBuffer;
+use crate::sac::SacAgent;
+use crate::report::html_report;
+use crate::tabular::TabularAgent;
+use crate::tools::{
+    activations_json, format_action_histogram, policy_map, record_best_game, value_trace_csv,
+    BestModel, Milestones, VisitHeatmap, ARROWS,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Records one check: Ok when it holds, else Err with its description
+type Check<'a> = dyn FnMut(bool, &str) -> Result<(), String> + 'a;
+
+/// Scripted deterministic checks of every subsystem, one group per function below. Returns
+/// the number of checks passed.
+pub fn run(grid_size: i32) -> Result<usize, String> {
+    let mut checks = 0;
+    let mut check = |ok: bool, what: &str| -> Result<(), String> {
+        checks += 1;
+        if ok {
+            Ok(())
+        } else {
+            Err(what.to_string())
+        }
+    };
+
+    engine_invariants(grid_size, &mut check)?;
+    replay(&mut check)?;
+    features(grid_size, &mut check)?;
+    dqn(grid_size, &mut check)?;
+    evaluation(grid_size, &mut check)?;
+    numerics(&mut check)?;
+    logging(grid_size, &mut check)?;
+    heads(grid_size, &mut check)?;
+    checkpoints(grid_size, &mut check)?;
+    algorithms(&mut check)?;
+
+    Ok(checks)
+}
+
+/// Engine invariants on scripted episodes: resets, eating, reversals, collisions, the reward
+/// terms and shaping (with and without the tail term), sticky actions, diagonals, food
+/// placement, flood features, the doom cutoff, truncation and action repeats
+fn engine_invariants(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let mut engine = SnakeEngine::new(grid_size);
+    let mid = grid_size / 2;
+    let area = (grid_size * grid_size) as usize;
+    let far = Point { x: 0, y: 0 };
+
+    // Reset
+    check(engine.snake.len() == 3, "reset: snake length is 3")?;
+    check(engine.snake[0].x == mid && engine.snake[0].y == mid, "reset: head at center")?;
+    check(engine.score == 0 && !engine.game_over, "reset: score 0, not over")?;
+
+    // Eat twice straight ahead: +10 score and +1 length each
+    for eaten in 1..=2 {
+        let head = engine.snake[0];
+        engine.food = Point { x: head.x + 1, y: head.y };
+        let (reward, done) = engine.step(1);
+        check(!done && reward == 10.0, "food: reward 10 and not done")?;
+        check(engine.score == 10 * eaten, "food: score increments by 10")?;
+        check(engine.snake.len() == 3 + eaten as usize, "food: snake grows by 1")?;
+        check(engine.snake.len() <= area, "food: length within area")?;
+    }
+
+    // Reversal is ignored: moving Left while heading Right keeps going Right
+    engine.food = far;
+    let head = engine.snake[0];
+    engine.step(3);
+    check(engine.snake[0].x == head.x + 1, "step: reversal ignored")?;
+
+    // Down, Left, Up curls the head back into the body
+    engine.step(2);
+    engine.step(3);
+    let (reward, done) = engine.step(0);
+    check(done && engine.game_over && reward == -10.0, "death: self-collision")?;
+
+    // Walk Right into the wall, head in bounds until death
+    engine.reset();
+    engine.food = far;
+    let mut steps = 0;
+    loop {
+        let (reward, done) = engine.step(1);
+        steps += 1;
+        if done {
+            check(reward == -10.0, "death: wall penalty")?;
+            break;
+        }
+        let head = engine.snake[0];
+        check(
+            head.x >= 0 && head.x < grid_size && head.y >= 0 && head.y < grid_size,
+            "step: head in bounds",
+        )?;
+        check(engine.snake.len() == 3, "step: length unchanged without food")?;
+    }
+    check(steps == grid_size - mid, "death: wall reached after expected steps")?;
+    check(!engine.snake.is_empty(), "death: snake still non-empty")?;
+
+    // An obstacle cell kills like a wall
+    engine.reset();
+    engine.food = far;
+    engine.obstacles = vec![Point { x: mid + 1, y: mid }];
+    let (reward, done) = engine.step(1);
+    check(done && reward == -10.0, "death: obstacle collision")?;
+
+    // Length-scaled death penalty: the same wall death costs more with a longer snake
+    let mut wall_death = |length: i32| {
+        engine.reset();
+        engine.food = far;
+        engine.reward.death_length_scale = 0.5;
+        engine.snake = (0..length).map(|i| Point { x: grid_size - 1 - i, y: mid }).collect();
+        engine.step(1).0
+    };
+    let short_death = wall_death(3);
+    let long_death = wall_death(8);
+    engine.reward.death_length_scale = 0.0;
+    check(short_death == -10.0, "death scale: starting length pays the base penalty")?;
+    check(long_death == -12.5, "death scale: longer snake pays more")?;
+
+    // Length-sloped food reward: a snake of length L eating earns 10 + slope * L
+    let mut food_reward = |length: i32, slope: f32| {
+        engine.reset();
+        engine.reward.food_length_slope = slope;
+        engine.snake = (0..length).map(|i| Point { x: mid - i, y: mid }).collect();
+        engine.food = Point { x: mid + 1, y: mid };
+        engine.step(1).0
+    };
+    let rewards = [(3, 0.5), (8, 0.5), (8, -0.25)].map(|(len, slope)| food_reward(len, slope));
+    engine.reward.food_length_slope = 0.0;
+    check(
+        rewards == [10.0 + 0.5 * 3.0, 10.0 + 0.5 * 8.0, 10.0 - 0.25 * 8.0],
+        "food slope: reward is base + slope x length",
+    )?;
+
+    // Revisit penalty: a one-cell snake circling a 2x2 square re-enters its first cell on the
+    // fifth move, and only that move costs the penalty
+    let mut square_rewards = |window: usize| {
+        engine.reset();
+        engine.snake.truncate(1);
+        engine.food = far;
+        engine.obstacles.clear();
+        engine.reward.revisit_window = window;
+        engine.reward.revisit_penalty = 0.25;
+        [1, 2, 3, 0, 1].map(|action| engine.step(action).0)
+    };
+    let plain = square_rewards(0);
+    let penalized = square_rewards(4);
+    engine.reward.revisit_window = 0;
+    check(plain[..4] == penalized[..4], "revisit: no penalty on new cells")?;
+    check(plain[4] - penalized[4] == 0.25, "revisit: re-entering a recent cell is penalized")?;
+
+    // Anti-loop: reversals are blocked, so the tightest oscillation is a one-cell snake circling
+    // a 2x2 square. Over a window of 8 its heads cover only 4 cells, so every move from the 8th
+    // on is penalized, and none before the window fills.
+    let mut circling_rewards = |window: usize| {
+        engine.reset();
+        engine.snake.truncate(1);
+        engine.food = far;
+        engine.obstacles.clear();
+        engine.reward.anti_loop_window = window;
+        engine.reward.anti_loop_penalty = 0.5;
+        [1, 2, 3, 0, 1, 2, 3, 0, 1, 2].map(|action| engine.step(action).0)
+    };
+    let plain = circling_rewards(0);
+    let penalized = circling_rewards(8);
+    engine.reward.anti_loop_window = 0;
+    check(plain[..7] == penalized[..7], "anti-loop: no penalty before the window fills")?;
+    check(
+        plain[7..].iter().zip(&penalized[7..]).all(|(p, q)| p - q == 0.5),
+        "anti-loop: circling over a few cells is penalized",
+    )?;
+
+    // Sticky actions: with p = 1 every request is replaced by the previous action, so a fresh
+    // snake asked to turn Down, Up and Down again keeps moving Right
+    engine.reset();
+    engine.food = far;
+    engine.sticky_actions = 1.0;
+    let head = engine.snake[0];
+    let executed = [2, 0, 2].map(|action| {
+        engine.step(action);
+        engine.last_action
+    });
+    engine.sticky_actions = 0.0;
+    check(
+        executed == [1, 1, 1] && engine.snake[0].x == head.x + 3 && engine.snake[0].y == head.y,
+        "sticky actions: p = 1 always repeats the previous action",
+    )?;
+
+    // Body proximity: moving alongside its own coil (nearest segment 1 away) costs more than
+    // moving ahead of a stretched-out body (nearest segment past the neck 2 away)
+    let stretched: Vec<Point> = (0..5).map(|i| Point { x: mid - i, y: mid }).collect();
+    let coiled = [(0, 0), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 2), (-1, 3)]
+        .map(|(dx, dy)| Point { x: mid + dx, y: mid + dy })
+        .to_vec();
+    let mut proximity_penalty = |body: &[Point]| {
+        let mut reward = |weight: f32| {
+            engine.reset();
+            engine.food = far;
+            engine.reward.body_proximity_penalty = weight;
+            engine.snake = body.to_vec();
+            // Keep moving the way the head points (away from the neck)
+            let (direction, action) = if body[1].x < body[0].x {
+                (Direction::Right, 1)
+            } else {
+                (Direction::Left, 3)
+            };
+            engine.direction = direction;
+            engine.step(action).0
+        };
+        reward(0.0) - reward(1.0)
+    };
+    let (stretched_penalty, coiled_penalty) =
+        (proximity_penalty(&stretched), proximity_penalty(&coiled));
+    engine.reward.body_proximity_penalty = 0.0;
+    check(
+        stretched_penalty == 0.5 && coiled_penalty == 1.0,
+        "body proximity: coiled penalized more than stretched",
+    )?;
+
+    // Space delta: an obstacle column at x = 5 with a door at the snake's row; the door's
+    // cells on either side are its only ways in. Leaving it heading Right, the second move
+    // frees the cell past the door and the head's reachable space grows by the whole left
+    // side; heading into it, the second move puts the head in the door with the body behind,
+    // cutting the left side off. Only the first earns a bonus.
+    let mut space_bonus = |head_x: i32| {
+        let mut second_reward = |bonus: f32| {
+            engine.reset();
+            engine.food = far;
+            engine.obstacles =
+                (0..grid_size).filter(|&y| y != mid).map(|y| Point { x: 5, y }).collect();
+            engine.direction = Direction::Right;
+            engine.snake = (0..3).map(|i| Point { x: head_x - i, y: mid }).collect();
+            engine.reward.space_delta_bonus = bonus;
+            engine.step(1);
+            engine.step(1).0
+        };
+        second_reward(1.0) - second_reward(0.0)
+    };
+    let (opening, closing) = (space_bonus(7), space_bonus(3));
+    engine.reward.space_delta_bonus = 0.0;
+    engine.obstacles.clear();
+    check(
+        opening > 0.0 && closing < 0.0,
+        "space delta: opening up space earns more than closing it off",
+    )?;
+
+    // Coverage bonus: on the same 2x2 loop the first three moves enter fresh cells; the fourth
+    // returns to the starting cell and the fifth repeats the first
+    let mut loop_rewards = |bonus: f32| {
+        engine.reset();
+        engine.snake.truncate(1);
+        engine.food = far;
+        engine.obstacles.clear();
+        engine.reward.coverage_bonus = bonus;
+        [1, 2, 3, 0, 1].map(|action| engine.step(action).0)
+    };
+    let plain = loop_rewards(0.0);
+    let covered = loop_rewards(0.5);
+    engine.reward.coverage_bonus = 0.0;
+    let gains = [0, 1, 2, 3, 4].map(|i| covered[i] - plain[i]);
+    check(gains[..3] == [0.5; 3], "coverage: entering a fresh cell earns the bonus")?;
+    check(gains[3..] == [0.0; 2], "coverage: re-entering a visited cell does not")?;
+
+    // Length-scaled starvation (2 steps per segment): with the counter at a 3-cell snake's
+    // limit, it starves on the next empty step while an 8-cell snake has time left
+    let mut starves = |length: i32| {
+        engine.reset();
+        engine.food = far;
+        engine.starvation_length_scale = 2.0;
+        engine.snake = (0..length).map(|i| Point { x: mid - i, y: mid }).collect();
+        engine.steps_without_food = (grid_size * grid_size) + 6;
+        let limit = engine.starvation_limit();
+        (limit, engine.step(0).1)
+    };
+    let (short_limit, short_starved) = starves(3);
+    let (long_limit, long_starved) = starves(8);
+    engine.starvation_length_scale = 0.0;
+    check(long_limit > short_limit, "starvation scale: longer snake has a larger limit")?;
+    check(short_starved && !long_starved, "starvation scale: longer snake survives longer")?;
+
+    // Starvation ramp over the last quarter of the limit: no penalty before it, then a per-step
+    // penalty that grows as the counter nears the limit
+    let limit = grid_size * grid_size;
+    let mut starving_reward = |ramp: f32, counter: i32| {
+        engine.reset();
+        engine.snake.truncate(1);
+        engine.food = far;
+        engine.reward.starvation_ramp = ramp;
+        engine.steps_without_food = counter;
+        engine.step(1).0
+    };
+    let ramp_penalties = [limit / 2, limit - limit / 8, limit - limit / 16, limit - 1]
+        .map(|counter| starving_reward(0.0, counter) - starving_reward(0.25, counter));
+    engine.reward.starvation_ramp = 0.0;
+    check(ramp_penalties[0] == 0.0, "starvation ramp: no penalty early")?;
+    check(
+        ramp_penalties.windows(2).all(|w| w[0] < w[1]),
+        "starvation ramp: penalty grows toward the limit",
+    )?;
+
+    // Potential-based shaping with gamma = 1 telescopes: a closed loop of states sums to zero
+    engine.reset();
+    engine.snake.truncate(1);
+    engine.food = far;
+    engine.reward.potential = Some(Potential::Distance);
+    engine.reward.potential_gamma = 1.0;
+    let shaping: f32 = [1, 2, 3, 0].iter().map(|&action| engine.step(action).0).sum();
+    check(shaping.abs() < 1e-5, "shaping: potential telescopes over a closed loop")?;
+    engine.reward.potential = None;
+
+    // Diagonals: Up-Right moves the head by (+1, -1); a diagonal step onto an obstacle kills
+    engine.diagonals = true;
+    engine.reset();
+    engine.food = far;
+    engine.obstacles.clear();
+    let head = engine.snake[0];
+    let (_, done) = engine.step(4);
+    check(
+        !done && engine.snake[0].x == head.x + 1 && engine.snake[0].y == head.y - 1,
+        "diagonal: head advances by (+1, -1)",
+    )?;
+    let head = engine.snake[0];
+    engine.obstacles = vec![Point { x: head.x + 1, y: head.y + 1 }];
+    let (reward, done) = engine.step(5);
+    check(done && reward == -10.0, "diagonal: collision detected")?;
+    engine.diagonals = false;
+
+    // Corner exclusion: food never lands on a corner cell
+    engine.corner_food_exclusion = 1.0;
+    let last = grid_size - 1;
+    let mut corner_food = false;
+    for _ in 0..1000 {
+        engine.reset();
+        let f = engine.food;
+        corner_food |= (f.x == 0 || f.x == last) && (f.y == 0 || f.y == last);
+    }
+    check(!corner_food, "food: no corner spawns with corner exclusion")?;
+    engine.corner_food_exclusion = 0.0;
+
+    // Food script: food appears at the scripted cells in order, skipping the center entry while
+    // the snake's body covers it, wrapping at the end and restarting on reset
+    engine.food_script = [(0, 0), (mid, mid), (last, 0), (0, last)]
+        .map(|(x, y)| Point { x, y })
+        .to_vec();
+    engine.reset();
+    let mut spawned = vec![(engine.food.x, engine.food.y)];
+    for _ in 0..3 {
+        let head = engine.snake[0];
+        engine.food = Point { x: head.x + 1, y: head.y };
+        engine.step(1);
+        spawned.push((engine.food.x, engine.food.y));
+    }
+    engine.reset();
+    spawned.push((engine.food.x, engine.food.y));
+    engine.food_script.clear();
+    check(
+        spawned == [(0, 0), (last, 0), (0, last), (0, 0), (0, 0)],
+        "food script: scripted positions in order",
+    )?;
+
+    // Flood features on a pinned board: an obstacle column at x = 2 walls off columns 0-1, the
+    // snake runs down column 0 heading Down. Straight and left (board right) reach the free
+    // cells of the pocket, right is the wall, and the global flood sees the same pocket.
+    engine.reset();
+    engine.snake = vec![Point { x: 0, y: 5 }, Point { x: 0, y: 4 }, Point { x: 0, y: 3 }];
+    engine.direction = Direction::Down;
+    engine.obstacles = (0..grid_size).map(|y| Point { x: 2, y }).collect();
+    let total_free = (area - 3 - grid_size as usize) as f32;
+    let pocket = (2 * grid_size - 3) as f32 / total_free;
+    let features = extract_features(&engine);
+    let flood = |name: &str| features[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+    check(flood("flood_ratio") == pocket, "flood: global counts the pocket")?;
+    check(flood("flood_straight") == pocket, "flood: straight counts the pocket")?;
+    check(flood("flood_right") == 0.0, "flood: blocked direction is 0")?;
+    check(flood("flood_left") == pocket, "flood: left counts the pocket")?;
+    engine.obstacles.clear();
+
+    // Largest region: a wall along row 3 (the snake lies in it, the cell ahead is blocked)
+    // splits the board into 3 rows above and the rest below. Heading Right the bigger region is
+    // to the snake's right; turned around to head Left, it is to its left.
+    let headings = [(Direction::Right, 1, "open_right"), (Direction::Left, -1, "open_left")];
+    for (direction, step, expected) in headings {
+        engine.reset();
+        engine.direction = direction;
+        engine.snake = (0..3).map(|i| Point { x: mid - step * i, y: 3 }).collect();
+        engine.obstacles = (0..grid_size)
+            .filter(|&x| !engine.snake.iter().any(|s| s.x == x))
+            .map(|x| Point { x, y: 3 })
+            .collect();
+        let features = extract_features(&engine);
+        let open = |name: &str| features[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+        check(
+            open(expected) == 1.0
+                && open("open_straight") + open("open_right") + open("open_left") == 1.0,
+            "largest region: one-hot points to the larger side",
+        )?;
+    }
+    engine.obstacles.clear();
+
+    // Open food bias: with the same obstacle column walling off columns 0-1, food eaten at the
+    // center respawns in that trapped pocket at a third of the uniform rate or less
+    let mut spawner = SnakeEngine::new(grid_size);
+    spawner.seed(3);
+    let mut trapped_share = |bias: f32| {
+        spawner.food_open_bias = bias;
+        let mut trapped = 0;
+        for _ in 0..1000 {
+            spawner.reset();
+            spawner.obstacles = (0..grid_size).map(|y| Point { x: 2, y }).collect();
+            let head = spawner.snake[0];
+            spawner.food = Point { x: head.x + 1, y: head.y };
+            spawner.step(1);
+            trapped += (spawner.food.x < 2) as u32;
+        }
+        trapped as f32 / 1000.0
+    };
+    let uniform = trapped_share(0.0);
+    let open = trapped_share(1.0);
+    check(
+        uniform > 0.0 && open <= uniform / 3.0,
+        "food bias: open mode rarely spawns food in a trapped region",
+    )?;
+
+    // Doom cutoff: moving Up into a two-cell pocket (walled by obstacles at x = 1) leaves a
+    // length-5 snake 2 reachable cells, which ends the episode only when the cutoff is set
+    for cutoff in [None, Some(1.0)] {
+        engine.reset();
+        engine.snake = (2..7).map(|y| Point { x: 0, y }).collect();
+        engine.direction = Direction::Up;
+        engine.food = far;
+        engine.obstacles = (0..3).map(|y| Point { x: 1, y }).collect();
+        engine.doom_cutoff = cutoff;
+        let (reward, done) = engine.step(0);
+        match cutoff {
+            None => check(!done, "doom: trapped snake lives on without cutoff")?,
+            Some(_) => check(done && reward == -10.0, "doom: cutoff ends a trapped episode")?,
+        }
+    }
+    engine.doom_cutoff = None;
+    engine.obstacles.clear();
+
+    // Truncation: with --truncate-at 3 the third step ends the episode as truncated, not as a
+    // death; a death on the cutoff step is still terminal
+    engine.reset();
+    engine.food = far;
+    engine.truncate_at = Some(3);
+    let dones = [1, 1, 1].map(|action| engine.step(action).1);
+    check(
+        dones == [false, false, true] && engine.truncated && !engine.game_over,
+        "truncation: the cutoff step is truncated, not terminal",
+    )?;
+    engine.reset();
+    engine.truncate_at = Some(1);
+    engine.snake = (0..3).map(|i| Point { x: grid_size - 1 - i, y: mid }).collect();
+    let (_, done) = engine.step(1);
+    engine.truncate_at = None;
+    check(done && engine.game_over && !engine.truncated, "truncation: a death stays terminal")?;
+
+    // Action repeat: three Right steps taken one at a time, then as one repeated action, must
+    // accumulate r0 + g r1 + g^2 r2 and bootstrap with g^3
+    let gamma = 0.9;
+    let far_right = |engine: &mut SnakeEngine| {
+        engine.reset();
+        engine.food = Point { x: grid_size - 1, y: 0 };
+    };
+    far_right(&mut engine);
+    let singles = [1, 1, 1].map(|action| engine.step(action).0);
+    far_right(&mut engine);
+    let (reward, done, span) = engine.step_repeat(1, 3, gamma);
+    let expected = singles[0] + gamma * singles[1] + gamma * gamma * singles[2];
+    check(!done && span == 3, "repeat: three steps in one span")?;
+    check((reward - expected).abs() < 1e-5, "repeat: rewards discounted within the span")?;
+    let exp = Experience {
+        state: Vec::new(),
+        action: 1,
+        reward,
+        next_state: Vec::new(),
+        done,
+        span,
+        next_action: None,
+    };
+    check(
+        (exp.bootstrap_discount(gamma) - gamma.powi(3)).abs() < 1e-6,
+        "repeat: bootstrap discounted by gamma^span",
+    )?;
+
+    // No tail bonus: a 20-cell snake folded over rows 5 and 6 of a 10x10 board (past the 15%
+    // shaping length) turns up with its tail walled off. With the tail term the step's reward
+    // carries its -1.0; --no-tail-bonus drops exactly that term and (test-hooks builds) never
+    // runs the tail search
+    let folded = |no_tail_bonus: bool| {
+        let mut engine = SnakeEngine::new(10);
+        engine.reward.no_tail_bonus = no_tail_bonus;
+        engine.reset();
+        engine.snake = (0..10)
+            .rev()
+            .map(|x| Point { x, y: 5 })
+            .chain((0..10).map(|x| Point { x, y: 6 }))
+            .collect();
+        engine.food = Point { x: 0, y: 0 };
+        let (reward, done) = engine.step(0);
+        (engine, reward, done)
+    };
+    let (_, tail_reward, done) = folded(false);
+    let (_, reward, _) = folded(true);
+    check(
+        !done && (tail_reward - (reward - 1.0)).abs() < 1e-6,
+        "no tail bonus: the reward omits exactly the tail term",
+    )?;
+    #[cfg(feature = "test-hooks")]
+    check(
+        folded(false).0.tail_checks == 1 && folded(true).0.tail_checks == 0,
+        "no tail bonus: can_reach_tail isn't run",
+    )?;
+    Ok(())
+}
+
+/// Replay buffer sampling: terminal fractions across evictions, lazy TD-error priorities, the
+/// age bound, action histograms and episode-bounded sequences
+fn replay(check: &mut Check) -> Result<(), String> {
+    // Terminal fraction: with every 5th of 50 transitions terminal, at least
+    // ceil(0.25 * 30) = 8 of 30 draws are terminal
+    let mut rng = ChaCha8Rng::seed_from_u64(7);
+    let fill = |capacity: usize, count: usize, done: &dyn Fn(usize) -> bool| {
+        let mut buffer = ReplayBuffer::new(capacity);
+        for i in 0..count {
+            buffer.push(Experience {
+                state: Vec::new(),
+                action: 0,
+                reward: 0.0,
+                next_state: Vec::new(),
+                done: done(i),
+                span: 1,
+                next_action: None,
+            });
+        }
+        buffer
+    };
+    let terminal_hits = |buffer: &ReplayBuffer, indices: Vec<usize>| {
+        indices.iter().filter(|&&i| buffer.get(i).done).count()
+    };
+    let buffer = fill(50, 50, &|i| i % 5 == 0);
+    let indices = buffer.sample_indices(30, 0.25, None, &mut rng);
+    check(
+        terminal_hits(&buffer, indices) >= 8,
+        "terminal fraction: at least ceil(fraction * batch) draws are terminal",
+    )?;
+    // A terminal transition that eviction has just made the oldest is still eligible: it is the
+    // only terminal among 100, so the terminal share of the batch must draw it
+    let buffer = fill(100, 101, &|i| i == 1);
+    let indices = buffer.sample_indices(8, 0.5, None, &mut rng);
+    check(
+        terminal_hits(&buffer, indices) >= 1,
+        "terminal fraction: the oldest transition stays eligible after an eviction",
+    )?;
+
+    // Lazy priorities: every 10th of 200 identical terminal transitions has reward 50 and the
+    // rest 0, so after the recompute at the first train step the 20 high-error ones (10% of
+    // the buffer) draw most of the samples
+    let mut agent = DQNAgent::with_seed(2, ORTHOGONAL_ACTIONS, false);
+    agent.lazy_priority_recompute = 1;
+    for i in 0..200 {
+        agent.remember(Experience {
+            state: vec![0.5; INPUT_SIZE],
+            action: 0,
+            reward: if i % 10 == 0 { 50.0 } else { 0.0 },
+            next_state: vec![0.5; INPUT_SIZE],
+            done: true,
+            span: 1,
+            next_action: None,
+        });
+    }
+    for _ in 0..4 {
+        agent.step_and_train();
+    }
+    let indices = agent.replay_buffer().sample_indices(2000, 0.0, None, &mut rng);
+    let high = indices.iter().filter(|&&i| agent.replay_buffer().get(i).reward > 0.0).count();
+    check(high > 1000, "lazy priorities: high-error transitions are sampled more often")?;
+
+    // Replay age bound: with 50 transitions inserted and a bound of 10, uniform, terminal and
+    // prioritized draws only ever return the last 10
+    let mut buffer = ReplayBuffer::new(100);
+    for i in 0..50 {
+        buffer.push(Experience {
+            state: Vec::new(),
+            action: 0,
+            reward: 0.0,
+            next_state: Vec::new(),
+            done: i % 5 == 0,
+            span: 1,
+            next_action: None,
+        });
+    }
+    let mut rng = rand::thread_rng();
+    let recent = |indices: Vec<usize>| indices.iter().all(|&i| (40..50).contains(&i));
+    check(
+        recent(buffer.sample_indices(1000, 0.5, Some(10), &mut rng)),
+        "replay: uniform and terminal samples within the age bound",
+    )?;
+    buffer.set_priorities((0..50).map(|i| (50 - i) as f32).collect());
+    check(
+        recent(buffer.sample_indices(1000, 0.0, Some(10), &mut rng)),
+        "replay: prioritized samples within the age bound",
+    )?;
+
+    // Action histogram: over a 2:1:4:1 mix, only the newest `recent` transitions are counted
+    let mut buffer = ReplayBuffer::new(100);
+    for action in [0, 0, 1, 3, 2, 2, 2, 2] {
+        buffer.push(Experience {
+            state: Vec::new(),
+            action,
+            reward: 0.0,
+            next_state: Vec::new(),
+            done: false,
+            span: 1,
+            next_action: None,
+        });
+    }
+    check(buffer.action_histogram(4, 4) == [0, 0, 4, 0], "histogram: recent window only")?;
+    check(buffer.action_histogram(8, 4) == [2, 1, 4, 1], "histogram: counts per action")?;
+    let formatted = format_action_histogram(&buffer.action_histogram(4, 4));
+    check(formatted == "↑ 0.0% | → 0.0% | ↓ 100.0% | ← 0.0%", "histogram: formatted shares")?;
+
+    // Episode sequences: windows of 4 drawn from episodes of 3, 7, 2 and 5 transitions have
+    // the requested length and stay inside one episode; no episode fits a window of 8
+    let mut buffer = ReplayBuffer::new(100);
+    for (episode, steps) in [3, 7, 2, 5].into_iter().enumerate() {
+        for step in 0..steps {
+            buffer.push(Experience {
+                state: Vec::new(),
+                action: episode,
+                reward: 0.0,
+                next_state: Vec::new(),
+                done: step + 1 == steps,
+                span: 1,
+                next_action: None,
+            });
+        }
+        buffer.end_episode();
+    }
+    let sequences = buffer.sample_sequences(500, 4, &mut rng);
+    check(
+        sequences.len() == 500 && sequences.iter().all(|seq| seq.len() == 4),
+        "sequences: requested count and length",
+    )?;
+    check(
+        sequences.iter().all(|seq| {
+            let episode = buffer.get(seq[0]).action;
+            seq.windows(2).all(|w| w[1] == w[0] + 1)
+                && seq.iter().all(|&i| buffer.get(i).action == episode)
+        }),
+        "sequences: contiguous and within one episode",
+    )?;
+    check(
+        buffer.sample_sequences(10, 8, &mut rng).is_empty(),
+        "sequences: none longer than episodes",
+    )?;
+    Ok(())
+}
+
+/// Feature extraction and model input: congestion and tail features, minimal snakes, in-place
+/// extraction, feature versions, TF.js imports and the input-size guard
+fn features(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let mut engine = SnakeEngine::new(grid_size);
+    let (mid, area) = (grid_size / 2, (grid_size * grid_size) as usize);
+
+    // Congestion: with no obstacles the free ratio is 1 - length / area, and a snake filling
+    // half the board is in the late phase
+    engine.reset();
+    engine.obstacles.clear();
+    let features = extract_features(&engine);
+    let feature = |name: &str| features[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+    check(
+        (feature("free_ratio") - (1.0 - 3.0 / area as f32)).abs() < 1e-6,
+        "congestion: free ratio is 1 - snake_len / area",
+    )?;
+    check(feature("phase_early") == 1.0, "congestion: a new snake is early-phase")?;
+    engine.snake = (0..area as i32 / 2)
+        .map(|i| Point { x: i % grid_size, y: i / grid_size })
+        .collect();
+    let features = extract_features(&engine);
+    let feature = |name: &str| features[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+    check(
+        feature("phase_late") == 1.0 && feature("phase_early") + feature("phase_mid") == 0.0,
+        "congestion: a half-board snake is late-phase",
+    )?;
+
+    // Tail adjacency on a coiled snake: heading Right, the body loops down and back up so the
+    // tail sits straight ahead of the head, five segments along the body
+    engine.reset();
+    engine.direction = Direction::Right;
+    engine.snake = [(0, 0), (-1, 0), (-1, 1), (0, 1), (1, 1), (1, 0)]
+        .iter()
+        .map(|&(dx, dy)| Point { x: mid + dx, y: mid + dy })
+        .collect();
+    let features = extract_features(&engine);
+    let feature = |name: &str| features[FEATURE_NAMES.iter().position(|&n| n == name).unwrap()];
+    check(
+        feature("tail_straight") == 1.0 && feature("tail_right") + feature("tail_left") == 0.0,
+        "tail: coiled tail is adjacent straight ahead only",
+    )?;
+    check(feature("tail_dx") == 1.0 && feature("tail_dy") == 0.0, "tail: direction to tail")?;
+    check(
+        (feature("tail_path") - 5.0 / area as f32).abs() < 1e-6,
+        "tail: path length counts the segments between head and tail",
+    )?;
+
+    // Minimum-length states: a lone head extracts normally, an empty snake yields zeros
+    engine.reset();
+    engine.snake.truncate(1);
+    let features = extract_features(&engine);
+    check(features.len() == INPUT_SIZE, "features: one-cell snake")?;
+    check(features.iter().all(|f| f.is_finite()), "features: finite values")?;
+    engine.snake.clear();
+    let features = extract_features(&engine);
+    check(
+        features.len() == INPUT_SIZE && features.iter().all(|&f| f == 0.0),
+        "features: empty snake gives zeros",
+    )?;
+
+    // In-place extraction: one buffer and scratch reused over a seeded random episode (and on
+    // into a board of another size) match a fresh allocating extraction at every step
+    let mut buf = Vec::new();
+    let mut scratch = Scratch::default();
+    let mut in_place_matches = true;
+    let mut rng = ChaCha8Rng::seed_from_u64(11);
+    for size in [grid_size, grid_size / 2 + 3] {
+        let mut engine = SnakeEngine::new(size);
+        engine.seed(5);
+        engine.reset();
+        loop {
+            extract_features_into(&engine, &mut buf, &mut scratch);
+            in_place_matches &= buf == extract_features(&engine);
+            if engine.step(rng.gen_range(0..4)).1 {
+                break;
+            }
+        }
+    }
+    check(in_place_matches, "features: in-place extraction matches the allocating one")?;
+
+    // Feature versions: a model exported with each feature set loads back with the same one and
+    // is fed that extractor's features at eval; unknown or inconsistent tags are rejected
+    let path = std::env::temp_dir().join(format!("snake_self_test_{}.json", std::process::id()));
+    let path = path.to_string_lossy();
+    for set in &FEATURE_SETS {
+        let mut agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+        agent.network = Network::new(set.size, ORTHOGONAL_ACTIONS, false, &mut rand::thread_rng());
+        agent.features = set;
+        export_model(&agent, &engine.mode(), &path);
+        let loaded = load_model(&path)?;
+        check(loaded.features.version == set.version, "versions: tag survives export")?;
+        let mut sizes_match = true;
+        run_episode(&mut engine, loaded.features, &mut Scratch::default(), &mut |_, features| {
+            sizes_match &= features.len() == set.size;
+            loaded.act_greedy(features)
+        });
+        check(sizes_match, "versions: eval uses the tagged extractor")?;
+    }
+
+    // TF.js import: an export whose weightData is re-encoded as base64 and whose tensor names
+    // carry another tool's scope prefix imports to the same weights and forward pass
+    let agent = DQNAgent::with_seed(5, ORTHOGONAL_ACTIONS, false);
+    export_model(&agent, &engine.mode(), &path);
+    let text = std::fs::read_to_string(&*path).map_err(|e| e.to_string())?;
+    let mut json: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    let bytes: Vec<u8> = json["weightData"]
+        .as_array()
+        .ok_or("tfjs import: export has no weightData array")?
+        .iter()
+        .map(|b| b.as_u64().unwrap_or(0) as u8)
+        .collect();
+    json["weightData"] = base64(&bytes).into();
+    for spec in json["weightSpecs"].as_array_mut().ok_or("tfjs import: no weightSpecs")? {
+        spec["name"] = format!("sequential/{}", spec["name"].as_str().unwrap_or("")).into();
+    }
+    std::fs::write(&*path, json.to_string()).map_err(|e| e.to_string())?;
+    let imported = import_model(&path, 1)?;
+    check(
+        imported.num_layers() == agent.network.num_layers()
+            && (0..imported.num_layers())
+                .all(|i| imported.layer_info(i) == agent.network.layer_info(i)),
+        "tfjs import: base64 weights round trip exactly",
+    )?;
+    let input = extract_features(&engine);
+    check(
+        imported.forward(&input) == agent.network.forward(&input),
+        "tfjs import: same forward pass",
+    )?;
+    let _ = std::fs::remove_file(&*path);
+
+    // Input guard: a feature vector of the wrong length is rejected with both lengths
+    let agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+    let short = vec![0.0; INPUT_SIZE - 1];
+    check(agent.check_input(&extract_features(&engine)).is_ok(), "input guard: match accepted")?;
+    check(
+        agent.check_input(&short).is_err_and(|e| {
+            e.contains(&(INPUT_SIZE - 1).to_string()) && e.contains(&INPUT_SIZE.to_string())
+        }),
+        "input guard: mismatch names both lengths",
+    )?;
+    Ok(())
+}
+
+/// DQN learning rules: the pure-random phase, n-step returns, the heuristic warmup mix,
+/// overestimation stats, the target rules, target clamping, target-update warmups, drift stats,
+/// feature masks, policy-map tie-breaks, the loss masked to the taken action and per-episode
+/// updates
+fn dqn(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let mid = grid_size / 2;
+    // Pure-random phase: 200 steps seed the buffer without training or epsilon decay; the
+    // first steps after it train, and the next episode end decays epsilon
+    let mut agent = DQNAgent::with_seed(0, ORTHOGONAL_ACTIONS, false);
+    agent.pure_random_steps = 200;
+    let play = |agent: &mut DQNAgent, steps: usize| {
+        for _ in 0..steps {
+            let state = vec![0.0; INPUT_SIZE];
+            let action = agent.act(&state);
+            agent.remember(Experience {
+                state: state.clone(),
+                action,
+                reward: 0.0,
+                next_state: state,
+                done: false,
+                span: 1,
+                next_action: None,
+            });
+            agent.step_and_train();
+        }
+        agent.end_episode();
+    };
+    play(&mut agent, 200);
+    check(
+        agent.checkpoint(0).train_steps == 0 && agent.epsilon == 1.0,
+        "pure random: no training or epsilon decay",
+    )?;
+    play(&mut agent, 8);
+    check(
+        agent.checkpoint(0).train_steps == 2 && agent.epsilon < 1.0,
+        "pure random: training and decay start afterwards",
+    )?;
+
+    // N-step returns: with n = 3 each entry sums three discounted rewards and bootstraps from
+    // the third next state; the window shrinks at the episode end, where a terminal step
+    // stops bootstrapping and a truncated one keeps it
+    for terminal in [true, false] {
+        let mut agent = DQNAgent::with_seed(0, ORTHOGONAL_ACTIONS, false);
+        agent.gamma = 0.5;
+        agent.n_step = 3;
+        for t in 0..5 {
+            agent.remember(Experience {
+                state: vec![t as f32],
+                action: 0,
+                reward: t as f32 + 1.0,
+                next_state: vec![t as f32 + 1.0],
+                done: terminal && t == 4,
+                span: 1,
+                next_action: None,
+            });
+        }
+        let stored_before_end = agent.buffer_len();
+        agent.end_episode();
+        let entries: Vec<(f32, f32, bool, u32)> = (0..agent.buffer_len())
+            .map(|i| agent.replay_buffer().get(i))
+            .map(|e| (e.reward, e.next_state[0], e.done, e.span))
+            .collect();
+        let expected = [
+            (2.75, 3.0, false, 3),
+            (4.5, 4.0, false, 3),
+            (6.25, 5.0, terminal, 3),
+            (6.5, 5.0, terminal, 2),
+            (5.0, 5.0, terminal, 1),
+        ];
+        check(
+            stored_before_end == if terminal { 5 } else { 3 } && entries == expected,
+            "n-step: windowed returns, shortened and correctly terminated at the episode end",
+        )?;
+    }
+
+    // Warmup mix: with a 0.3 heuristic ratio over 2000 pure-random steps, about 30% of the
+    // actions come from the heuristic, and none once the phase is over
+    let mut agent = DQNAgent::with_seed(0, ORTHOGONAL_ACTIONS, false);
+    agent.pure_random_steps = 2000;
+    agent.warmup_heuristic_ratio = 0.3;
+    let state = vec![0.0; INPUT_SIZE];
+    let mut guided = 0;
+    for _ in 0..2000 {
+        agent.act_guided(&state, || {
+            guided += 1;
+            0
+        });
+        agent.step_and_train();
+    }
+    check(
+        (guided as f32 / 2000.0 - 0.3).abs() < 0.03,
+        "warmup mix: heuristic share matches ratio",
+    )?;
+    let mut late = 0;
+    agent.act_guided(&state, || {
+        late += 1;
+        0
+    });
+    check(late == 0, "warmup mix: heuristic unused after the phase")?;
+
+    // Overestimation: rewards 1, 0, -10 with discount 0.5 return -1.5, -5, -10, so constant
+    // predictions of 0 overestimate by 5.5 on average; a truncated episode is not scored
+    let mut stats = OverestimationStats::default();
+    for reward in [1.0, 0.0, -10.0] {
+        stats.record(0.0, reward, 0.5);
+    }
+    stats.end_episode(true);
+    stats.record(100.0, 0.0, 0.5);
+    stats.end_episode(false);
+    check(
+        stats.take_mean().is_some_and(|(bias, n)| (bias - 5.5).abs() < 1e-6 && n == 3),
+        "overestimation: signed bias against known returns",
+    )?;
+    check(stats.take_mean().is_none(), "overestimation: reset after reporting")?;
+
+    // Vanilla vs Double DQN targets: the main network prefers action 0, the target network
+    // action 1; Double DQN evaluates the main net's pick, vanilla takes the target net's max,
+    // and clipped Double Q the smaller of the twin targets' estimates for the main net's pick
+    let mut agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+    let exp = Experience {
+        state: Vec::new(),
+        action: 0,
+        reward: 1.0,
+        next_state: Vec::new(),
+        done: false,
+        span: 1,
+        next_action: None,
+    };
+    let main_q = [5.0, 0.0, 0.0, 0.0];
+    let target_q = [2.0, 3.0, 0.0, 0.0];
+    let twin_q = [1.5, 4.0, 0.0, 0.0];
+    let double = agent.td_target(&exp, &main_q, &target_q, None);
+    agent.set_target_rule(TargetRule::Vanilla);
+    let vanilla = agent.td_target(&exp, &main_q, &target_q, None);
+    agent.set_target_rule(TargetRule::ClippedDouble);
+    let clipped = agent.td_target(&exp, &main_q, &target_q, Some(&twin_q));
+    check(double == 1.0 + agent.gamma * 2.0, "targets: Double DQN evaluates the main argmax")?;
+    check(vanilla == 1.0 + agent.gamma * 3.0, "targets: vanilla takes the target network's max")?;
+    check(
+        clipped == 1.0 + agent.gamma * 1.5 && agent.checkpoint(0).twin.is_some(),
+        "targets: clipped Double Q takes the twin minimum",
+    )?;
+
+    // SARSA: each stored transition learns the action remembered after it (none after the
+    // terminal one), and its target evaluates that action, or the Double DQN pick without one
+    let mut agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+    agent.n_step = 1;
+    agent.set_target_rule(TargetRule::Sarsa);
+    for (action, done) in [(0, false), (1, false), (2, true)] {
+        agent.remember(Experience {
+            state: vec![0.0; INPUT_SIZE],
+            action,
+            reward: 0.0,
+            next_state: vec![0.0; INPUT_SIZE],
+            done,
+            span: 1,
+            next_action: None,
+        });
+    }
+    let buffer = agent.replay_buffer();
+    let next_actions: Vec<Option<usize>> = (0..3).map(|i| buffer.get(i).next_action).collect();
+    let taken = Experience { next_action: Some(2), ..exp.clone() };
+    let sarsa = agent.td_target(&taken, &main_q, &target_q, None);
+    let fallback = agent.td_target(&exp, &main_q, &target_q, None);
+    check(next_actions == [Some(1), Some(2), None], "sarsa: transitions record the next action")?;
+    check(sarsa == 1.0 && fallback == double, "sarsa: target evaluates the next action taken")?;
+
+    // Expected SARSA: at epsilon 0.5 the bootstrap value is half the Double DQN pick's value
+    // (2) and half the mean over actions (1.25); the checkpoint records the rule
+    agent.set_target_rule(TargetRule::ExpectedSarsa);
+    agent.epsilon = 0.5;
+    check(
+        agent.td_target(&exp, &main_q, &target_q, None) == 1.0 + agent.gamma * 1.625
+            && agent.checkpoint(0).target_rule == Some(TargetRule::ExpectedSarsa),
+        "expected sarsa: epsilon-greedy expectation over next Q-values",
+    )?;
+
+    // Target clamp: that same target (about 2.61) is cut to the upper bound of [-1, 2], a
+    // terminal reward of -5 to the lower one, and a target inside [-10, 10] is unchanged
+    let unclamped = agent.td_target(&exp, &main_q, &target_q, None);
+    let lost = Experience { reward: -5.0, done: true, ..exp.clone() };
+    agent.target_clamp = Some((-1.0, 2.0));
+    let clamped = (
+        agent.td_target(&exp, &main_q, &target_q, None),
+        agent.td_target(&lost, &main_q, &target_q, None),
+    );
+    agent.target_clamp = Some((-10.0, 10.0));
+    check(clamped == (2.0, -1.0), "target clamp: out-of-range targets are clamped")?;
+    check(
+        agent.td_target(&exp, &main_q, &target_q, None) == unclamped,
+        "target clamp: in-range targets are unchanged",
+    )?;
+
+    // Target update warmup: hard copies (tau 1) through step K, the soft tau after, and soft
+    // updates throughout without a warmup
+    let mut agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+    let soft = agent.target_tau(1);
+    agent.target_update_warmup = 3;
+    let taus = [1, 2, 3, 4, 100].map(|step| agent.target_tau(step));
+    check(soft < 1.0, "target warmup: soft updates by default")?;
+    check(taus == [1.0, 1.0, 1.0, soft, soft], "target warmup: hard copies, then soft")?;
+
+    // Target drift: zero while the target is an exact copy; with a deliberately different
+    // target it is the mean |Q - Q'| over every action of the 2 newest of 3 buffer states
+    let mut agent = DQNAgent::with_seed(4, ORTHOGONAL_ACTIONS, false);
+    let states: Vec<Vec<f32>> =
+        (0..3).map(|i| (0..INPUT_SIZE).map(|j| ((i * 5 + j) as f32).cos()).collect()).collect();
+    for state in &states {
+        agent.remember(Experience {
+            state: state.clone(),
+            action: 0,
+            reward: 0.0,
+            next_state: state.clone(),
+            done: true,
+            span: 1,
+            next_action: None,
+        });
+    }
+    let copy_drift = agent.target_drift(2);
+    agent.target_network =
+        Network::new(INPUT_SIZE, ORTHOGONAL_ACTIONS, false, &mut ChaCha8Rng::seed_from_u64(9));
+    let expected: f32 = states[1..]
+        .iter()
+        .flat_map(|s| {
+            let (q, target_q) = (agent.network.forward(s), agent.target_network.forward(s));
+            q.iter().zip(&target_q).map(|(a, b)| (a - b).abs()).collect::<Vec<_>>()
+        })
+        .sum::<f32>()
+        / (2 * ORTHOGONAL_ACTIONS) as f32;
+    check(copy_drift == 0.0, "target drift: zero for an identical target")?;
+    check(
+        expected > 0.0 && (agent.target_drift(2) - expected).abs() < 1e-5,
+        "target drift: mean |Q - Q'| over the recent states",
+    )?;
+
+    // Feature mask: masked indices reach the network as zero whatever was extracted, so their
+    // values can't change the Q-values
+    let mut agent = DQNAgent::new(ORTHOGONAL_ACTIONS, false);
+    agent.feature_mask = vec![22, 23, 24];
+    let mut features = extract_features(&SnakeEngine::new(grid_size));
+    features[22..25].fill(7.0);
+    let input = agent.network_input(&features);
+    let q = agent.q_values(&features);
+    features[22..25].fill(-3.0);
+    check(input[22..25] == [0.0; 3], "feature mask: masked inputs are zero")?;
+    check(input[..22] == features[..22], "feature mask: other inputs untouched")?;
+    check(agent.q_values(&features) == q, "feature mask: masked values don't affect Q")?;
+
+    // Policy-map tie-break: renders of the same model are byte-identical, and a network whose
+    // Q-values all tie maps every cell to the lowest action
+    let food = Point { x: mid, y: mid };
+    check(
+        policy_map(&agent, grid_size, food) == policy_map(&agent, grid_size, food),
+        "policy map: repeated renders identical",
+    )?;
+    let layer_sizes: Vec<(usize, usize)> = (0..agent.network.num_layers())
+        .map(|i| {
+            let (_, _, in_size, out_size) = agent.network.layer_info(i);
+            (in_size, out_size)
+        })
+        .collect();
+    agent.network = Network::from_layers(
+        layer_sizes.iter().map(|&(i, o)| (vec![0.0; i * o], vec![0.0; o])).collect(),
+    )?;
+    check(
+        policy_map(&agent, grid_size, food).chars().all(|c| " F\n".contains(c) || c == ARROWS[0]),
+        "policy map: ties go to the lowest action",
+    )?;
+
+    // Masked loss: a train_batch step where every sample took action 2 moves only that
+    // action's output-layer column and bias; the other actions' are bit for bit unchanged
+    let mut network = DQNAgent::with_seed(4, ORTHOGONAL_ACTIONS, false).network;
+    let last = network.num_layers() - 1;
+    let (weights, biases, inputs_n, outputs) = network.layer_info(last);
+    let (weights, biases) = (weights.to_vec(), biases.to_vec());
+    let inputs: Vec<Vec<f32>> = (0..8)
+        .map(|i| (0..network.input_size()).map(|j| ((i * 5 + j) as f32).cos()).collect())
+        .collect();
+    network.train_batch(&inputs, &[5.0; 8], &[2; 8], 0.01);
+    let (after, after_biases, _, _) = network.layer_info(last);
+    let moved = |j: usize| {
+        after_biases[j] != biases[j]
+            || (0..inputs_n).any(|i| after[i * outputs + j] != weights[i * outputs + j])
+    };
+    check(
+        (0..outputs).all(|j| moved(j) == (j == 2)),
+        "masked loss: only the taken action's output column changes",
+    )?;
+
+    // Update per episode: over three 12-step episodes the online weights hold still through
+    // every step and change exactly once, at each episode's end
+    let mut agent = DQNAgent::with_seed(5, ORTHOGONAL_ACTIONS, false);
+    agent.update_per_episode = true;
+    let (mut step_changes, mut end_changes) = (0, 0);
+    let mut weights = parameters(&agent.network);
+    for episode in 0..3 {
+        for step in 0..12 {
+            let state: Vec<f32> =
+                (0..INPUT_SIZE).map(|j| ((episode * 12 + step + j) as f32).sin()).collect();
+            let action = agent.act(&state);
+            agent.remember(Experience {
+                state: state.clone(),
+                action,
+                reward: step as f32 - 5.0,
+                next_state: state,
+                done: step == 11,
+                span: 1,
+                next_action: None,
+            });
+            agent.step_and_train();
+            step_changes += usize::from(parameters(&agent.network) != weights);
+        }
+        agent.end_episode();
+        let after = parameters(&agent.network);
+        end_changes += usize::from(after != weights);
+        weights = after;
+    }
+    check(
+        step_changes == 0 && end_changes == 3 && agent.checkpoint(0).train_steps == 3,
+        "update per episode: weights change once per episode, at its end",
+    )?;
+    Ok(())
+}
+
+/// Heuristic pretraining and agreement, tree distillation, evaluation (greedy, seeded,
+/// parallel and tempered), episode-seed replays (through `--eval` too), eval environment
+/// mismatches and grid sizes, trajectory sampling, curricula, seed sweeps and milestones
+fn evaluation(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    // Heuristic pretraining: after imitation on one set of episodes, the greedy action agrees
+    // with the heuristic on most states of held-out episodes
+    let mut agent = DQNAgent::with_seed(1, ORTHOGONAL_ACTIONS, false);
+    let (states, labels) = heuristic_dataset(grid_size, false, agent.features, 2000, 1);
+    agent.pretrain(&states, &labels, 300);
+    let agreement = heuristic_agreement(&agent, grid_size, 500, 2);
+    check(
+        agreement >= 0.75,
+        "pretraining: greedy policy imitates the heuristic on held-out states",
+    )?;
+
+    // Heuristic agreement: near chance for an untrained network, close to 100% for one trained
+    // to mimic the heuristic
+    let untrained = DQNAgent::with_seed(1, ORTHOGONAL_ACTIONS, false);
+    check(
+        heuristic_agreement(&untrained, grid_size, 500, 2) < 0.5 && agreement >= 0.95,
+        "heuristic agreement: untrained near chance, heuristic mimic near 100%",
+    )?;
+
+    // Tree distillation of the heuristic mimic. Its greedy action follows a few food-direction
+    // and danger features, so a depth-4 tree (16 leaves) captures nearly all of it: 98.5-99.4%
+    // over sample seeds 0-3, so the 95% bound leaves margin. A single split (two leaves for
+    // four actions) stays near 50%, so a high score isn't trivial.
+    let deep = distill_tree(&agent, grid_size, 2000, 4, Some(1));
+    let stump = distill_tree(&agent, grid_size, 2000, 1, Some(1));
+    check(
+        deep.samples == 2000 && deep.agreement >= 0.95 && stump.agreement < 0.6,
+        "distilled tree: a depth-4 tree matches the heuristic mimic on at least 95% of states",
+    )?;
+
+    // Eval epsilon 0: two evals of the heuristic mimic under the same seed score identically
+    // episode by episode (and it does eat, so the scores aren't trivially all zero)
+    let options = EvalOptions {
+        episodes: 5,
+        epsilon: 0.0,
+        temperature: None,
+        seed: Some(21),
+        threads: 1,
+    };
+    let bare = || SnakeEngine::new(grid_size);
+    let first = evaluate(&agent, &bare, &options).scores;
+    check(
+        first.iter().any(|&s| s > 0) && evaluate(&agent, &bare, &options).scores == first,
+        "eval epsilon: greedy eval is deterministic under a fixed seed",
+    )?;
+
+    // Episode seeds: episodes played after reseeding with a logged seed (obstacles and sticky
+    // actions included) score the same when replayed on a fresh engine with that seed
+    let play_seeded = |seed: u64| {
+        let mut engine = SnakeEngine::new(grid_size);
+        engine.num_obstacles = 5;
+        engine.sticky_actions = 0.1;
+        engine.seed(seed);
+        let mut scratch = Scratch::default();
+        let features = FEATURE_SETS.last().unwrap();
+        run_episode(&mut engine, features, &mut scratch, &mut |e, _| greedy_food_action(e))
+    };
+    let logged: Vec<(u64, i32)> = (1..=5)
+        .map(|episode| crate::episode_seed(Some(7), episode))
+        .map(|seed| (seed, play_seeded(seed)))
+        .collect();
+    check(
+        logged.iter().all(|&(seed, score)| play_seeded(seed) == score),
+        "episode seeds: replaying a logged seed reproduces the score",
+    )?;
+    check(
+        crate::episode_seed(Some(7), 3) == logged[2].0 && logged[0].0 != logged[1].0,
+        "episode seeds: stable per run seed, distinct per episode",
+    )?;
+
+    // Eval replay: `--eval --eval-seed S` with one greedy episode plays on the configured
+    // environment (obstacles, sticky actions, truncation), so it scores what a greedy training
+    // episode logged with seed S scored, where the bare board of the old eval path doesn't
+    let seed = crate::episode_seed(Some(7), 3);
+    let args: Vec<String> = [
+        "rust_entrenador",
+        "--obstacles",
+        "4",
+        "--sticky-actions",
+        "0.2",
+        "--truncate-at",
+        "300",
+        "--eval",
+        "model.json",
+        "--eval-episodes",
+        "1",
+        "--eval-seed",
+        &seed.to_string(),
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    let config = crate::parse_args(&args);
+    let mut engine = crate::trainer_engine(&config, Some(7), agent.gamma);
+    engine.seed(seed);
+    let mut greedy = |_: &SnakeEngine, features: &[f32]| agent.act_greedy(features);
+    let trained = run_episode(&mut engine, agent.features, &mut Scratch::default(), &mut greedy);
+    let make_engine = crate::eval_engine(&config, &agent, engine.grid_size);
+    check(
+        evaluate(&agent, &make_engine, &config.eval_options).scores == [trained]
+            && evaluate(&agent, &bare, &config.eval_options).scores != [trained],
+        "eval replay: --eval-seed replays a logged training episode's score",
+    )?;
+
+    // Eval environment: a model trained without obstacles, evaluated with --obstacles 4, is
+    // reported as a mismatch (and nothing else is); one trained with them is not
+    let trained = |obstacles| EnvMode {
+        grid_size: engine.grid_size,
+        obstacles,
+        diagonals: false,
+    };
+    let current = crate::eval_env_mode(&config, &agent, &Ok(Some(trained(0))));
+    check(
+        trained(0).differences(&current) == ["0 obstacles (now 4)"]
+            && trained(4).differences(&current).is_empty(),
+        "eval environment: an obstacle mismatch with the trained model is reported",
+    )?;
+
+    // Eval grid sizes: --eval plays the model and the baseline on every listed board, each
+    // scoring what a direct eval on that board does
+    let args: Vec<String> = [
+        "rust_entrenador",
+        "--eval",
+        "model.json",
+        "--eval-grid-sizes",
+        "8,12,16",
+        "--eval-episodes",
+        "3",
+        "--eval-seed",
+        "5",
+        "--baseline-eval",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    let config = crate::parse_args(&args);
+    let results = crate::eval_grid_sizes(&config, &agent);
+    let on_board = |grid_size| {
+        let make_engine = crate::eval_engine(&config, &agent, grid_size);
+        evaluate(&agent, &make_engine, &config.eval_options).scores
+    };
+    check(
+        results.iter().map(|r| r.0).collect::<Vec<_>>() == [8, 12, 16]
+            && results.iter().all(|(grid_size, stats, baseline)| {
+                stats.scores == on_board(*grid_size)
+                    && baseline.as_ref().is_some_and(|b| b.scores.len() == 3)
+            }),
+        "eval grid sizes: every listed board is evaluated, with its baseline",
+    )?;
+
+    // Trajectory sampling: with every = 4, exactly episodes 4, 8 and 12 of the first 12 are
+    // logged, every = 1 logs all of them and every = 0 none
+    let sampled = |every: u64| -> Vec<u64> {
+        (1..=12).filter(|&episode| crate::logs_trajectory(every, episode)).collect()
+    };
+    check(
+        sampled(4) == [4, 8, 12] && sampled(1).len() == 12 && sampled(0).is_empty(),
+        "trajectory log: exactly every Nth episode is sampled",
+    )?;
+
+    // Parallel eval: per-seed scores (with exploration, so the per-seed RNG matters too) are
+    // the same for one thread and several, in seed order
+    let agent = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let seeds: Vec<u64> = (0..7).map(|i| derive_seed(11, i)).collect();
+    let mut options = EvalOptions {
+        episodes: seeds.len() as u64,
+        epsilon: 0.1,
+        temperature: None,
+        seed: None,
+        threads: 1,
+    };
+    let serial = evaluate_seeds(&agent, &bare, &options, &seeds);
+    options.threads = 3;
+    let parallel = evaluate_seeds(&agent, &bare, &options, &seeds);
+    check(serial == parallel, "parallel eval: identical per-seed scores")?;
+
+    // Seeded eval: `evaluate` with exploration scores the same episodes on 1 thread and on 4
+    options.seed = Some(11);
+    options.threads = 4;
+    let parallel = evaluate(&agent, &bare, &options).scores;
+    options.threads = 1;
+    check(
+        evaluate(&agent, &bare, &options).scores == parallel,
+        "parallel eval: a seeded evaluate scores the same on 1 and 4 threads",
+    )?;
+
+    // Play temperature: over 4000 draws from Q = [1, 0.5, 0, -1], T = 1e-4 always picks the
+    // greedy action and T = 1e4 picks every action about equally often (within 15% of 1000)
+    let q = [1.0, 0.5, 0.0, -1.0];
+    let mut rng = ChaCha8Rng::seed_from_u64(17);
+    let mut counts = |temperature: f32| {
+        let mut counts = [0; 4];
+        for _ in 0..4000 {
+            counts[softmax_sample(&q, temperature, &mut rng)] += 1;
+        }
+        counts
+    };
+    check(counts(1e-4) == [4000, 0, 0, 0], "temperature: near zero is greedy")?;
+    check(
+        counts(1e4).iter().all(|&c| (850..=1150).contains(&c)),
+        "temperature: very high is near uniform",
+    )?;
+
+    // Curriculum epsilon reset: epsilon is untouched until the stage advances, then raised to
+    // the configured value
+    let mut curriculum = Curriculum::new(vec![8, 10], CurriculumMetric::Score, 10.0);
+    curriculum.epsilon_reset = Some(0.5);
+    let mut epsilon = 0.1;
+    let mut within_stage = Vec::new();
+    while !curriculum.record(10, 3, &mut epsilon) {
+        within_stage.push(epsilon);
+    }
+    check(
+        within_stage.len() == 99 && within_stage.iter().all(|&e| e == 0.1),
+        "curriculum epsilon: unchanged within a stage",
+    )?;
+    check(epsilon == 0.5, "curriculum epsilon: raised on stage advance")?;
+
+    // Curriculum fill: on 8x8 with a 0.25 threshold, 100 deaths at length 15 (0.234 of the
+    // board) hold the stage however high the score; deaths at length 20 lift the rolling
+    // average to exactly 16 cells on the 20th, which advances to the 10x10 board
+    let mut curriculum = Curriculum::new(vec![8, 10], CurriculumMetric::Fill, 0.25);
+    let held = (0..100).all(|_| !curriculum.record(1000, 15, &mut epsilon));
+    let advanced_at = (1..=100).find(|_| curriculum.record(0, 20, &mut epsilon));
+    check(held, "curriculum fill: the score doesn't count")?;
+    check(
+        advanced_at == Some(20) && curriculum.grid_size() == 10,
+        "curriculum fill: crossing the fill threshold advances the stage",
+    )?;
+
+    // Seed sweep: one run per seed in the given order, aggregated as the mean and population
+    // std of the per-seed finals (2, 4, 4, 4, 5, 5, 7, 9 average 5 with std 2)
+    let seeds = [8, 3, 5, 1, 7, 2, 6, 4];
+    let mut trained = Vec::new();
+    let (finals, mean, std) = crate::sweep_seeds(&seeds, |seed| {
+        trained.push(seed);
+        [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0][trained.len() - 1]
+    });
+    check(trained == seeds && finals.len() == 8, "seed sweep: one run per seed")?;
+    check(mean == 5.0 && std == 2.0, "seed sweep: mean and std across seeds")?;
+
+    // Milestones: a synthetic rising average crosses 50 at episode 3 and jumps past both 100
+    // and 120 at episode 5; 150 is never reached, and a later dip doesn't move a milestone
+    let mut milestones = Milestones::new(vec![50.0, 100.0, 120.0, 150.0]);
+    for (episode, avg) in [(1, 10.0), (2, 49.9), (3, 50.0), (4, 80.0), (5, 130.0), (6, 40.0)] {
+        milestones.record(avg, episode, episode * 100);
+    }
+    let reached: Vec<Option<(u64, u64)>> = milestones.reached().map(|(_, r)| r).collect();
+    check(
+        reached == [Some((3, 300)), Some((5, 500)), Some((5, 500)), None],
+        "milestones: first episode and steps at each threshold",
+    )?;
+    Ok(())
+}
+
+/// Network numerics: known weights (`test-hooks` builds), f32 against f64 accumulation, f64
+/// return sums and the running observation normalizer
+fn numerics(check: &mut Check) -> Result<(), String> {
+    // Known weights (test-hooks builds only): constant layers give an exact forward pass, and
+    // a wrongly shaped layer is rejected
+    #[cfg(feature = "test-hooks")]
+    {
+        let mut network = Network::new(2, 2, false, &mut rand::thread_rng());
+        let shapes: Vec<(usize, usize)> = (0..network.num_layers())
+            .map(|i| {
+                let (_, _, in_size, out_size) = network.layer_info(i);
+                (in_size, out_size)
+            })
+            .collect();
+        let [(in0, h1), (_, h2), (_, out)] = shapes[..] else {
+            return Err("known weights: expected 3 layers".to_string());
+        };
+        // h1 = relu(1) = 1; h2 = h1 · 1/h1 = 1; q = sum(h2 · 0.5) + bias
+        network.set_layer_weights(0, vec![0.0; in0 * h1], vec![1.0; h1])?;
+        network.set_layer_weights(1, vec![1.0 / h1 as f32; h1 * h2], vec![0.0; h2])?;
+        network.set_layer_weights(2, vec![0.5; h2 * out], vec![1.0, -1.0])?;
+        let expected = 0.5 * h2 as f32;
+        check(
+            network.forward(&[3.0, -7.0]) == [expected + 1.0, expected - 1.0],
+            "known weights: exact forward pass",
+        )?;
+        check(network.layer_weights(2).1 == [1.0, -1.0], "known weights: getter round trip")?;
+        check(
+            network.set_layer_weights(2, vec![0.0; 3], vec![0.0; out]).is_err()
+                && network.set_layer_weights(3, Vec::new(), Vec::new()).is_err(),
+            "known weights: shape and index are validated",
+        )?;
+    }
+
+    // Gradient accumulation: a 4096-sample weight gradient (ReLU-like activations in [0, 2),
+    // deltas in [-1, 1), so mean |a * b| = 0.5) summed in f32 stays within 2e-6 of the same sum
+    // in f64. That is the random-walk estimate sqrt(4096) * 2^-24 * 0.5 of the rounding error;
+    // the worst case is 64 times larger and this seed measures 6.9e-8.
+    let mut rng = ChaCha8Rng::seed_from_u64(13);
+    let (bs, m, n) = (4096, 64, 4);
+    let a: Vec<f32> = (0..bs * m).map(|_| rng.gen_range(0.0..2.0)).collect();
+    let b: Vec<f32> = (0..bs * n).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    let single: Vec<f32> = matmul_at_b(&a, &b, m, n, bs, bs as f32);
+    let double: Vec<f64> = matmul_at_b(&a, &b, m, n, bs, bs as f32);
+    check(
+        single.iter().zip(&double).all(|(s, d)| (*s as f64 - d).abs() < 2e-6),
+        "accumulation: f32 and f64 gradient sums agree within the documented bound",
+    )?;
+
+    // Return accumulation: a million steps of a -0.01 shaping reward, summed step by step the
+    // way training sums an episode's return. Against the exact total, the f64 sum stays within
+    // 1e-6 while the f32 sum drifts by more than 1 (it ends about 135 short).
+    let exact = 1e6 * (-0.01f32) as f64;
+    let (mut single, mut double) = (0.0f32, 0.0f64);
+    for _ in 0..1_000_000 {
+        single += -0.01f32;
+        double += (-0.01f32) as f64;
+    }
+    check(
+        (double - exact).abs() < 1e-6 && (single as f64 - exact).abs() > 1.0,
+        "return sum: f64 accumulation doesn't drift like f32",
+    )?;
+
+    // Observation normalizer: after a seeded stream of features with very different scales
+    // (a flag, a unit-range value and an offset count), the running Welford mean and std match
+    // the two-pass population stats of the same samples
+    let mut rng = ChaCha8Rng::seed_from_u64(31);
+    let samples: Vec<[f32; 3]> = (0..5000)
+        .map(|_| {
+            let flag = if rng.gen_bool(0.1) { 1.0 } else { 0.0 };
+            [flag, rng.gen_range(-1.0..1.0), 40.0 + rng.gen_range(0.0..20.0)]
+        })
+        .collect();
+    let mut normalizer = ObsNormalizer::new(3);
+    for sample in &samples {
+        normalizer.update(sample);
+    }
+    let count = samples.len() as f64;
+    let converged = (0..3).all(|i| {
+        let mean = samples.iter().map(|s| s[i] as f64).sum::<f64>() / count;
+        let var = samples.iter().map(|s| (s[i] as f64 - mean).powi(2)).sum::<f64>() / count;
+        (normalizer.mean()[i] - mean).abs() < 1e-9
+            && (normalizer.std()[i] - var.sqrt()).abs() < 1e-9
+    });
+    check(converged, "normalizer: running mean and std converge to the empirical stats")?;
+    Ok(())
+}
+
+/// Logs and reports: logfmt stats, metrics-log rotation, activation exports, best-game
+/// recordings and value traces, HTML reports and visitation heatmaps
+fn logging(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let agent = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let engine = SnakeEngine::new(grid_size);
+
+    // Logfmt stats: every expected key appears once with a parseable number, and values that
+    // need it are quoted
+    let line = crate::stats_logfmt(12, 30, 50, 21.5, &agent, 65);
+    let fields: Vec<(&str, &str)> = line.split(' ').filter_map(|f| f.split_once('=')).collect();
+    let keys = ["episode", "score", "max", "avg", "epsilon", "lr", "buffer", "elapsed_s"];
+    check(
+        fields.len() == keys.len()
+            && keys.iter().all(|k| {
+                fields.iter().any(|(key, value)| key == k && value.parse::<f64>().is_ok())
+            }),
+        "logfmt: expected keys with parseable values",
+    )?;
+    check(
+        logfmt(&[("run", "a b".to_string())]) == "run=\"a b\"",
+        "logfmt: values with spaces are quoted",
+    )?;
+
+    // Log rotation: 17-byte records under a 50-byte limit fill log.jsonl with 3 of them, then
+    // writing continues in log.1.jsonl, and reading the files back in order yields every record
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_logs", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let mut log = MetricsLog::create(&dir.join("log.jsonl").to_string_lossy())
+        .map_err(|e| e.to_string())?;
+    log.rotate_bytes = Some(50);
+    let mut rotated_at = None;
+    for episode in 0..5 {
+        log.write(&serde_json::json!({ "episode": 1000 + episode }))
+            .map_err(|e| e.to_string())?;
+        if rotated_at.is_none() && log.current_path().ends_with("log.1.jsonl") {
+            rotated_at = Some(episode);
+        }
+    }
+    log.flush().map_err(|e| e.to_string())?;
+    let episodes: Vec<u64> = read_records(&log.paths())?
+        .iter()
+        .filter_map(|r| r["episode"].as_u64())
+        .collect();
+    let first_file = std::fs::metadata(dir.join("log.jsonl")).map_or(0, |m| m.len());
+    let _ = std::fs::remove_dir_all(&dir);
+    check(
+        rotated_at == Some(2) && first_file == 51 && log.paths().len() == 2,
+        "log rotation: a new file starts once the size limit is crossed",
+    )?;
+    check(episodes == [1000, 1001, 1002, 1003, 1004], "log rotation: no record lost")?;
+
+    // Activation export: one [states x units] matrix per hidden layer
+    let states = vec![extract_features(&engine); 7];
+    let activations = activations_json(&agent, &states);
+    let layers = activations["layers"].as_array().unwrap();
+    check(layers.len() == 2, "activations: one matrix per hidden layer")?;
+    for (i, layer) in layers.iter().enumerate() {
+        let hidden = agent.network.layer_info(i).3;
+        let rows = layer["activations"].as_array().unwrap();
+        check(
+            layer["shape"] == serde_json::json!([7, hidden])
+                && rows.len() == 7
+                && rows.iter().all(|r| r.as_array().is_some_and(|r| r.len() == hidden)),
+            "activations: num_states x hidden_size",
+        )?;
+    }
+    check(feature_set(Some(0), INPUT_SIZE).is_err(), "versions: unknown tag rejected")?;
+    check(feature_set(Some(1), INPUT_SIZE).is_err(), "versions: size mismatch rejected")?;
+
+    // Best-game recording: one board frame per step of the kept episode
+    let recording = record_best_game(&agent, grid_size, 3, Some(7));
+    check(
+        recording.steps > 0 && recording.frames.len() == recording.steps as usize,
+        "recording: one frame per step",
+    )?;
+    check(
+        recording.frames.iter().all(|f| f.lines().count() == grid_size as usize),
+        "recording: every frame is a full board",
+    )?;
+    check(
+        recording.values.len() == recording.steps as usize
+            && value_trace_csv(&recording).lines().count() == recording.steps as usize + 1,
+        "value trace: one value per step",
+    )?;
+
+    // HTML report: one chart per logged series with a point per record, none for a series the
+    // records lack
+    let records: Vec<serde_json::Value> = (1..=5)
+        .map(|e| serde_json::json!({ "episode": e, "score": e * 10, "avg": 5.0, "lr": 1e-3 }))
+        .collect();
+    let html = html_report("self-test", &records);
+    let series_points = |name: &str| {
+        let tag = format!("<polyline data-series=\"{}\" points=\"", name);
+        let (_, rest) = html.split_once(&tag)?;
+        Some(rest.split('"').next()?.split(' ').count())
+    };
+    check(
+        ["score", "avg", "lr"].iter().all(|s| series_points(s) == Some(records.len())),
+        "html report: every logged series charted with one point per record",
+    )?;
+    check(series_points("epsilon").is_none(), "html report: missing series left out")?;
+
+    // Visitation heatmap: on a 6x6 board the head loops right, down, left, up twice from the
+    // center (each loop cell visited twice), then runs up into the wall: the three cells on the
+    // way count once and the fatal step isn't counted
+    let mut engine = SnakeEngine::new(6);
+    engine.reset();
+    engine.food = Point { x: 0, y: 5 };
+    let mut heatmap = VisitHeatmap::new(6);
+    for action in [1, 2, 3, 0, 1, 2, 3, 0, 0, 0, 0, 0] {
+        engine.step(action);
+        heatmap.record_step(&engine);
+    }
+    check(
+        engine.game_over
+            && heatmap.render()
+                == "0 0 0 1 0 0\n\
+                    0 0 0 1 0 0\n\
+                    0 0 0 1 0 0\n\
+                    0 0 0 2 2 0\n\
+                    0 0 0 2 2 0\n\
+                    0 0 0 0 0 0\n",
+        "visitation heatmap: exact head-visit counts for a scripted path",
+    )?;
+    Ok(())
+}
+
+/// Network heads and their exports: dueling, C51, QR-DQN, NoisyNet, Rainbow and NumPy
+fn heads(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let agent = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let engine = SnakeEngine::new(grid_size);
+
+    // Dueling network: fits fixed targets through the combined head, and exports as an
+    // equivalent plain network whose Q-values match after a round trip
+    let mut dueling = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, true);
+    let states: Vec<Vec<f32>> = (0..8)
+        .map(|i| (0..dueling.features.size).map(|j| ((i * 7 + j) as f32).sin()).collect())
+        .collect();
+    let actions: Vec<usize> = (0..states.len()).map(|i| i % ORTHOGONAL_ACTIONS).collect();
+    let targets: Vec<f32> = (0..states.len()).map(|i| i as f32 - 3.0).collect();
+    let fit_error = |network: &Network| -> f32 {
+        let q = network.predict_batch(&states);
+        (0..states.len()).map(|i| (q[i][actions[i]] - targets[i]).abs()).sum()
+    };
+    let before = fit_error(&dueling.network);
+    for _ in 0..300 {
+        dueling.network.train_batch(&states, &targets, &actions, 1e-3);
+    }
+    check(
+        dueling.network.is_dueling() && fit_error(&dueling.network) < 0.05 * before,
+        "dueling: combined value/advantage head fits targets",
+    )?;
+    let path = format!("snake_self_test_{}_dueling.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    export_model(&dueling, &engine.mode(), &path);
+    let loaded = load_model(&path);
+    let _ = std::fs::remove_file(&*path);
+    let loaded = loaded?;
+    let max_diff = states
+        .iter()
+        .flat_map(|s| {
+            let (a, b) = (dueling.q_values(s), loaded.q_values(s));
+            a.into_iter().zip(b).map(|(x, y)| (x - y).abs())
+        })
+        .fold(0.0f32, f32::max);
+    check(
+        !loaded.network.is_dueling() && max_diff < 1e-4,
+        "dueling: TF.js export folds the head into an equivalent plain network",
+    )?;
+
+    // C51 projection: a shifted atom between two support points splits its mass between them,
+    // and a terminal target is the reward alone, clamped into the support
+    let support = Support { atoms: 5, v_min: -2.0, v_max: 2.0 };
+    let at_zero = support.dirac(0.0);
+    check(
+        support.project(0.5, 1.0, false, &at_zero) == [0.0, 0.0, 0.5, 0.5, 0.0]
+            && support.project(5.0, 0.9, true, &at_zero) == [0.0, 0.0, 0.0, 0.0, 1.0]
+            && support.project(-1.0, 0.5, false, &support.dirac(2.0)) == at_zero,
+        "c51: Bellman projection onto the support",
+    )?;
+
+    // C51 agent: 51 logits per action, fits target distributions by cross-entropy so the mean
+    // of each fitted distribution lands on its target, and survives an export round trip
+    let support = Support::new(-20.0, 100.0);
+    let categorical = ReturnDistribution::Categorical(support);
+    let mut c51 = DQNAgent::distributional(ORTHOGONAL_ACTIONS, categorical, false, Some(3));
+    let fitted_error = |agent: &DQNAgent| -> f32 {
+        let q: Vec<Vec<f32>> = states.iter().map(|s| agent.q_values(s)).collect();
+        (0..states.len()).map(|i| (q[i][actions[i]] - targets[i]).abs()).sum()
+    };
+    let before = fitted_error(&c51);
+    let distributions: Vec<Vec<f32>> = targets.iter().map(|&t| support.dirac(t)).collect();
+    for _ in 0..300 {
+        c51.network.train_batch_distributional(&states, &distributions, &actions, 1e-3);
+    }
+    check(
+        c51.num_actions() == ORTHOGONAL_ACTIONS
+            && c51.network.output_size() == ORTHOGONAL_ACTIONS * support.atoms
+            && fitted_error(&c51) < 0.1 * before,
+        "c51: distributional head fits target distributions",
+    )?;
+    let path = format!("snake_self_test_{}_c51.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    export_model(&c51, &engine.mode(), &path);
+    let loaded = load_model(&path);
+    let _ = std::fs::remove_file(&*path);
+    let loaded = loaded?;
+    let same_q = states.iter().all(|s| {
+        let (a, b) = (c51.q_values(s), loaded.q_values(s));
+        a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4)
+    });
+    check(
+        loaded.distribution() == Some(categorical) && same_q,
+        "c51: export keeps the support and Q-values",
+    )?;
+
+    // QR-DQN targets: every quantile shifted by the discounted reward, or collapsed onto the
+    // reward at a terminal step
+    let quantile = ReturnDistribution::Quantile(3);
+    check(
+        quantile.bellman_target(1.0, 0.5, false, &[-2.0, 0.0, 4.0]) == [0.0, 1.0, 3.0]
+            && quantile.bellman_target(1.0, 0.5, true, &[-2.0, 0.0, 4.0]) == [1.0, 1.0, 1.0],
+        "qr-dqn: Bellman target quantiles",
+    )?;
+
+    // QR-DQN agent: the quantile Huber loss spreads each fitted action's quantiles in order
+    // over an evenly spread target sample, with their mean on the target, and the quantile
+    // count survives an export round trip
+    let quantile = ReturnDistribution::Quantile(5);
+    let mut qr = DQNAgent::distributional(ORTHOGONAL_ACTIONS, quantile, false, Some(3));
+    let spread: Vec<Vec<f32>> =
+        targets.iter().map(|&t| (-2..=2).map(|d| t + d as f32).collect()).collect();
+    let before = fitted_error(&qr);
+    for _ in 0..300 {
+        qr.network.train_batch_quantile(&states, &spread, &actions, 3e-3);
+    }
+    let ordered = states.iter().zip(&actions).all(|(s, &a)| {
+        let output = qr.network.forward(s);
+        output[a * 5..(a + 1) * 5].windows(2).all(|w| w[0] < w[1])
+    });
+    check(
+        qr.num_actions() == ORTHOGONAL_ACTIONS && ordered && fitted_error(&qr) < 0.1 * before,
+        "qr-dqn: quantile Huber loss fits ordered quantiles",
+    )?;
+    let path = format!("snake_self_test_{}_qr.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    export_model(&qr, &engine.mode(), &path);
+    let loaded = load_model(&path);
+    let _ = std::fs::remove_file(&*path);
+    check(
+        loaded?.distribution() == Some(quantile),
+        "qr-dqn: export keeps the quantile count",
+    )?;
+
+    // NoisyNet: with epsilon off, acting still explores through the per-step noise sample
+    // while the greedy action stays put; the noisy layers fit targets, and the export keeps
+    // only the noise-free means
+    let mut noisy = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    noisy.enable_noisy_nets();
+    let greedy = noisy.act_greedy(&states[0]);
+    let acted: Vec<usize> = (0..50).map(|_| noisy.act(&states[0])).collect();
+    check(
+        noisy.epsilon == 0.0
+            && acted.iter().any(|&a| a != acted[0])
+            && noisy.act_greedy(&states[0]) == greedy,
+        "noisy nets: parameter noise explores, greedy actions use the means",
+    )?;
+    let before = fit_error(&noisy.network);
+    let mut rng = ChaCha8Rng::seed_from_u64(3);
+    for _ in 0..300 {
+        noisy.network.resample_noise(&mut rng);
+        noisy.network.train_batch(&states, &targets, &actions, 1e-3);
+    }
+    check(
+        noisy.is_noisy() && fit_error(&noisy.network) < 0.1 * before,
+        "noisy nets: noisy layers fit targets",
+    )?;
+    let path = format!("snake_self_test_{}_noisy.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    export_model(&noisy, &engine.mode(), &path);
+    let loaded = load_model(&path);
+    let _ = std::fs::remove_file(&*path);
+    let loaded = loaded?;
+    let same_q = states.iter().all(|s| {
+        let (a, b) = (noisy.q_values(s), loaded.q_values(s));
+        a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4)
+    });
+    check(
+        !loaded.is_noisy() && same_q,
+        "noisy nets: export keeps the noise-free Q-values",
+    )?;
+
+    // Rainbow head: a noisy dueling C51 network recombines value and advantage per atom, fits
+    // target distributions, and folds into an equivalent plain network on export
+    let mut rainbow = DQNAgent::distributional(ORTHOGONAL_ACTIONS, categorical, true, Some(3));
+    rainbow.enable_noisy_nets();
+    let before = fitted_error(&rainbow);
+    for _ in 0..300 {
+        rainbow.network.resample_noise(&mut rng);
+        rainbow.network.train_batch_distributional(&states, &distributions, &actions, 1e-3);
+    }
+    check(
+        rainbow.network.is_dueling()
+            && rainbow.network.output_size() == ORTHOGONAL_ACTIONS * support.atoms
+            && fitted_error(&rainbow) < 0.1 * before,
+        "rainbow: noisy dueling C51 head fits target distributions",
+    )?;
+    let path = format!("snake_self_test_{}_rainbow.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    export_model(&rainbow, &engine.mode(), &path);
+    let loaded = load_model(&path);
+    let _ = std::fs::remove_file(&*path);
+    let loaded = loaded?;
+    let same_q = states.iter().all(|s| {
+        let (a, b) = (rainbow.q_values(s), loaded.q_values(s));
+        a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4)
+    });
+    check(
+        !loaded.network.is_dueling() && loaded.distribution() == Some(categorical) && same_q,
+        "rainbow: export folds the per-atom dueling head",
+    )?;
+
+    // NumPy export: a kernel and a bias array per layer, with the layer shapes
+    let path = std::env::temp_dir().join(format!("snake_self_test_{}.npz", std::process::id()));
+    export_npz(&agent, &path.to_string_lossy());
+    let bytes = std::fs::read(&path).map_err(|e| e.to_string());
+    let _ = std::fs::remove_file(&path);
+    let arrays = npz_shapes(&bytes?)?;
+    let mut expected = Vec::new();
+    for i in 0..agent.network.num_layers() {
+        let (_, _, in_size, out_size) = agent.network.layer_info(i);
+        expected.push((format!("dense_{}_kernel.npy", i), vec![in_size, out_size]));
+        expected.push((format!("dense_{}_bias.npy", i), vec![out_size]));
+    }
+    check(arrays == expected, "npz: entry names and shapes")?;
+    Ok(())
+}
+
+/// Checkpoints and artifacts: Adam state across a resume, whole resumed runs, artifact paths,
+/// checkpoint pruning, the best-model eval and reverting to the best model on a regression
+fn checkpoints(grid_size: i32, check: &mut Check) -> Result<(), String> {
+    let agent = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let engine = SnakeEngine::new(grid_size);
+
+    // Resume: a checkpoint saved after 5 Adam steps restores the step count and moments, so
+    // the resumed network's next step on the same batch matches the original run's exactly,
+    // while a weights-only copy (t = 0, no moments) takes a different step
+    let mut original = DQNAgent::with_seed(6, ORTHOGONAL_ACTIONS, false);
+    let inputs: Vec<Vec<f32>> =
+        (0..8).map(|i| (0..INPUT_SIZE).map(|j| ((i * 3 + j) as f32).sin()).collect()).collect();
+    let (targets, actions): (Vec<f32>, Vec<usize>) = (0..8).map(|i| (i as f32, i % 4)).unzip();
+    for _ in 0..5 {
+        original.network.train_batch(&inputs, &targets, &actions, 0.01);
+    }
+    let path = format!("snake_self_test_{}_resume.json", std::process::id());
+    let path = std::env::temp_dir().join(path);
+    let path = path.to_string_lossy();
+    save_checkpoint(&original, &engine, &BestModel::default(), 1, false, &path);
+    let mut resumed = DQNAgent::with_seed(99, ORTHOGONAL_ACTIONS, false);
+    resumed.restore(load_checkpoint(&path)?);
+    let _ = std::fs::remove_file(&*path);
+    let mut fresh_optimizer = original.network.clone_weights();
+    check(resumed.network.adam_steps() == 5, "resume: Adam step count restored")?;
+    for network in [&mut original.network, &mut resumed.network, &mut fresh_optimizer] {
+        network.train_batch(&inputs, &targets, &actions, 0.01);
+    }
+    let after = parameters(&original.network);
+    check(
+        parameters(&resumed.network) == after && resumed.network.adam_steps() == 6,
+        "resume: next Adam step matches the original run",
+    )?;
+    check(parameters(&fresh_optimizer) != after, "resume: a reset optimizer steps differently")?;
+
+    // Checkpoint pruning: after every save only the newest episode checkpoints are left, and
+    // model_best/model_final survive
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_ckpt", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let env = SnakeEngine::new(10).mode();
+    for name in ["model_best.json", "model_final.json"] {
+        export_model(&agent, &env, &dir.join(name).to_string_lossy());
+    }
+    for episode in 1..=5 {
+        let filename = dir.join(format!("model_ep{}.json", episode * 100));
+        export_model(&agent, &env, &filename.to_string_lossy());
+        prune_checkpoints(&filename.to_string_lossy(), 2);
+    }
+    let mut left: Vec<String> = std::fs::read_dir(&dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| Some(e.ok()?.file_name().to_string_lossy().into_owned()))
+        .collect();
+    left.sort();
+    let _ = std::fs::remove_dir_all(&dir);
+    check(
+        left == ["model_best.json", "model_ep400.json", "model_ep500.json", "model_final.json"],
+        "keep checkpoints: only the newest episode checkpoints remain",
+    )?;
+
+    // Best-model eval: model_best.json and its greedy eval are redone at a new best Avg(100)
+    // but not at a lower one, and the eval is on fixed seeds in the configured environment, so
+    // it matches a direct eval of the saved model
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_best", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let args: Vec<String> = ["rust_entrenador", "--best-eval-episodes", "2", "--obstacles", "3"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain(["--out-dir".to_string(), dir.to_string_lossy().into_owned()])
+        .collect();
+    let config = crate::parse_args(&args);
+    let mut learner = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let engine = crate::trainer_engine(&config, None, learner.gamma);
+    let mut best = BestModel::default();
+    let finish = |learner: &mut DQNAgent, best: &mut BestModel, episode: u64, score: i32| {
+        let avg = best.record(score);
+        crate::track_best(&config, "", learner, &engine, best, episode, avg);
+    };
+    for episode in 1..=100 {
+        finish(&mut learner, &mut best, episode, 10);
+    }
+    let saved = best.best_eval;
+    let best_path = dir.join("model_best.json");
+    let _ = std::fs::remove_file(&best_path);
+    finish(&mut learner, &mut best, 101, 0);
+    let kept = best.best_eval == saved && !best_path.exists();
+    finish(&mut learner, &mut best, 102, 500);
+    let make_engine = crate::eval_engine(&config, &learner, engine.grid_size);
+    let options = EvalOptions {
+        episodes: 2,
+        epsilon: 0.0,
+        temperature: None,
+        seed: Some(crate::BEST_EVAL_SEED),
+        threads: 1,
+    };
+    let direct = evaluate(&learner, &make_engine, &options).mean();
+    let resaved = best_path.exists();
+    let _ = std::fs::remove_dir_all(&dir);
+    check(
+        saved.is_some_and(|(_, at)| at == 100)
+            && kept
+            && resaved
+            && best.best_eval == Some((direct, 102)),
+        "best eval: updated only when a new model_best.json is saved, on fixed seeds",
+    )?;
+
+    // Revert on regression: after a best Avg(100) of 50 the weights drift, and with
+    // --revert-on-regression 20 the 40th zero-score episode (Avg 30) keeps them while the 41st
+    // (Avg 29.5) restores the snapshot's weights and starts a fresh window
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_revert", std::process::id()));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let args: Vec<String> = ["rust_entrenador", "--revert-on-regression", "20"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain(["--out-dir".to_string(), dir.to_string_lossy().into_owned()])
+        .collect();
+    let config = crate::parse_args(&args);
+    let mut learner = DQNAgent::with_seed(3, ORTHOGONAL_ACTIONS, false);
+    let mut best = BestModel::default();
+    let finish = |learner: &mut DQNAgent, best: &mut BestModel, episode: u64, score: i32| {
+        let avg = best.record(score);
+        crate::track_best(&config, "", learner, &engine, best, episode, avg);
+    };
+    for episode in 1..=100 {
+        finish(&mut learner, &mut best, episode, 50);
+    }
+    let at_best = parameters(&learner.network);
+    for _ in 0..3 {
+        learner.network.train_batch(&inputs, &targets, &actions, 0.01);
+    }
+    for episode in 101..=140 {
+        finish(&mut learner, &mut best, episode, 0);
+    }
+    let drifted = parameters(&learner.network) != at_best;
+    finish(&mut learner, &mut best, 141, 0);
+    let _ = std::fs::remove_dir_all(&dir);
+    check(
+        drifted && parameters(&learner.network) == at_best && !best.is_full() && best.avg() == 0.0,
+        "revert on regression: a drop past the threshold restores the best snapshot",
+    )?;
+
+    // Resumed run: with --checkpoint-replay, 150 episodes in one go and 102 episodes resumed
+    // for 48 more end on the same weights, bit for bit. The split checkpoint carries the
+    // rolling scores and best snapshot, which the revert after the split
+    // (--revert-on-regression 0.1) depends on.
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_rerun", std::process::id()));
+    let train = |episodes: u64, out: &str, resume: Option<String>| {
+        let mut args: Vec<String> = [
+            "rust_entrenador",
+            "--update-per-episode",
+            "--revert-on-regression",
+            "0.1",
+            "--checkpoint-replay",
+            "--print-every",
+            "1000",
+            "--save-every",
+            "1000",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let out = dir.join(out).to_string_lossy().into_owned();
+        args.extend(["--episodes".to_string(), episodes.to_string(), "--out-dir".to_string()]);
+        args.push(out.clone());
+        if let Some(path) = resume {
+            args.extend(["--resume".to_string(), path]);
+        }
+        crate::train(&crate::parse_args(&args), Some(5), "");
+        out
+    };
+    let uninterrupted = train(150, "once", None);
+    let split = train(102, "split", None);
+    let checkpoint = format!("{}/checkpoint.json", split);
+    let tracked = load_checkpoint(&checkpoint)?.best.is_some_and(|best| best.snapshot.is_some());
+    let resumed = train(150, "split", Some(checkpoint));
+    let weights = |out: &str| -> Result<Vec<f32>, String> {
+        Ok(parameters(&load_model(&format!("{}/model_final.json", out))?.network))
+    };
+    let same = weights(&uninterrupted)? == weights(&resumed)?;
+    let _ = std::fs::remove_dir_all(&dir);
+    check(tracked && same, "resume: a resumed run ends on the uninterrupted run's weights")?;
+
+    // Artifact paths: a run's models, checkpoint and log all land in --out-dir, named with the
+    // run name and then the sweep prefix; an absolute path keeps its own directory
+    let dir = std::env::temp_dir().join(format!("snake_self_test_{}_out", std::process::id()));
+    let args: Vec<String> = [
+        "rust_entrenador",
+        "--episodes",
+        "3",
+        "--save-every",
+        "2",
+        "--print-every",
+        "1000",
+        "--run-name",
+        "r",
+        "--log-file",
+        "metrics.jsonl",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .chain(["--out-dir".to_string(), dir.to_string_lossy().into_owned()])
+    .collect();
+    let config = crate::parse_args(&args);
+    crate::train(&config, Some(5), "seed5_");
+    let mut written: Vec<String> = std::fs::read_dir(&dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| Some(e.ok()?.file_name().to_string_lossy().into_owned()))
+        .collect();
+    written.sort();
+    let _ = std::fs::remove_dir_all(&dir);
+    let absolute = std::env::temp_dir().join("elsewhere.json").to_string_lossy().into_owned();
+    check(
+        written
+            == [
+                "r_seed5_checkpoint.json",
+                "r_seed5_metrics.jsonl",
+                "r_seed5_model_ep2.json",
+                "r_seed5_model_final.json",
+            ]
+            && crate::artifact_path(&config, &absolute, "seed5_")
+                == std::env::temp_dir().join("r_seed5_elsewhere.json").to_string_lossy(),
+        "artifact paths: run artifacts land in --out-dir with the run name and prefix",
+    )?;
+    Ok(())
+}
+
+/// The other trainers: tabular Q-learning, PPO, A2C, discrete SAC, REINFORCE, evolution
+/// strategies and genetic algorithms
+fn algorithms(check: &mut Check) -> Result<(), String> {
+    // Tabular Q-learning: a fresh snake heading right with nothing ahead is the state with
+    // only the dir_right bit and the food bits, and a few hundred episodes on a small board
+    // learn to eat
+    let mut tabular = TabularAgent::new(ORTHOGONAL_ACTIONS, Some(0));
+    let mut engine = SnakeEngine::new(10);
+    engine.seed(0);
+    let key = tabular.state_key(&extract_features(&engine));
+    check(key & 0b111_1111 == 1 << 4, "tabular: 11-bit state key")?;
+    let mut scratch = Scratch::default();
+    for _ in 0..300 {
+        tabular.train_episode(&mut engine, &mut scratch);
+    }
+    let mean = (0..20).map(|_| tabular.play_greedy(&mut engine, &mut scratch)).sum::<i32>() / 20;
+    check(mean >= 30, "tabular: Q-learning learns to eat")?;
+
+    // Tabular truncation: with Q(s', .) = 10 and alpha = 1, a one-step episode cut off by
+    // --truncate-at 1 sets Q(s, a) to r + gamma * 10 rather than to r alone
+    let mut tabular = TabularAgent::new(ORTHOGONAL_ACTIONS, Some(0));
+    (tabular.alpha, tabular.epsilon) = (1.0, 0.0);
+    let mut engine = SnakeEngine::new(10);
+    engine.seed(0);
+    engine.reset();
+    let state = extract_features(&engine);
+    let (reward, _) = engine.step(0);
+    let next_state = extract_features(&engine);
+    for action in 0..ORTHOGONAL_ACTIONS {
+        tabular.remember(Experience {
+            state: next_state.clone(),
+            action,
+            reward: 10.0,
+            next_state: next_state.clone(),
+            done: true,
+            span: 1,
+            next_action: None,
+        });
+    }
+    engine.seed(0);
+    engine.truncate_at = Some(1);
+    tabular.train_episode(&mut engine, &mut scratch);
+    let bootstrapped = reward + tabular.gamma * 10.0;
+    check(
+        engine.truncated && (tabular.q_values(&state)[0] - bootstrapped).abs() < 1e-5,
+        "tabular: a truncated step still bootstraps",
+    )?;
+
+    // PPO: GAE stops bootstrapping at a done step, and the clipped surrogate raises the
+    // probability of an action with positive advantage
+    let (advantages, returns) = gae(&[1.0; 3], &[0.5; 3], &[false, true, false], 2.0, 0.5, 1.0);
+    check(
+        advantages == [1.0, 0.5, 1.5] && returns == [1.5, 1.0, 2.0],
+        "ppo: GAE advantages and returns",
+    )?;
+    let mut rng = ChaCha8Rng::seed_from_u64(5);
+    let mut policy = Network::new(4, 3, false, &mut rng);
+    let input = vec![vec![0.5, -0.25, 1.0, 0.0]; 8];
+    let before = softmax(&policy.forward(&input[0]))[2];
+    let loss = PolicyLoss {
+        clip: 0.2,
+        entropy_coef: 0.0,
+    };
+    for _ in 0..5 {
+        policy.train_batch_ppo(&input, &[2; 8], &[1.0; 8], &[before.ln(); 8], &loss, 1e-3);
+    }
+    let after = softmax(&policy.forward(&input[0]))[2];
+    check(after > before, "ppo: surrogate raises a positive-advantage action")?;
+
+    // A2C: one update over both environments' rollouts clears them and moves V toward the
+    // (terminal) return
+    let mut a2c = A2cAgent::new(ORTHOGONAL_ACTIONS, 2, Some(0));
+    let state = vec![0.5; a2c.features.size];
+    let before = a2c.value.forward(&state)[0];
+    for env in 0..2 {
+        a2c.act(env, &state);
+        a2c.remember(env, 5.0, true, false, &state);
+    }
+    a2c.update(&[state.clone(), state.clone()]);
+    let after = a2c.value.forward(&state)[0];
+    check(
+        a2c.rollout_len() == 0 && (5.0 - after).abs() < (5.0 - before).abs(),
+        "a2c: update clears the rollouts and fits V to the return",
+    )?;
+
+    // Discrete SAC: the soft policy loss moves the policy toward the best Q-value, and a
+    // near-uniform policy (entropy above the target) lowers the temperature
+    let mut policy = Network::new(4, 3, false, &mut rng);
+    let before = softmax(&policy.forward(&input[0]))[1];
+    for _ in 0..5 {
+        policy.train_batch_soft_policy(&input, &vec![vec![0.0, 1.0, 0.0]; 8], 0.1, 1e-3);
+    }
+    let after = softmax(&policy.forward(&input[0]))[1];
+    check(after > before, "sac: soft policy loss favours the best action")?;
+    let mut sac = SacAgent::new(ORTHOGONAL_ACTIONS, Some(0));
+    let state = vec![0.5; sac.features.size];
+    for action in (0..sac.batch_size).map(|i| i % ORTHOGONAL_ACTIONS) {
+        sac.remember(Experience {
+            state: state.clone(),
+            action,
+            reward: 1.0,
+            next_state: state.clone(),
+            done: false,
+            span: 1,
+            next_action: None,
+        });
+    }
+    let entropy = sac.train_step();
+    check(
+        entropy > sac.target_entropy && sac.alpha() < 1.0,
+        "sac: entropy above the target lowers alpha",
+    )?;
+
+    // REINFORCE: a one-step episode with a return above the baseline learns on `done`,
+    // making its action more likely and moving V toward the return
+    let mut reinforce = ReinforceAgent::new(ORTHOGONAL_ACTIONS, Some(0));
+    let state = vec![0.5; reinforce.features.size];
+    let value_before = reinforce.value.forward(&state)[0];
+    let action = reinforce.act(&state);
+    let before = softmax(&reinforce.policy.forward(&state))[action];
+    reinforce.remember(5.0, true, false, &state);
+    let after = softmax(&reinforce.policy.forward(&state))[action];
+    let value_after = reinforce.value.forward(&state)[0];
+    check(
+        after > before && (5.0 - value_after).abs() < (5.0 - value_before).abs(),
+        "reinforce: an episode's return reinforces its action and fits V",
+    )?;
+
+    // Evolution strategy: centered ranks span [-0.5, 0.5], and a generation keeps its fittest
+    // candidate as the best, with identical results on one thread or several
+    check(centered_ranks(&[3.0, -1.0, 2.0]) == [0.5, -0.5, 0.0], "es: centered ranks")?;
+    let make_engine = || SnakeEngine::new(10);
+    let fitness = |threads: usize| {
+        let mut es = EvolutionStrategy::new(ORTHOGONAL_ACTIONS, 4, 0.1, Some(0));
+        es.episodes = 1;
+        let candidates = es.generation(&make_engine, threads);
+        let top = candidates.iter().map(|c| c.fitness).fold(f32::NEG_INFINITY, f32::max);
+        (candidates.iter().map(|c| c.fitness).collect::<Vec<_>>(), es.best_fitness() == Some(top))
+    };
+    let ((serial, serial_best), (parallel, _)) = (fitness(1), fitness(3));
+    check(serial_best && serial == parallel, "es: generation keeps the fittest candidate")?;
+
+    // Genetic algorithm: a generation keeps the fittest member as the best, carries the elites
+    // over unchanged and breeds a full-size population
+    let mut ga = GeneticAlgorithm::new(ORTHOGONAL_ACTIONS, 6, 0.5, Some(0));
+    ga.episodes = 1;
+    let first = ga.population.iter().map(|n| n.forward(&state)).collect::<Vec<_>>();
+    let members = ga.generation(&make_engine, 2);
+    let top = members.iter().map(|c| c.mean_score).fold(0.0, f32::max);
+    let elite = ga.population[0].forward(&state);
+    check(
+        ga.population.len() == 6 && ga.best_fitness() == Some(top) && first.contains(&elite),
+        "ga: generation keeps the fittest member and its elites",
+    )?;
+    Ok(())
+}
+
+/// Standard padded base64, as TF.js tools write `weightData`
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().fold(0u32, |n, &b| n << 8 | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// (entry name, shape) of each float32 array in a stored (uncompressed) `.npz`, read from the
+/// zip local headers and the `.npy` headers
+fn npz_shapes(bytes: &[u8]) -> Result<Vec<(String, Vec<usize>)>, String> {
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+    let mut arrays = Vec::new();
+    let mut pos = 0;
+    while bytes.len() >= pos + 30 && u32_at(pos) == 0x0403_4b50 {
+        let (size, name_len, extra_len) = (u32_at(pos + 18), u16_at(pos + 26), u16_at(pos + 28));
+        let name = String::from_utf8_lossy(&bytes[pos + 30..pos + 30 + name_len]).into_owned();
+        let data = &bytes[pos + 30 + name_len + extra_len..][..size];
+        pos += 30 + name_len + extra_len + size;
+
+        if !data.starts_with(b"\x93NUMPY") {
+            return Err(format!("npz: {} is not an .npy file", name));
+        }
+        let header_len = u16::from_le_bytes([data[8], data[9]]) as usize;
+        let header = String::from_utf8_lossy(&data[10..10 + header_len]);
+        let shape: Vec<usize> = header
+            .split_once("'shape': (")
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .ok_or_else(|| format!("npz: {} has no shape", name))?
+            .0
+            .split(',')
+            .filter_map(|d| d.trim().parse().ok())
+            .collect();
+        if !header.contains("'descr': '<f4'")
+            || data.len() != 10 + header_len + 4 * shape.iter().product::<usize>()
+        {
+            return Err(format!("npz: {} is not a float32 array of its shape", name));
+        }
+        arrays.push((name, shape));
+    }
+    Ok(arrays)
+}
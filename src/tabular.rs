@@ -0,0 +1,147 @@
+use crate::agent::Experience;
+use crate::engine::SnakeEngine;
+use crate::eval::run_episode;
+use crate::features::{current_feature_set, Scratch, FEATURE_NAMES};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Features of the classic 11-bit snake state: danger one step straight/right/left, heading
+/// and the food's direction. Each is 0 or 1, so together they index the Q-table.
+const STATE_FEATURES: [&str; 11] = [
+    "danger_straight",
+    "danger_right",
+    "danger_left",
+    "dir_up",
+    "dir_right",
+    "dir_down",
+    "dir_left",
+    "food_up",
+    "food_right",
+    "food_down",
+    "food_left",
+];
+
+/// Tabular Q-learning on the 11-bit state: a dirt-cheap baseline that exercises the engine and
+/// rewards without a network. Learns online from every transition it is shown; there is no
+/// replay or target table.
+pub struct TabularAgent {
+    // Q-values, [2^11 states × num_actions]
+    table: Vec<f32>,
+    num_actions: usize,
+    // Positions of `STATE_FEATURES` in the current feature vector
+    state_features: Vec<usize>,
+    pub alpha: f32,
+    pub gamma: f32,
+    pub epsilon: f32,
+    pub epsilon_min: f32,
+    pub epsilon_decay: f32,
+    rng: ChaCha8Rng,
+}
+
+impl TabularAgent {
+    /// Zero-initialized table; reproducible exploration when `seed` is set
+    pub fn new(num_actions: usize, seed: Option<u64>) -> Self {
+        let state_features = STATE_FEATURES
+            .iter()
+            .map(|name| FEATURE_NAMES.iter().position(|n| n == name).unwrap())
+            .collect();
+        TabularAgent {
+            table: vec![0.0; (1 << STATE_FEATURES.len()) * num_actions],
+            num_actions,
+            state_features,
+            alpha: 0.1,
+            gamma: 0.95,
+            epsilon: 1.0,
+            epsilon_min: 0.01,
+            epsilon_decay: 0.995,
+            rng: seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64),
+        }
+    }
+
+    /// Table row of `features`: bit i is set when the i-th of `STATE_FEATURES` is
+    pub fn state_key(&self, features: &[f32]) -> usize {
+        self.state_features
+            .iter()
+            .enumerate()
+            .filter(|(_, &f)| features[f] > 0.5)
+            .fold(0, |key, (bit, _)| key | 1 << bit)
+    }
+
+    pub fn q_values(&self, features: &[f32]) -> &[f32] {
+        let row = self.state_key(features) * self.num_actions;
+        &self.table[row..row + self.num_actions]
+    }
+
+    /// Epsilon-greedy action
+    pub fn act(&mut self, features: &[f32]) -> usize {
+        if self.rng.gen::<f32>() < self.epsilon {
+            self.rng.gen_range(0..self.num_actions)
+        } else {
+            self.act_greedy(features)
+        }
+    }
+
+    /// Highest-valued action, the lowest index on ties
+    pub fn act_greedy(&self, features: &[f32]) -> usize {
+        let q = self.q_values(features);
+        (0..q.len()).fold(0, |best, a| if q[a] > q[best] { a } else { best })
+    }
+
+    /// Q-learning update on `exp`: Q(s, a) moves `alpha` of the way to r + gamma^span *
+    /// max Q(s'), or to r alone for a terminal transition
+    pub fn remember(&mut self, exp: Experience) {
+        let next_value = if exp.done {
+            0.0
+        } else {
+            let next = self.q_values(&exp.next_state);
+            next.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+        };
+        let target = exp.reward + exp.bootstrap_discount(self.gamma) * next_value;
+        let idx = self.state_key(&exp.state) * self.num_actions + exp.action;
+        self.table[idx] += self.alpha * (target - self.table[idx]);
+    }
+
+    pub fn end_episode(&mut self) {
+        self.epsilon = (self.epsilon * self.epsilon_decay).max(self.epsilon_min);
+    }
+
+    /// States with at least one updated Q-value
+    pub fn visited_states(&self) -> usize {
+        self.table.chunks(self.num_actions).filter(|row| row.iter().any(|&q| q != 0.0)).count()
+    }
+
+    /// One epsilon-greedy episode on `engine`, learning from every step; returns the score
+    pub fn train_episode(&mut self, engine: &mut SnakeEngine, scratch: &mut Scratch) -> i32 {
+        let features = current_feature_set();
+        let mut state = Vec::new();
+        let mut next_state = Vec::new();
+        engine.reset();
+        features.extract(engine, &mut state, scratch);
+        loop {
+            let action = self.act(&state);
+            let (reward, done) = engine.step(action);
+            features.extract(engine, &mut next_state, scratch);
+            self.remember(Experience {
+                state: std::mem::take(&mut state),
+                action,
+                reward,
+                next_state: next_state.clone(),
+                // A truncated step isn't terminal: its target still bootstraps from s'
+                done: done && !engine.truncated,
+                span: 1,
+                next_action: None,
+            });
+            if done {
+                self.end_episode();
+                return engine.score;
+            }
+            std::mem::swap(&mut state, &mut next_state);
+        }
+    }
+
+    /// Score of one greedy episode on `engine`
+    pub fn play_greedy(&self, engine: &mut SnakeEngine, scratch: &mut Scratch) -> i32 {
+        let mut policy = |_: &SnakeEngine, features: &[f32]| self.act_greedy(features);
+        run_episode(engine, current_feature_set(), scratch, &mut policy)
+    }
+}
@@ -0,0 +1,386 @@
+use crate::agent::{Checkpoint, DQNAgent};
+use crate::engine::{Point, SnakeEngine, ACTIONS};
+use crate::eval::run_episode;
+use crate::features::{Scratch, FEATURE_NAMES};
+use crate::nn::Network;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Arrow for each entry of `ACTIONS`
+pub const ARROWS: [char; 8] = ['↑', '→', '↓', '←', '↗', '↘', '↙', '↖'];
+
+/// Greedy action for the read-only tools: ties (e.g. an untrained or saturated network) go to
+/// the lowest action index, so repeated renders of the same model are identical
+pub fn tool_action(agent: &DQNAgent, features: &[f32]) -> usize {
+    let q = agent.q_values(features);
+    let mut best = 0;
+    for (action, &value) in q.iter().enumerate().skip(1) {
+        if value > q[best] {
+            best = action;
+        }
+    }
+    best
+}
+
+/// Policy map: greedy action (see `tool_action`) for every free head cell, with a fixed food
+/// and a one-cell snake (heading Right). Food is drawn as `F`.
+pub fn policy_map(agent: &DQNAgent, grid_size: i32, food: Point) -> String {
+    let mut engine = SnakeEngine::new(grid_size);
+    engine.diagonals = agent.num_actions() == ACTIONS.len();
+    engine.food = food;
+    let mut scratch = Scratch::default();
+    let mut features = Vec::new();
+
+    let mut out = String::new();
+    for y in 0..grid_size {
+        for x in 0..grid_size {
+            out.push(' ');
+            if x == food.x && y == food.y {
+                out.push('F');
+                continue;
+            }
+            engine.snake = vec![Point { x, y }];
+            agent.features.extract(&engine, &mut features, &mut scratch);
+            out.push(ARROWS[tool_action(agent, &features)]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Share of each action in `counts`, one `arrow pct%` entry per action in `ACTIONS` order
+pub fn format_action_histogram(counts: &[usize]) -> String {
+    let total = counts.iter().sum::<usize>().max(1) as f32;
+    counts
+        .iter()
+        .zip(ARROWS)
+        .map(|(&n, arrow)| format!("{} {:.1}%", arrow, 100.0 * n as f32 / total))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// The board as text: `H` head, `o` body, `F` food, `#` obstacle, `.` empty
+pub fn render_board(engine: &SnakeEngine) -> String {
+    let size = engine.grid_size;
+    let mut cells = vec!['.'; (size * size) as usize];
+    let mut put = |p: Point, c: char| {
+        if p.x >= 0 && p.x < size && p.y >= 0 && p.y < size {
+            cells[(p.y * size + p.x) as usize] = c;
+        }
+    };
+    for &o in &engine.obstacles {
+        put(o, '#');
+    }
+    put(engine.food, 'F');
+    for (i, &s) in engine.snake.iter().enumerate() {
+        put(s, if i == 0 { 'H' } else { 'o' });
+    }
+
+    let mut out = String::new();
+    for row in cells.chunks(size as usize) {
+        out.extend(row);
+        out.push('\n');
+    }
+    out
+}
+
+/// A played game: the board before every step, in order, and the agent's value estimate
+/// (max Q) of each of those states
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub score: i32,
+    pub steps: u32,
+    pub frames: Vec<String>,
+    #[serde(default)]
+    pub values: Vec<f32>,
+}
+
+/// Plays `episodes` greedy games and keeps the highest-scoring one (the first on ties). With a
+/// seed the games are reproducible.
+pub fn record_best_game(
+    agent: &DQNAgent,
+    grid_size: i32,
+    episodes: u64,
+    seed: Option<u64>,
+) -> Recording {
+    let mut engine = SnakeEngine::new(grid_size);
+    if let Some(seed) = seed {
+        engine.seed(seed);
+    }
+    engine.diagonals = agent.num_actions() == ACTIONS.len();
+    let mut scratch = Scratch::default();
+    let mut best: Option<Recording> = None;
+    for _ in 0..episodes.max(1) {
+        let mut frames = Vec::new();
+        let mut values = Vec::new();
+        let score = run_episode(&mut engine, agent.features, &mut scratch, &mut |engine, features| {
+            frames.push(render_board(engine));
+            let q = agent.q_values(features);
+            values.push(q.iter().copied().fold(f32::NEG_INFINITY, f32::max));
+            agent.act_greedy(features)
+        });
+        if best.as_ref().is_none_or(|b| score > b.score) {
+            best = Some(Recording {
+                score,
+                steps: engine.steps,
+                frames,
+                values,
+            });
+        }
+    }
+    best.unwrap()
+}
+
+/// The value trace of `recording` as CSV: `step,value`, one row per step
+pub fn value_trace_csv(recording: &Recording) -> String {
+    let mut out = String::from("step,value\n");
+    for (step, value) in recording.values.iter().enumerate() {
+        out.push_str(&format!("{},{}\n", step + 1, value));
+    }
+    out
+}
+
+/// Plays a recording back in the terminal, one frame every `delay`
+pub fn play_recording(recording: &Recording, delay: Duration) {
+    let total = recording.frames.len();
+    for (i, frame) in recording.frames.iter().enumerate() {
+        // Clear the screen and home the cursor
+        print!("\x1b[2J\x1b[H{}", frame);
+        match recording.values.get(i) {
+            Some(value) => println!(
+                "Step {}/{} | Score: {} | V: {:.2}",
+                i + 1,
+                total,
+                recording.score,
+                value
+            ),
+            None => println!("Step {}/{} | Score: {}", i + 1, total, recording.score),
+        }
+        std::thread::sleep(delay);
+    }
+}
+
+/// Rolling Avg(100) of a DQN run's episode scores and the best model it has reached
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct BestModel {
+    recent_scores: VecDeque<i32>,
+    /// Highest full-window Avg(100) so far, the one model_best.json was saved at
+    pub best_avg: f32,
+    /// Greedy eval mean of the current model_best.json and the episode it was saved at
+    pub best_eval: Option<(f32, u64)>,
+    /// Agent state when `best_avg` was reached, for --revert-on-regression
+    pub snapshot: Option<Box<Checkpoint>>,
+}
+
+impl BestModel {
+    /// Records an episode's score; returns the Avg(100) including it
+    pub fn record(&mut self, score: i32) -> f32 {
+        if self.recent_scores.len() == 100 {
+            self.recent_scores.pop_front();
+        }
+        self.recent_scores.push_back(score);
+        self.avg()
+    }
+
+    /// Mean of the last (up to) 100 scores, 0 before any
+    pub fn avg(&self) -> f32 {
+        self.recent_scores.iter().sum::<i32>() as f32 / self.recent_scores.len().max(1) as f32
+    }
+
+    /// Whether the window holds 100 scores, so its average can count as a best or regression
+    pub fn is_full(&self) -> bool {
+        self.recent_scores.len() >= 100
+    }
+
+    /// Starts a fresh window, e.g. to judge a restored agent on its own episodes
+    pub fn clear_window(&mut self) {
+        self.recent_scores.clear();
+    }
+}
+
+/// Sample efficiency: the episode and total environment steps at which the rolling average
+/// score first reached each threshold
+pub struct Milestones {
+    thresholds: Vec<f32>,
+    reached: Vec<Option<(u64, u64)>>,
+}
+
+impl Milestones {
+    pub fn new(thresholds: Vec<f32>) -> Self {
+        let reached = vec![None; thresholds.len()];
+        Milestones {
+            thresholds,
+            reached,
+        }
+    }
+
+    /// Records the rolling average after `episode`, with `env_steps` steps taken so far
+    pub fn record(&mut self, avg: f32, episode: u64, env_steps: u64) {
+        for (threshold, reached) in self.thresholds.iter().zip(self.reached.iter_mut()) {
+            if reached.is_none() && avg >= *threshold {
+                *reached = Some((episode, env_steps));
+            }
+        }
+    }
+
+    /// (threshold, first (episode, env steps) at or above it)
+    pub fn reached(&self) -> impl Iterator<Item = (f32, Option<(u64, u64)>)> + '_ {
+        self.thresholds.iter().copied().zip(self.reached.iter().copied())
+    }
+
+    pub fn print(&self) {
+        println!(">>> Sample efficiency (first Avg(100) >= threshold):");
+        for (threshold, reached) in self.reached() {
+            match reached {
+                Some((episode, steps)) => {
+                    println!("    {:>8.1}: episode {} | {} steps", threshold, episode, steps)
+                }
+                None => println!("    {:>8.1}: not reached", threshold),
+            }
+        }
+    }
+}
+
+/// Head-visit counts per board cell, accumulated during training
+pub struct VisitHeatmap {
+    size: i32,
+    counts: Vec<u64>,
+}
+
+impl VisitHeatmap {
+    /// `size` must cover the largest grid the run will use
+    pub fn new(size: i32) -> Self {
+        VisitHeatmap {
+            size,
+            counts: vec![0; (size * size) as usize],
+        }
+    }
+
+    pub fn record(&mut self, head: Point) {
+        if head.x >= 0 && head.x < self.size && head.y >= 0 && head.y < self.size {
+            self.counts[(head.y * self.size + head.x) as usize] += 1;
+        }
+    }
+
+    /// Records the head after a training step, unless the step ended the game (the head then
+    /// sits on a wall or the body rather than a visited cell)
+    pub fn record_step(&mut self, engine: &SnakeEngine) {
+        if !engine.game_over {
+            self.record(engine.snake[0]);
+        }
+    }
+
+    /// One row per line, space-separated counts (row 0 = top of the board)
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for row in self.counts.chunks(self.size as usize) {
+            let cells: Vec<String> = row.iter().map(|c| c.to_string()).collect();
+            out.push_str(&cells.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// L2 norm of each input unit's outgoing first-layer weights, as (feature index, norm), largest
+/// first. A near-zero norm means the network effectively ignores that feature.
+pub fn feature_importance(network: &Network) -> Vec<(usize, f32)> {
+    let (weights, _, in_size, out_size) = network.layer_info(0);
+    let mut norms: Vec<(usize, f32)> = (0..in_size)
+        .map(|i| {
+            let row = &weights[i * out_size..(i + 1) * out_size];
+            (i, row.iter().map(|w| w * w).sum::<f32>().sqrt())
+        })
+        .collect();
+    norms.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    norms
+}
+
+pub fn print_feature_importance(network: &Network) {
+    println!(">>> Feature importance (L2 norm of first-layer weights):");
+    for (rank, (idx, norm)) in feature_importance(network).iter().enumerate() {
+        println!("    {:>2}. {:<18} {:.4}", rank + 1, FEATURE_NAMES[*idx], norm);
+    }
+}
+
+/// Feature vectors of the first `samples` states visited by greedy episodes of `agent`. With a
+/// seed the food sequence, and so the states, are reproducible.
+pub fn sample_greedy_states(
+    agent: &DQNAgent,
+    grid_size: i32,
+    samples: usize,
+    seed: Option<u64>,
+) -> Vec<Vec<f32>> {
+    let mut engine = SnakeEngine::new(grid_size);
+    if let Some(seed) = seed {
+        engine.seed(seed);
+    }
+    engine.diagonals = agent.num_actions() == ACTIONS.len();
+    let mut scratch = Scratch::default();
+    let mut states: Vec<Vec<f32>> = Vec::with_capacity(samples);
+    while states.len() < samples {
+        run_episode(&mut engine, agent.features, &mut scratch, &mut |_, features| {
+            if states.len() < samples {
+                states.push(features.to_vec());
+            }
+            agent.act_greedy(features)
+        });
+    }
+    states
+}
+
+/// Single-inference latency of `act_greedy` in microseconds: states are collected from greedy
+/// episodes until there are `samples` of them, then each one is timed on its own (after a
+/// warm-up pass). Reports mean and percentiles.
+pub fn latency_report(agent: &DQNAgent, grid_size: i32, samples: usize) -> String {
+    let states = sample_greedy_states(agent, grid_size, samples, None);
+
+    for state in &states {
+        black_box(agent.act_greedy(black_box(state)));
+    }
+
+    let mut micros: Vec<f64> = states
+        .iter()
+        .map(|state| {
+            let start = Instant::now();
+            black_box(agent.act_greedy(black_box(state)));
+            start.elapsed().as_secs_f64() * 1e6
+        })
+        .collect();
+    micros.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mean = micros.iter().sum::<f64>() / micros.len() as f64;
+    let pct = |p: f64| micros[((micros.len() - 1) as f64 * p).round() as usize];
+    format!(
+        "Inference latency over {} states (us): mean {:.2} | p50 {:.2} | p90 {:.2} | p99 {:.2} | max {:.2}",
+        micros.len(),
+        mean,
+        pct(0.50),
+        pct(0.90),
+        pct(0.99),
+        micros[micros.len() - 1]
+    )
+}
+
+/// Hidden activations of `agent` on `states`, for probing: one `[states × units]` matrix per
+/// hidden layer, named like the exported layers
+pub fn activations_json(agent: &DQNAgent, states: &[Vec<f32>]) -> serde_json::Value {
+    let per_state: Vec<Vec<Vec<f32>>> =
+        states.iter().map(|s| agent.hidden_activations(s)).collect();
+    let layers: Vec<serde_json::Value> = ["dense", "dense_1"]
+        .iter()
+        .enumerate()
+        .map(|(layer, name)| {
+            let matrix: Vec<&Vec<f32>> = per_state.iter().map(|a| &a[layer]).collect();
+            let units = matrix.first().map_or(0, |row| row.len());
+            serde_json::json!({
+                "name": name,
+                "shape": [matrix.len(), units],
+                "activations": matrix,
+            })
+        })
+        .collect();
+    serde_json::json!({ "feature_version": agent.features.version, "layers": layers })
+}